@@ -0,0 +1,53 @@
+// Operator requests for the server's local admin socket (used by the `admin` binary). One JSON
+// request per line in, one JSON response per line out.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BuildingDTO, Resources, UnitDTO};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum AdminRequest {
+    ListPlayers,
+    InspectPlayer { player_id: i32 },
+    // Adds `delta` to the player's stockpile; negative amounts remove (never below zero).
+    GrantResources { player_id: i32, delta: Resources },
+    Kick { player_id: i32 },
+    // Refuses the token on future joins and kicks whoever is using it now.
+    BanToken { token: String },
+    ResetWorld,
+    SaveSnapshot,
+    // A web client speaking `version` is deployed: clients on older ones that accept update
+    // notices are told to reload, now and when they join.
+    SetLatestClient { version: u32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum AdminResponse {
+    Ok {
+        message: String,
+    },
+    Players {
+        players: Vec<PlayerSummary>,
+    },
+    Player {
+        player: PlayerSummary,
+        units: Vec<UnitDTO>,
+        buildings: Vec<BuildingDTO>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerSummary {
+    pub id: i32,
+    pub chunk_x: i32,
+    pub chunk_y: i32,
+    pub online: bool,
+    pub resources: Resources,
+    pub units: usize,
+    pub buildings: usize,
+}
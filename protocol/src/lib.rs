@@ -0,0 +1,233 @@
+//! Wire protocol and game rules shared by the browser client (`temty`) and `chat-server`.
+//! Anything both sides must agree on lives here, so a change to one side that the other
+//! doesn't follow fails to compile instead of silently desyncing.
+
+use serde::{Deserialize, Serialize};
+
+pub mod admin;
+mod rules;
+pub mod terrain;
+pub mod wire;
+
+pub use rules::*;
+
+/// Protocol spoken by this build, sent in `Hello`. Bump it whenever a message changes shape.
+pub const PROTOCOL_VERSION: u32 = 31;
+
+/// Optional capabilities named in `Hello` and answered in `ServerHello`. Only the ones both
+/// sides list are used.
+pub mod feature {
+    /// Binary frames (`wire::Encoding::Binary`).
+    pub const MSGPACK: &str = "msgpack";
+    /// `NewClientAvailable` pushes.
+    pub const UPDATE_NOTICE: &str = "update-notice";
+
+    /// Everything this build understands.
+    pub const ALL: &[&str] = &[MSGPACK, UPDATE_NOTICE];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerInfo {
+    pub id: i32,
+    pub chunk_x: i32,
+    pub chunk_y: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnitDTO {
+    pub owner_id: i32,
+    pub unit_id: u64,
+    pub x: f32,
+    pub y: f32,
+    pub kind: u8,
+    pub hp: f32,
+    #[serde(default)]
+    pub carry_wood: f32,
+    #[serde(default)]
+    pub carry_stone: f32,
+    #[serde(default)]
+    pub carry_gold: f32,
+    #[serde(default)]
+    pub carry_food: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildingDTO {
+    pub id: i32,
+    pub owner_id: i32,
+    pub kind: u8,
+    pub tile_x: i32,
+    pub tile_y: i32,
+    pub hp: f32,
+}
+
+// A building under construction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildSite {
+    pub tile_x: i32,
+    pub tile_y: i32,
+    pub kind: u8,
+    pub progress: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum GameMessage {
+    // Client -> server, first frame, always JSON. `encoding` picks the format of everything the
+    // server sends back (see `wire`); Binary needs the msgpack feature.
+    Hello { version: u32, features: Vec<String>, token: Option<String>, #[serde(default)] encoding: wire::Encoding },
+    // Server -> client, right before the Welcome: what was agreed. `build` identifies the
+    // server deployment.
+    ServerHello { version: u32, min_version: u32, features: Vec<String>, build: String },
+    // Server -> client, always JSON, then the connection closes: this client is too old to play.
+    VersionRejected { version: u32, min_version: u32, build: String },
+    // Server -> client: the deployed web client speaks a newer protocol than this one, so a
+    // reload would pick up an update. Sent at join and whenever the server learns of a new
+    // deploy; the session carries on.
+    NewClientAvailable { version: u32 },
+    // Server -> client: the server is going down (usually a redeploy); it will be worth
+    // reconnecting after about `reconnect_after_ms`. The connection closes right after.
+    ServerShutdown { reason: String, reconnect_after_ms: u32 },
+    // Handshake of clients before protocol 28. Only parsed so they can be told to update.
+    Join { version: u32, token: Option<String>, #[serde(default)] encoding: wire::Encoding },
+    // Server -> client: this connection is being dropped (or the join refused) for `reason`,
+    // which is meant for the player. The connection closes right after; `reconnect` says whether
+    // trying again can work (false for a banned account).
+    Kicked { reason: String, reconnect: bool },
+    Welcome { player_id: i32, chunk_x: i32, chunk_y: i32, players: Vec<PlayerInfo>, units: Vec<UnitDTO>, buildings: Vec<BuildingDTO>, token: String, resources: Resources, pop_cap: i32, pop_used: i32, balance: Box<Balance> },
+    NewPlayer { player: PlayerInfo, units: Vec<UnitDTO> },
+    // Command: the owner is always the sending connection, never a field in the message.
+    MoveUnit { unit_id: u64, tile_x: i32, tile_y: i32 },
+    // Server -> client only (echo of an accepted MoveUnit so others can predict the walk)
+    UnitMove { player_id: i32, unit_id: u64, tile_x: i32, tile_y: i32 },
+    UnitSync { player_id: i32, unit_id: u64, x: f32, y: f32 },
+    SpawnUnit,
+    TrainUnit { building_id: i32, kind: u8 },
+    UnitSpawned { unit: UnitDTO },
+    Build { kind: u8, tile_x: i32, tile_y: i32 },
+    BuildProgress { tile_x: i32, tile_y: i32, kind: u8, progress: f32 },
+    BuildingSpawned { building: BuildingDTO },
+    AssignGather { unit_ids: Vec<u64>, target_x: i32, target_y: i32, kind: u8 },
+    DepositNow { unit_ids: Vec<u64> },
+    TowerShot { x1: f32, y1: f32, x2: f32, y2: f32 },
+    UnitDied { owner_id: i32, unit_id: u64 },
+    BuildingDestroyed { tile_x: i32, tile_y: i32 },
+    UnitHp { owner_id: i32, unit_id: u64, hp: f32 },
+    BuildingHp { tile_x: i32, tile_y: i32, hp: f32 },
+    ResourceUpdate { player_id: i32, resources: Resources, pop_cap: i32, pop_used: i32 },
+    DeleteUnit { unit_id: u64 },
+    DeleteBuilding { tile_x: i32, tile_y: i32 },
+    UnitCarry { owner_id: i32, unit_id: u64, carry_wood: f32, carry_stone: f32, carry_gold: f32, carry_food: f32 },
+    CommandAck { seq: u32, ok: bool, reason: Option<RejectReason> },
+    // Command: the chunks the camera covers (inclusive). The server only sends events for these,
+    // the chunks around the player's own units and buildings, and the player's own units.
+    SetView { chunk_x0: i32, chunk_y0: i32, chunk_x1: i32, chunk_y1: i32 },
+    // Server -> client: everything in a chunk that just came into view. Replaces what the client
+    // knew about other players' units, buildings and sites there.
+    ChunkEnter { chunk_x: i32, chunk_y: i32, units: Vec<UnitDTO>, buildings: Vec<BuildingDTO>, sites: Vec<BuildSite> },
+    // Server -> client: no more events for this chunk; other players' things in it go stale.
+    ChunkLeave { chunk_x: i32, chunk_y: i32 },
+    // Server -> client: another player's unit walked into / out of the chunks this client sees.
+    UnitEnter { unit: UnitDTO },
+    UnitLeave { owner_id: i32, unit_id: u64 },
+    // Command: ask for a FullState, e.g. after the tab was in the background.
+    RequestResync,
+    // Server -> client: everything this client can see, replacing what it had. Sent on request,
+    // and when the client fell so far behind that events for it were dropped.
+    FullState { players: Vec<PlayerInfo>, units: Vec<UnitDTO>, buildings: Vec<BuildingDTO>, sites: Vec<BuildSite>, resources: Resources, pop_cap: i32, pop_used: i32 },
+    // Server -> client: several messages in one frame, to be applied in order before the next
+    // render. Everything a client gets for one tick (or one command) arrives as a single frame.
+    TickBatch { tick: u64, events: Vec<GameMessage> },
+    Error { message: String },
+}
+
+impl GameMessage {
+    /// The variant name, as in the JSON `type` tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMessage::Hello { .. } => "Hello",
+            GameMessage::ServerHello { .. } => "ServerHello",
+            GameMessage::VersionRejected { .. } => "VersionRejected",
+            GameMessage::NewClientAvailable { .. } => "NewClientAvailable",
+            GameMessage::ServerShutdown { .. } => "ServerShutdown",
+            GameMessage::Join { .. } => "Join",
+            GameMessage::Kicked { .. } => "Kicked",
+            GameMessage::Welcome { .. } => "Welcome",
+            GameMessage::NewPlayer { .. } => "NewPlayer",
+            GameMessage::MoveUnit { .. } => "MoveUnit",
+            GameMessage::UnitMove { .. } => "UnitMove",
+            GameMessage::UnitSync { .. } => "UnitSync",
+            GameMessage::SpawnUnit => "SpawnUnit",
+            GameMessage::TrainUnit { .. } => "TrainUnit",
+            GameMessage::UnitSpawned { .. } => "UnitSpawned",
+            GameMessage::Build { .. } => "Build",
+            GameMessage::BuildProgress { .. } => "BuildProgress",
+            GameMessage::BuildingSpawned { .. } => "BuildingSpawned",
+            GameMessage::AssignGather { .. } => "AssignGather",
+            GameMessage::DepositNow { .. } => "DepositNow",
+            GameMessage::TowerShot { .. } => "TowerShot",
+            GameMessage::UnitDied { .. } => "UnitDied",
+            GameMessage::BuildingDestroyed { .. } => "BuildingDestroyed",
+            GameMessage::UnitHp { .. } => "UnitHp",
+            GameMessage::BuildingHp { .. } => "BuildingHp",
+            GameMessage::ResourceUpdate { .. } => "ResourceUpdate",
+            GameMessage::DeleteUnit { .. } => "DeleteUnit",
+            GameMessage::DeleteBuilding { .. } => "DeleteBuilding",
+            GameMessage::UnitCarry { .. } => "UnitCarry",
+            GameMessage::CommandAck { .. } => "CommandAck",
+            GameMessage::SetView { .. } => "SetView",
+            GameMessage::ChunkEnter { .. } => "ChunkEnter",
+            GameMessage::ChunkLeave { .. } => "ChunkLeave",
+            GameMessage::UnitEnter { .. } => "UnitEnter",
+            GameMessage::UnitLeave { .. } => "UnitLeave",
+            GameMessage::RequestResync => "RequestResync",
+            GameMessage::FullState { .. } => "FullState",
+            GameMessage::TickBatch { .. } => "TickBatch",
+            GameMessage::Error { .. } => "Error",
+        }
+    }
+}
+
+// Everything a client sends after Hello. `seq` is echoed back in the CommandAck so the client can
+// confirm or roll back whatever it predicted for that command.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientCommand {
+    pub seq: u32,
+    #[serde(flatten)]
+    pub msg: GameMessage,
+}
+
+// Why a command was refused. Sent only to the connection that issued it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    UnknownUnit,
+    UnknownBuilding,
+    NotOwner,
+    InvalidKind,
+    TileBlocked,
+    CannotAfford,
+    PopCapReached,
+    ProtectedBuilding,
+    NoPath,
+    NotACommand,
+    // Sent faster than the server's per-connection limit; try again shortly
+    RateLimited,
+}
+
+impl RejectReason {
+    pub fn describe(self) -> &'static str {
+        match self {
+            RejectReason::UnknownUnit => "That unit no longer exists",
+            RejectReason::UnknownBuilding => "That building no longer exists",
+            RejectReason::NotOwner => "That isn't yours",
+            RejectReason::InvalidKind => "Can't do that here",
+            RejectReason::TileBlocked => "Tile is blocked",
+            RejectReason::CannotAfford => "Not enough resources",
+            RejectReason::PopCapReached => "Population cap reached",
+            RejectReason::ProtectedBuilding => "Town Center can't be deleted",
+            RejectReason::NoPath => "No path to target",
+            RejectReason::NotACommand => "Unknown command",
+            RejectReason::RateLimited => "Too many commands, slow down",
+        }
+    }
+}
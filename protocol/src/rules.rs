@@ -0,0 +1,436 @@
+// Kind ids and the balance table. Kinds travel as `u8` on the wire; the enums below are the one
+// place those numbers are spelled out.
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Missing amounts read as 0, so balance files can write `{ "wood": 30 }`
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Resources {
+    pub wood: f32,
+    pub stone: f32,
+    pub gold: f32,
+    pub food: f32,
+}
+
+impl Resources {
+    pub const fn new(wood: f32, stone: f32, gold: f32, food: f32) -> Self {
+        Resources {
+            wood,
+            stone,
+            gold,
+            food,
+        }
+    }
+
+    pub fn has(&self, cost: &Resources) -> bool {
+        self.wood >= cost.wood
+            && self.stone >= cost.stone
+            && self.gold >= cost.gold
+            && self.food >= cost.food
+    }
+
+    pub fn spend(&mut self, cost: &Resources) -> bool {
+        if self.has(cost) {
+            self.wood -= cost.wood;
+            self.stone -= cost.stone;
+            self.gold -= cost.gold;
+            self.food -= cost.food;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Amounts as a balance file spells them. Unlike `Resources` on the wire, a key that isn't a
+// resource is a typo: `{ "wod": 30 }` must not load as a free building.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Cost {
+    wood: f32,
+    stone: f32,
+    gold: f32,
+    food: f32,
+}
+
+fn cost<'de, D: Deserializer<'de>>(d: D) -> Result<Resources, D::Error> {
+    let Cost {
+        wood,
+        stone,
+        gold,
+        food,
+    } = Cost::deserialize(d)?;
+    Ok(Resources::new(wood, stone, gold, food))
+}
+
+// Building kind 0; never placed by players, so it isn't a BuildKind.
+pub const TOWN_CENTER_KIND: u8 = 0;
+
+// Buildings a player can place (kinds 1..=8).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BuildKind {
+    Wall,
+    Farm,
+    House,
+    Tower,
+    Barracks,
+    LumberMill,
+    MiningCamp,
+    WheatMill,
+}
+
+impl BuildKind {
+    pub fn to_kind_id(self) -> u8 {
+        match self {
+            BuildKind::Wall => 1,
+            BuildKind::Farm => 2,
+            BuildKind::House => 3,
+            BuildKind::Tower => 4,
+            BuildKind::Barracks => 5,
+            BuildKind::LumberMill => 6,
+            BuildKind::MiningCamp => 7,
+            BuildKind::WheatMill => 8,
+        }
+    }
+
+    pub fn from_kind_id(kind: u8) -> Option<Self> {
+        match kind {
+            1 => Some(BuildKind::Wall),
+            2 => Some(BuildKind::Farm),
+            3 => Some(BuildKind::House),
+            4 => Some(BuildKind::Tower),
+            5 => Some(BuildKind::Barracks),
+            6 => Some(BuildKind::LumberMill),
+            7 => Some(BuildKind::MiningCamp),
+            8 => Some(BuildKind::WheatMill),
+            _ => None,
+        }
+    }
+}
+
+// What workers gather (`AssignGather` kinds 2..=5). Wood, stone and gold come from terrain
+// (`TileType::gather_kind`), food from farms.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResourceKind {
+    Wood,
+    Stone,
+    Gold,
+    Food,
+}
+
+impl ResourceKind {
+    pub fn to_kind_id(self) -> u8 {
+        match self {
+            ResourceKind::Wood => 2,
+            ResourceKind::Stone => 3,
+            ResourceKind::Gold => 4,
+            ResourceKind::Food => 5,
+        }
+    }
+
+    pub fn from_kind_id(kind: u8) -> Option<Self> {
+        match kind {
+            2 => Some(ResourceKind::Wood),
+            3 => Some(ResourceKind::Stone),
+            4 => Some(ResourceKind::Gold),
+            5 => Some(ResourceKind::Food),
+            _ => None,
+        }
+    }
+
+    // This resource's share of `r`.
+    pub fn amount(self, r: &Resources) -> f32 {
+        match self {
+            ResourceKind::Wood => r.wood,
+            ResourceKind::Stone => r.stone,
+            ResourceKind::Gold => r.gold,
+            ResourceKind::Food => r.food,
+        }
+    }
+
+    // Where workers can drop it off besides the Town Center.
+    pub fn dropoff(self) -> BuildKind {
+        match self {
+            ResourceKind::Wood => BuildKind::LumberMill,
+            ResourceKind::Stone | ResourceKind::Gold => BuildKind::MiningCamp,
+            ResourceKind::Food => BuildKind::WheatMill,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnitKind {
+    Worker,
+    Warrior,
+}
+
+impl UnitKind {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            UnitKind::Worker => 0,
+            UnitKind::Warrior => 1,
+        }
+    }
+
+    pub fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(UnitKind::Worker),
+            1 => Some(UnitKind::Warrior),
+            _ => None,
+        }
+    }
+}
+
+// Everything tunable about the economy and combat. The server loads it at startup (defaults
+// below for any field the balance file leaves out) and sends it in Welcome, so the client's
+// affordability checks and HP bars always use the numbers the server enforces.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Balance {
+    pub costs: Costs,
+    pub hp: HitPoints,
+    // Amount a worker picks up per server tick, per resource
+    #[serde(deserialize_with = "cost")]
+    pub gather_per_tick: Resources,
+    // Starting amount in each resource tile
+    #[serde(deserialize_with = "cost")]
+    pub node_amount: Resources,
+    pub carry_cap: f32,
+    pub tower_damage: f32,
+    pub warrior_dps: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Costs {
+    #[serde(deserialize_with = "cost")]
+    pub wall: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub farm: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub house: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub tower: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub barracks: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub lumber_mill: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub mining_camp: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub wheat_mill: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub worker: Resources,
+    #[serde(deserialize_with = "cost")]
+    pub warrior: Resources,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HitPoints {
+    pub town_center: f32,
+    pub wall: f32,
+    pub farm: f32,
+    pub house: f32,
+    pub tower: f32,
+    pub barracks: f32,
+    pub lumber_mill: f32,
+    pub mining_camp: f32,
+    pub wheat_mill: f32,
+    pub worker: f32,
+    pub warrior: f32,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Balance {
+            costs: Costs::default(),
+            hp: HitPoints::default(),
+            gather_per_tick: Resources::new(2.0, 2.0, 2.0, 2.0),
+            node_amount: Resources::new(120.0, 120.0, 120.0, 100.0),
+            carry_cap: 80.0,
+            tower_damage: 25.0,
+            warrior_dps: 30.0,
+        }
+    }
+}
+
+impl Default for Costs {
+    fn default() -> Self {
+        Costs {
+            wall: Resources::new(1.0, 5.0, 0.0, 0.0),
+            farm: Resources::new(30.0, 0.0, 0.0, 0.0),
+            house: Resources::new(25.0, 0.0, 0.0, 0.0),
+            tower: Resources::new(0.0, 40.0, 0.0, 0.0),
+            barracks: Resources::new(60.0, 0.0, 0.0, 0.0),
+            lumber_mill: Resources::new(30.0, 10.0, 0.0, 0.0),
+            mining_camp: Resources::new(30.0, 10.0, 0.0, 0.0),
+            wheat_mill: Resources::new(30.0, 10.0, 0.0, 0.0),
+            worker: Resources::new(0.0, 0.0, 0.0, 50.0),
+            warrior: Resources::new(0.0, 0.0, 20.0, 40.0),
+        }
+    }
+}
+
+impl Default for HitPoints {
+    fn default() -> Self {
+        HitPoints {
+            town_center: 800.0,
+            wall: 200.0,
+            farm: 220.0,
+            house: 220.0,
+            tower: 300.0,
+            barracks: 260.0,
+            lumber_mill: 220.0,
+            mining_camp: 220.0,
+            wheat_mill: 220.0,
+            worker: 50.0,
+            warrior: 120.0,
+        }
+    }
+}
+
+impl Balance {
+    // Cost of any building kind id; nothing for the Town Center or unknown ids.
+    pub fn build_cost(&self, kind: u8) -> Resources {
+        let c = &self.costs;
+        match BuildKind::from_kind_id(kind) {
+            Some(BuildKind::Wall) => c.wall,
+            Some(BuildKind::Farm) => c.farm,
+            Some(BuildKind::House) => c.house,
+            Some(BuildKind::Tower) => c.tower,
+            Some(BuildKind::Barracks) => c.barracks,
+            Some(BuildKind::LumberMill) => c.lumber_mill,
+            Some(BuildKind::MiningCamp) => c.mining_camp,
+            Some(BuildKind::WheatMill) => c.wheat_mill,
+            None => Resources::new(0.0, 0.0, 0.0, 0.0),
+        }
+    }
+
+    // Max hit points of any building kind id, including the Town Center.
+    pub fn building_hp(&self, kind: u8) -> f32 {
+        let hp = &self.hp;
+        if kind == TOWN_CENTER_KIND {
+            return hp.town_center;
+        }
+        match BuildKind::from_kind_id(kind) {
+            Some(BuildKind::Wall) => hp.wall,
+            Some(BuildKind::Farm) => hp.farm,
+            Some(BuildKind::House) => hp.house,
+            Some(BuildKind::Tower) => hp.tower,
+            Some(BuildKind::Barracks) => hp.barracks,
+            Some(BuildKind::LumberMill) => hp.lumber_mill,
+            Some(BuildKind::MiningCamp) => hp.mining_camp,
+            Some(BuildKind::WheatMill) => hp.wheat_mill,
+            None => 200.0,
+        }
+    }
+
+    pub fn unit_cost(&self, kind: UnitKind) -> Resources {
+        match kind {
+            UnitKind::Worker => self.costs.worker,
+            UnitKind::Warrior => self.costs.warrior,
+        }
+    }
+
+    // Max hit points of a unit kind id; unknown ids are treated as workers.
+    pub fn unit_hp(&self, kind: u8) -> f32 {
+        match UnitKind::from_u8(kind) {
+            Some(UnitKind::Warrior) => self.hp.warrior,
+            _ => self.hp.worker,
+        }
+    }
+
+    // Rejects tables the simulation can't run with: negative or non-finite costs and rates,
+    // and hit points or carry capacity that aren't positive. The error names the offending field.
+    pub fn validate(&self) -> Result<(), String> {
+        let c = &self.costs;
+        let costs = [
+            ("costs.wall", c.wall),
+            ("costs.farm", c.farm),
+            ("costs.house", c.house),
+            ("costs.tower", c.tower),
+            ("costs.barracks", c.barracks),
+            ("costs.lumber_mill", c.lumber_mill),
+            ("costs.mining_camp", c.mining_camp),
+            ("costs.wheat_mill", c.wheat_mill),
+            ("costs.worker", c.worker),
+            ("costs.warrior", c.warrior),
+            ("gather_per_tick", self.gather_per_tick),
+            ("node_amount", self.node_amount),
+        ];
+        for (name, r) in costs {
+            for (field, v) in [
+                ("wood", r.wood),
+                ("stone", r.stone),
+                ("gold", r.gold),
+                ("food", r.food),
+            ] {
+                if !v.is_finite() || v < 0.0 {
+                    return Err(format!(
+                        "{}.{} must be a number >= 0 (got {})",
+                        name, field, v
+                    ));
+                }
+            }
+        }
+
+        let hp = &self.hp;
+        let positive = [
+            ("hp.town_center", hp.town_center),
+            ("hp.wall", hp.wall),
+            ("hp.farm", hp.farm),
+            ("hp.house", hp.house),
+            ("hp.tower", hp.tower),
+            ("hp.barracks", hp.barracks),
+            ("hp.lumber_mill", hp.lumber_mill),
+            ("hp.mining_camp", hp.mining_camp),
+            ("hp.wheat_mill", hp.wheat_mill),
+            ("hp.worker", hp.worker),
+            ("hp.warrior", hp.warrior),
+            ("carry_cap", self.carry_cap),
+        ];
+        for (name, v) in positive {
+            if !v.is_finite() || v <= 0.0 {
+                return Err(format!("{} must be a number > 0 (got {})", name, v));
+            }
+        }
+
+        for (name, v) in [
+            ("tower_damage", self.tower_damage),
+            ("warrior_dps", self.warrior_dps),
+        ] {
+            if !v.is_finite() || v < 0.0 {
+                return Err(format!("{} must be a number >= 0 (got {})", name, v));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance_files_fill_in_missing_amounts_but_refuse_typos() {
+        let balance: Balance =
+            serde_json::from_str(r#"{ "costs": { "farm": { "wood": 30 } } }"#).unwrap();
+        assert_eq!(
+            (balance.costs.farm.wood, balance.costs.farm.stone),
+            (30.0, 0.0)
+        );
+        assert_eq!(balance.costs.house.wood, Costs::default().house.wood);
+
+        for typo in [
+            r#"{ "costs": { "farm": { "wod": 30 } } }"#,
+            r#"{ "gather_per_tick": { "wood": 2, "iron": 1 } }"#,
+        ] {
+            let err = serde_json::from_str::<Balance>(typo)
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("unknown field"), "{}", err);
+        }
+    }
+}
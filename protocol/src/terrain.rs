@@ -0,0 +1,122 @@
+//! The world generator. Terrain is never sent over the wire: the client and the server each run
+//! this to draw, path and validate against the same tiles, so it lives here rather than on either
+//! side.
+
+use crate::ResourceKind;
+
+pub const CHUNK_SIZE: i32 = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TileType {
+    Grass,
+    Forest,
+    Mountain,
+    Gold,
+}
+
+impl TileType {
+    // Only grass can be walked on or built over
+    pub fn is_walkable(self) -> bool {
+        self == TileType::Grass
+    }
+
+    // The resource this tile yields, if any. Food comes from farms, not terrain.
+    pub fn gather_kind(self) -> Option<ResourceKind> {
+        match self {
+            TileType::Forest => Some(ResourceKind::Wood),
+            TileType::Mountain => Some(ResourceKind::Stone),
+            TileType::Gold => Some(ResourceKind::Gold),
+            TileType::Grass => None,
+        }
+    }
+}
+
+pub fn calculate_tile_type(cx: i32, cy: i32, lx: i32, ly: i32) -> TileType {
+    // Ensure walkability for Town Center (center of chunk)
+    let mid = CHUNK_SIZE / 2;
+    if lx >= mid - 3 && lx <= mid + 3 && ly >= mid - 3 && ly <= mid + 3 {
+        return TileType::Grass;
+    }
+
+    // Per mille: 25% forest, 3% mountain, 0.3% gold
+    let r = tile_hash(cx, cy, lx, ly) % 1000;
+    if r < 250 {
+        TileType::Forest
+    } else if r < 280 {
+        TileType::Mountain
+    } else if r < 283 {
+        TileType::Gold
+    } else {
+        TileType::Grass
+    }
+}
+
+// Integer arithmetic only, so the wasm client and the native server agree bit for bit; floating
+// point functions like `sin` differ between libm implementations.
+fn tile_hash(cx: i32, cy: i32, lx: i32, ly: i32) -> u64 {
+    [cx, cy, lx, ly]
+        .into_iter()
+        .fold(0, |h, v| splitmix64(h ^ v as u32 as u64))
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// One chunk's tiles, row by row.
+pub fn generate_chunk(cx: i32, cy: i32) -> Vec<TileType> {
+    (0..CHUNK_SIZE)
+        .flat_map(|ly| (0..CHUNK_SIZE).map(move |lx| calculate_tile_type(cx, cy, lx, ly)))
+        .collect()
+}
+
+/// Terrain at a global tile coordinate.
+pub fn tile_at(gx: i32, gy: i32) -> TileType {
+    calculate_tile_type(
+        gx.div_euclid(CHUNK_SIZE),
+        gy.div_euclid(CHUNK_SIZE),
+        gx.rem_euclid(CHUNK_SIZE),
+        gy.rem_euclid(CHUNK_SIZE),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_match_global_lookups_and_keep_their_centre_clear() {
+        for (cx, cy) in [(0, 0), (-1, 2), (5, -7)] {
+            let tiles = generate_chunk(cx, cy);
+            for (i, tile) in tiles.iter().enumerate() {
+                let (lx, ly) = (i as i32 % CHUNK_SIZE, i as i32 / CHUNK_SIZE);
+                assert_eq!(*tile, tile_at(cx * CHUNK_SIZE + lx, cy * CHUNK_SIZE + ly));
+            }
+            let mid = CHUNK_SIZE / 2;
+            assert!(tiles[(mid * CHUNK_SIZE + mid) as usize].is_walkable());
+            assert!(tiles.iter().any(|t| t.gather_kind().is_some()));
+        }
+    }
+
+    #[test]
+    fn known_tiles_never_change() {
+        // Any change here reshapes every existing world under its players' buildings
+        let pinned = [
+            ((16, 16), TileType::Grass),
+            ((-16, -16), TileType::Grass),
+            ((-40, -40), TileType::Grass),
+            ((21, 19), TileType::Forest),
+            ((-36, -40), TileType::Forest),
+            ((24, -40), TileType::Mountain),
+            ((-25, -39), TileType::Mountain),
+            ((17, 20), TileType::Gold),
+            ((10, -35), TileType::Gold),
+        ];
+        for ((gx, gy), tile) in pinned {
+            assert_eq!(tile_at(gx, gy), tile, "tile ({}, {})", gx, gy);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Frame encodings. Every connection starts with a JSON `Hello`; from then on the server sends
+//! in whichever `Encoding` the client asked for. JSON text frames are easy to read in devtools.
+//! Binary frames are MessagePack and pack the high-volume messages tighter. For example, a
+//! `UnitSync` drops its field names and sends positions as whole multiples of
+//! 1/`POSITION_SCALE` of a pixel.
+
+use serde::{Deserialize, Serialize};
+
+use crate::GameMessage;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    Binary,
+}
+
+/// Binary positions are rounded to this fraction of a pixel.
+pub const POSITION_SCALE: f32 = 4.0;
+
+// Short tags: they are sent with every message
+#[derive(Serialize, Deserialize)]
+enum Packed {
+    #[serde(rename = "s")]
+    UnitSync(i32, u64, i32, i32),
+    #[serde(rename = "b")]
+    TickBatch(u64, Vec<Packed>),
+    #[serde(rename = "m")]
+    Message(GameMessage),
+}
+
+fn quantize(v: f32) -> i32 {
+    (v * POSITION_SCALE).round() as i32
+}
+
+fn dequantize(v: i32) -> f32 {
+    v as f32 / POSITION_SCALE
+}
+
+fn pack(msg: &GameMessage) -> Packed {
+    match *msg {
+        GameMessage::UnitSync {
+            player_id,
+            unit_id,
+            x,
+            y,
+        } => Packed::UnitSync(player_id, unit_id, quantize(x), quantize(y)),
+        GameMessage::TickBatch { tick, ref events } => {
+            Packed::TickBatch(tick, events.iter().map(pack).collect())
+        }
+        ref msg => Packed::Message(msg.clone()),
+    }
+}
+
+fn unpack(packed: Packed) -> GameMessage {
+    match packed {
+        Packed::UnitSync(player_id, unit_id, x, y) => GameMessage::UnitSync {
+            player_id,
+            unit_id,
+            x: dequantize(x),
+            y: dequantize(y),
+        },
+        Packed::TickBatch(tick, events) => GameMessage::TickBatch {
+            tick,
+            events: events.into_iter().map(unpack).collect(),
+        },
+        Packed::Message(msg) => msg,
+    }
+}
+
+pub fn to_binary(msg: &GameMessage) -> Vec<u8> {
+    rmp_serde::to_vec(&pack(msg)).expect("messages always encode")
+}
+
+pub fn from_binary(bytes: &[u8]) -> Result<GameMessage, String> {
+    rmp_serde::from_slice(bytes)
+        .map(unpack)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuildingDTO, Resources};
+
+    #[test]
+    fn unit_sync_is_small_and_keeps_quarter_pixels() {
+        let msg = GameMessage::UnitSync {
+            player_id: 3,
+            unit_id: 1234,
+            x: 8520.26,
+            y: -17.6,
+        };
+        let bytes = to_binary(&msg);
+        assert!(bytes.len() < 16, "{} bytes", bytes.len());
+        let GameMessage::UnitSync {
+            player_id,
+            unit_id,
+            x,
+            y,
+        } = from_binary(&bytes).unwrap()
+        else {
+            panic!()
+        };
+        assert_eq!((player_id, unit_id, x, y), (3, 1234, 8520.25, -17.5));
+    }
+
+    #[test]
+    fn other_messages_round_trip() {
+        let building = BuildingDTO {
+            id: 7,
+            owner_id: 2,
+            kind: 3,
+            tile_x: -40,
+            tile_y: 16,
+            hp: 250.0,
+        };
+        let msgs = [
+            GameMessage::BuildingSpawned { building },
+            GameMessage::ResourceUpdate {
+                player_id: 2,
+                resources: Resources::new(1.0, 2.0, 3.0, 4.5),
+                pop_cap: 6,
+                pop_used: 2,
+            },
+            GameMessage::ChunkLeave {
+                chunk_x: -1,
+                chunk_y: 0,
+            },
+            GameMessage::RequestResync,
+            GameMessage::TickBatch {
+                tick: 9,
+                events: vec![
+                    GameMessage::UnitSync {
+                        player_id: 1,
+                        unit_id: 2,
+                        x: 3.25,
+                        y: 4.5,
+                    },
+                    GameMessage::RequestResync,
+                ],
+            },
+        ];
+        // Debug output is enough to compare messages without making GameMessage PartialEq
+        for msg in msgs {
+            let decoded = from_binary(&to_binary(&msg)).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", msg));
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! Local admin socket. Reads one `AdminRequest` per line, hands it to the simulation and writes
+//! back one `AdminResponse` per line. Only loopback peers are served.
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+use protocol::admin::{AdminRequest, AdminResponse};
+
+use tracing::{info, warn};
+
+use crate::sim::SimCommand;
+
+/// When `ADMIN_ADDR` isn't set. The `admin` binary defaults to the same address.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:9002";
+
+pub async fn serve(listener: TcpListener, sim: mpsc::Sender<SimCommand>) {
+    while let Ok((stream, peer)) = listener.accept().await {
+        if !peer.ip().is_loopback() {
+            warn!(%peer, "Refusing admin connection");
+            continue;
+        }
+        tokio::spawn(handle(stream, sim.clone()));
+    }
+}
+
+async fn handle(stream: TcpStream, sim: mpsc::Sender<SimCommand>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let resp = match serde_json::from_str::<AdminRequest>(&line) {
+            Ok(req) => {
+                info!(?req, "Admin request");
+                let (reply, rx) = oneshot::channel();
+                if sim.send(SimCommand::Admin { req, reply }).await.is_err() {
+                    break;
+                }
+                rx.await.unwrap_or(AdminResponse::Error {
+                    message: "Simulation stopped".to_string(),
+                })
+            }
+            Err(e) => AdminResponse::Error {
+                message: format!("Bad request: {}", e),
+            },
+        };
+        let mut json = serde_json::to_string(&resp).unwrap();
+        json.push('\n');
+        if write.write_all(json.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
@@ -1,3 +1,176 @@
+// Operations CLI. Talks to a running chat-server over its loopback admin socket (ADMIN_ADDR,
+// default 127.0.0.1:9002).
+//
+//   admin players
+//   admin player <id>
+//   admin grant <id> <wood> <stone> <gold> <food>   (negative amounts remove)
+//   admin kick <id>
+//   admin ban <token>
+//   admin reset-world --yes
+//   admin save
+//   admin latest-client <version>
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::exit;
+
+use protocol::admin::{AdminRequest, AdminResponse, PlayerSummary};
+use protocol::Resources;
+
+const USAGE: &str = "usage: admin <command>
+  players                                  list players and their resources
+  player <id>                              show a player's units and buildings
+  grant <id> <wood> <stone> <gold> <food>  add resources (negative removes)
+  kick <id>                                disconnect a player
+  ban <token>                              refuse a token and kick its player
+  reset-world --yes                        wipe every player, unit and building
+  save                                     write a snapshot now
+  latest-client <version>                  tell older clients a new web client is out";
+
 fn main() {
-    println!("Admin tool disabled: server now runs purely in-memory (no SQL).");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let req = match parse(&args) {
+        Ok(req) => req,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+
+    let addr =
+        std::env::var("ADMIN_ADDR").unwrap_or_else(|_| chat_server::DEFAULT_ADMIN_ADDR.to_string());
+    match send(&addr, &req) {
+        Ok(AdminResponse::Error { message }) => {
+            eprintln!("error: {}", message);
+            exit(1);
+        }
+        Ok(resp) => print_response(resp),
+        Err(e) => {
+            eprintln!("admin socket {}: {}", addr, e);
+            exit(1);
+        }
+    }
+}
+
+fn parse(args: &[String]) -> Result<AdminRequest, String> {
+    let arg = |i: usize, name: &str| {
+        args.get(i)
+            .cloned()
+            .ok_or_else(|| format!("missing <{}>", name))
+    };
+    let player_id = |i: usize| -> Result<i32, String> {
+        arg(i, "id")?
+            .parse()
+            .map_err(|_| format!("<id> must be a number, got {:?}", args[i]))
+    };
+    let amount = |i: usize, name: &str| -> Result<f32, String> {
+        arg(i, name)?
+            .parse()
+            .map_err(|_| format!("<{}> must be a number, got {:?}", name, args[i]))
+    };
+
+    let Some(cmd) = args.first() else {
+        return Err("missing command".to_string());
+    };
+    match cmd.as_str() {
+        "players" => Ok(AdminRequest::ListPlayers),
+        "player" => Ok(AdminRequest::InspectPlayer {
+            player_id: player_id(1)?,
+        }),
+        "grant" => Ok(AdminRequest::GrantResources {
+            player_id: player_id(1)?,
+            delta: Resources::new(
+                amount(2, "wood")?,
+                amount(3, "stone")?,
+                amount(4, "gold")?,
+                amount(5, "food")?,
+            ),
+        }),
+        "kick" => Ok(AdminRequest::Kick {
+            player_id: player_id(1)?,
+        }),
+        "ban" => Ok(AdminRequest::BanToken {
+            token: arg(1, "token")?,
+        }),
+        "reset-world" => {
+            if args.get(1).map(String::as_str) != Some("--yes") {
+                return Err("reset-world deletes everything; pass --yes to confirm".to_string());
+            }
+            Ok(AdminRequest::ResetWorld)
+        }
+        "save" => Ok(AdminRequest::SaveSnapshot),
+        "latest-client" => {
+            let version = arg(1, "version")?;
+            let version = version
+                .parse()
+                .map_err(|_| format!("<version> must be a number, got {:?}", version))?;
+            Ok(AdminRequest::SetLatestClient { version })
+        }
+        other => Err(format!("unknown command {:?}", other)),
+    }
+}
+
+fn send(addr: &str, req: &AdminRequest) -> std::io::Result<AdminResponse> {
+    let mut stream = TcpStream::connect(addr)?;
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(serde_json::from_str(&reply)?)
+}
+
+fn print_response(resp: AdminResponse) {
+    match resp {
+        AdminResponse::Ok { message } => println!("{}", message),
+        AdminResponse::Players { players } => {
+            println!(
+                "{:>5}  {:>8}  {:>7}  {:>6}  {:>6}  {:>6}  {:>6}  {:>5}  {:>9}",
+                "id", "chunk", "online", "wood", "stone", "gold", "food", "units", "buildings"
+            );
+            for p in &players {
+                print_player_row(p);
+            }
+            println!("{} players", players.len());
+        }
+        AdminResponse::Player {
+            player,
+            units,
+            buildings,
+        } => {
+            print_player_row(&player);
+            println!("\nunits:");
+            for u in &units {
+                println!(
+                    "  #{:<6} kind {}  hp {:>5.0}  at ({:.0}, {:.0})",
+                    u.unit_id, u.kind, u.hp, u.x, u.y
+                );
+            }
+            println!("\nbuildings:");
+            for b in &buildings {
+                println!(
+                    "  #{:<10} kind {}  hp {:>5.0}  tile ({}, {})",
+                    b.id, b.kind, b.hp, b.tile_x, b.tile_y
+                );
+            }
+        }
+        AdminResponse::Error { message } => eprintln!("error: {}", message),
+    }
+}
+
+fn print_player_row(p: &PlayerSummary) {
+    let r = &p.resources;
+    println!(
+        "{:>5}  {:>8}  {:>7}  {:>6.0}  {:>6.0}  {:>6.0}  {:>6.0}  {:>5}  {:>9}",
+        p.id,
+        format!("{},{}", p.chunk_x, p.chunk_y),
+        if p.online { "yes" } else { "no" },
+        r.wood,
+        r.stone,
+        r.gold,
+        r.food,
+        p.units,
+        p.buildings,
+    );
 }
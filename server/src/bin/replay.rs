@@ -0,0 +1,146 @@
+// Re-runs a replay log written by chat-server (to REPLAY_FILE, when set) and
+// checks that it reproduces the broadcasts the live server sent.
+//
+//   replay [file]                    check every session (server run) in the log; the file
+//                                    defaults to REPLAY_FILE
+//   replay [file] --session <n>      check only the n-th session (1-based)
+//   replay [file] --events <out>     also write each replayed broadcast to <out> as `<tick>\t<json>`
+//
+// Event files from two builds can be diffed to find the first event that changed.
+
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process::exit;
+
+use chat_server::replay::{self, Report};
+
+const USAGE: &str = "usage: replay [file] [--session <n>] [--events <out>]";
+
+struct Args {
+    path: PathBuf,
+    session: Option<usize>,
+    events: Option<PathBuf>,
+}
+
+fn main() {
+    let args = match parse(std::env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+
+    let sessions = match replay::read(&args.path) {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    if let Some(n) = args.session {
+        if n == 0 || n > sessions.len() {
+            eprintln!(
+                "{} has {} sessions; there is no session {}",
+                args.path.display(),
+                sessions.len(),
+                n
+            );
+            exit(2);
+        }
+    }
+
+    let mut events = match &args.events {
+        Some(path) => match std::fs::File::create(path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let mut diverged = false;
+    for (i, session) in sessions.iter().enumerate() {
+        let n = i + 1;
+        if args.session.is_some_and(|only| only != n) {
+            continue;
+        }
+        let result = replay::run(session, |tick, json| {
+            if let Some(out) = &mut events {
+                let _ = writeln!(out, "{}\t{}", tick, json);
+            }
+        });
+        match result {
+            Ok(report) => {
+                diverged |= report.divergence.is_some();
+                eprintln!(
+                    "session {} (line {}, seed {}): {}",
+                    n,
+                    session.line,
+                    session.seed,
+                    describe(&report)
+                );
+            }
+            Err(e) => {
+                eprintln!("session {} (line {}): {}", n, session.line, e);
+                exit(1);
+            }
+        }
+    }
+    if let Some(out) = &mut events {
+        let _ = out.flush();
+    }
+    if diverged {
+        exit(1);
+    }
+}
+
+fn parse(args: Vec<String>) -> Result<Args, String> {
+    let mut path = None;
+    let mut session = None;
+    let mut events = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--events" => {
+                events = Some(PathBuf::from(
+                    args.next().ok_or("missing <out> after --events")?,
+                ))
+            }
+            "--session" => {
+                let n = args.next().ok_or("missing <n> after --session")?;
+                session = Some(
+                    n.parse()
+                        .map_err(|_| format!("<n> must be a number, got {:?}", n))?,
+                );
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {:?}", flag)),
+            _ if path.is_some() => return Err(format!("unexpected argument {:?}", arg)),
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let path = path
+        .or_else(replay::replay_path)
+        .ok_or("no log: pass a file or set REPLAY_FILE")?;
+    Ok(Args {
+        path,
+        session,
+        events,
+    })
+}
+
+fn describe(report: &Report) -> String {
+    let summary = format!(
+        "{} ticks, {} entries applied, {} checksums matched",
+        report.ticks, report.applied, report.checked
+    );
+    match &report.divergence {
+        None => format!("ok: {}", summary),
+        Some(d) => format!(
+            "DIVERGED between tick {} and {}: recorded {} events (hash {:016x}), replayed {} (hash {:016x}); {}",
+            d.since_tick, d.tick, d.expected_events, d.expected_hash, d.replayed_events, d.replayed_hash, summary
+        ),
+    }
+}
@@ -0,0 +1,161 @@
+//! Plain HTTP on the game port, for the host's health checks and uptime monitoring. Requests are
+//! read ahead without consuming them: WebSocket upgrades go on to the game untouched, anything
+//! else is answered here and closed.
+//!
+//! - `GET /healthz`: the process is up.
+//! - `GET /readyz`: the simulation is running and answering.
+//! - `GET /info`: players, tick rate, uptime and build, as JSON.
+//! - `GET /metrics`: Prometheus metrics (see `metrics`).
+
+use std::time::Instant;
+
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep, timeout, Duration};
+
+use crate::sim::SimCommand;
+use crate::{build_id, MIN_CLIENT_VERSION_DEFAULT, TICK_MS};
+
+// Longest request head we wait for; probes send a few hundred bytes
+const MAX_HEAD: usize = 8192;
+
+// How long the endpoints that ask the simulation wait on the simulation before calling it unresponsive
+const SIM_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub enum Request {
+    /// A WebSocket handshake, still unread on the stream.
+    Upgrade,
+    /// Anything else; `head_len` bytes of request head are waiting on the stream.
+    Plain {
+        method: String,
+        path: String,
+        head_len: usize,
+    },
+}
+
+/// Waits for a whole request head and classifies it. `None` when the peer closed first or sent
+/// something that isn't HTTP.
+pub async fn peek_request(stream: &TcpStream) -> std::io::Result<Option<Request>> {
+    let mut buf = vec![0; MAX_HEAD];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if let Some(end) = buf[..n].windows(4).position(|w| w == b"\r\n\r\n") {
+            return Ok(parse(&buf[..end + 4]));
+        }
+        if n == MAX_HEAD {
+            return Ok(None);
+        }
+        // peek returns whatever has arrived so far; give the rest a moment
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn parse(head: &[u8]) -> Option<Request> {
+    let text = std::str::from_utf8(head).ok()?;
+    let mut lines = text.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let upgrade = lines
+        .filter_map(|l| l.split_once(':'))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("upgrade")
+                && value.trim().eq_ignore_ascii_case("websocket")
+        });
+    if upgrade {
+        return Some(Request::Upgrade);
+    }
+    let path = target.split('?').next().unwrap_or(target).to_string();
+    Some(Request::Plain {
+        method,
+        path,
+        head_len: head.len(),
+    })
+}
+
+/// Answers a plain request and closes the connection.
+pub async fn respond(
+    mut stream: TcpStream,
+    method: &str,
+    path: &str,
+    head_len: usize,
+    sim: &mpsc::Sender<SimCommand>,
+    started: Instant,
+) {
+    // Consumed so closing doesn't reset the connection under the response
+    let mut head = vec![0; head_len];
+    if stream.read_exact(&mut head).await.is_err() {
+        return;
+    }
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/healthz") => ("200 OK", "text/plain", "ok".to_string()),
+        ("GET", "/readyz") => match ask(sim, |reply| SimCommand::Status { reply }).await {
+            Some(_) => ("200 OK", "text/plain", "ready".to_string()),
+            None => (
+                "503 Service Unavailable",
+                "text/plain",
+                "simulation not responding".to_string(),
+            ),
+        },
+        ("GET", "/info") => match ask(sim, |reply| SimCommand::Status { reply }).await {
+            Some(s) => {
+                let info = json!({
+                    "build": build_id(),
+                    "protocol": protocol::PROTOCOL_VERSION,
+                    "min_client_version": MIN_CLIENT_VERSION_DEFAULT,
+                    "uptime_secs": started.elapsed().as_secs(),
+                    "tick_ms": TICK_MS,
+                    "tick": s.tick,
+                    "players": s.players,
+                    "online": s.online,
+                });
+                ("200 OK", "application/json", info.to_string())
+            }
+            None => (
+                "503 Service Unavailable",
+                "text/plain",
+                "simulation not responding".to_string(),
+            ),
+        },
+        ("GET", "/metrics") => match ask(sim, |reply| SimCommand::Metrics { reply }).await {
+            Some(text) => ("200 OK", "text/plain; version=0.0.4", text),
+            None => (
+                "503 Service Unavailable",
+                "text/plain",
+                "simulation not responding".to_string(),
+            ),
+        },
+        (_, "/healthz" | "/readyz" | "/info" | "/metrics") => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "GET only".to_string(),
+        ),
+        _ => ("404 Not Found", "text/plain", "not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+// Sends the simulation a query and waits for its answer, or gives up after SIM_TIMEOUT.
+async fn ask<T>(
+    sim: &mpsc::Sender<SimCommand>,
+    query: impl FnOnce(oneshot::Sender<T>) -> SimCommand,
+) -> Option<T> {
+    let (reply, rx) = oneshot::channel();
+    let answer = async {
+        sim.send(query(reply)).await.ok()?;
+        rx.await.ok()
+    };
+    timeout(SIM_TIMEOUT, answer).await.ok().flatten()
+}
@@ -0,0 +1,230 @@
+//! Interest management. A connection receives events only for the chunks its camera covers and
+//! the chunks around its player's units and buildings, plus anything about its own units
+//! wherever they are. Chunks that come into interest are sent whole (`ChunkEnter`); chunks that
+//! drop out are announced (`ChunkLeave`) so the client can forget them.
+
+use std::collections::{BTreeSet, HashMap};
+
+use protocol::terrain::CHUNK_SIZE;
+use protocol::{BuildSite, GameMessage};
+
+use crate::{GlobalState, TILE_SIZE};
+
+pub type Chunk = (i32, i32);
+
+// Chunks around the view and around owned units and buildings that also receive events, so
+// whatever walks on screen is already known.
+const MARGIN: i32 = 1;
+
+// Widest view accepted per axis, in chunks; larger requests are clamped.
+const MAX_VIEW: i32 = 8;
+
+// Chunks of i32 tile coordinates lie within ±this. Views are clamped to it, which also keeps
+// the arithmetic below far from overflowing whatever a client sends.
+const MAX_CHUNK: i32 = i32::MAX / CHUNK_SIZE;
+
+/// The chunk range a client's camera covers, inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct View {
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32,
+}
+
+impl View {
+    pub fn new(x0: i32, y0: i32, x1: i32, y1: i32) -> View {
+        let [x0, y0, x1, y1] = [x0, y0, x1, y1].map(|c| c.clamp(-MAX_CHUNK, MAX_CHUNK));
+        let (x0, x1) = (x0.min(x1), x0.max(x1));
+        let (y0, y1) = (y0.min(y1), y0.max(y1));
+        View {
+            x0,
+            y0,
+            x1: x1.min(x0 + MAX_VIEW - 1),
+            y1: y1.min(y0 + MAX_VIEW - 1),
+        }
+    }
+
+    /// What a client sees before its first SetView: its home chunk.
+    pub fn around((cx, cy): Chunk) -> View {
+        View::new(cx, cy, cx, cy)
+    }
+}
+
+pub fn tile_chunk(tile_x: i32, tile_y: i32) -> Chunk {
+    (tile_x.div_euclid(CHUNK_SIZE), tile_y.div_euclid(CHUNK_SIZE))
+}
+
+pub fn pos_chunk(x: f32, y: f32) -> Chunk {
+    tile_chunk(
+        (x / TILE_SIZE).floor() as i32,
+        (y / TILE_SIZE).floor() as i32,
+    )
+}
+
+/// Chunks `player_id` receives events for.
+pub fn interest(gs: &GlobalState, player_id: i32, view: View) -> BTreeSet<Chunk> {
+    let mut chunks = BTreeSet::new();
+    let mut add = |x0: i32, y0: i32, x1: i32, y1: i32| {
+        for cx in x0.saturating_sub(MARGIN)..=x1.saturating_add(MARGIN) {
+            for cy in y0.saturating_sub(MARGIN)..=y1.saturating_add(MARGIN) {
+                chunks.insert((cx, cy));
+            }
+        }
+    };
+    add(view.x0, view.y0, view.x1, view.y1);
+    for u in gs.units.get(&player_id).into_iter().flatten() {
+        let (cx, cy) = pos_chunk(u.x, u.y);
+        add(cx, cy, cx, cy);
+    }
+    for b in gs.buildings.iter().filter(|b| b.owner_id == player_id) {
+        let (cx, cy) = tile_chunk(b.tile_x, b.tile_y);
+        add(cx, cy, cx, cy);
+    }
+    chunks
+}
+
+/// Everything in `chunk`, for a client that just started receiving it.
+pub fn chunk_enter(gs: &GlobalState, (chunk_x, chunk_y): Chunk) -> GameMessage {
+    let units = gs
+        .units
+        .iter()
+        .flat_map(|(pid, us)| us.iter().map(move |u| (pid, u)))
+        .filter(|(_, u)| pos_chunk(u.x, u.y) == (chunk_x, chunk_y))
+        .map(|(pid, u)| u.to_dto(*pid))
+        .collect();
+    let buildings = gs
+        .buildings
+        .iter()
+        .filter(|b| tile_chunk(b.tile_x, b.tile_y) == (chunk_x, chunk_y))
+        .cloned()
+        .collect();
+    let sites = gs
+        .building_progress
+        .values()
+        .filter(|t| tile_chunk(t.tile_x, t.tile_y) == (chunk_x, chunk_y))
+        .map(|t| BuildSite {
+            tile_x: t.tile_x,
+            tile_y: t.tile_y,
+            kind: t.kind,
+            progress: t.progress.min(1.0),
+        })
+        .collect();
+    GameMessage::ChunkEnter {
+        chunk_x,
+        chunk_y,
+        units,
+        buildings,
+        sites,
+    }
+}
+
+/// Who an event goes to.
+pub enum Audience {
+    Everyone,
+    /// Only this player (their own resources, carry loads).
+    Player(i32),
+    /// Clients receiving any of these chunks, and `owner` wherever they are.
+    Near {
+        chunks: Vec<Chunk>,
+        owner: Option<i32>,
+    },
+}
+
+impl Audience {
+    pub fn includes(&self, player_id: i32, interest: &BTreeSet<Chunk>) -> bool {
+        match self {
+            Audience::Everyone => true,
+            Audience::Player(pid) => *pid == player_id,
+            Audience::Near { chunks, owner } => {
+                *owner == Some(player_id) || chunks.iter().any(|c| interest.contains(c))
+            }
+        }
+    }
+}
+
+/// Where `msg` happens. Events that name a unit by id are placed at the unit's chunk as of
+/// `unit_chunks`; an unknown unit only reaches its owner.
+pub fn audience(msg: &GameMessage, unit_chunks: &HashMap<u64, Chunk>) -> Audience {
+    let unit = |owner: i32, unit_id: u64| Audience::Near {
+        chunks: unit_chunks.get(&unit_id).copied().into_iter().collect(),
+        owner: Some(owner),
+    };
+    let tile = |tile_x: i32, tile_y: i32, owner: Option<i32>| Audience::Near {
+        chunks: vec![tile_chunk(tile_x, tile_y)],
+        owner,
+    };
+    match msg {
+        GameMessage::UnitMove {
+            player_id, unit_id, ..
+        } => unit(*player_id, *unit_id),
+        GameMessage::UnitSync {
+            player_id, unit_id, ..
+        } => unit(*player_id, *unit_id),
+        GameMessage::UnitHp {
+            owner_id, unit_id, ..
+        } => unit(*owner_id, *unit_id),
+        GameMessage::UnitDied { owner_id, unit_id } => unit(*owner_id, *unit_id),
+        GameMessage::UnitSpawned { unit } => Audience::Near {
+            chunks: vec![pos_chunk(unit.x, unit.y)],
+            owner: Some(unit.owner_id),
+        },
+        GameMessage::BuildProgress { tile_x, tile_y, .. } => tile(*tile_x, *tile_y, None),
+        GameMessage::BuildingSpawned { building } => {
+            tile(building.tile_x, building.tile_y, Some(building.owner_id))
+        }
+        GameMessage::BuildingHp { tile_x, tile_y, .. } => tile(*tile_x, *tile_y, None),
+        GameMessage::BuildingDestroyed { tile_x, tile_y } => tile(*tile_x, *tile_y, None),
+        GameMessage::TowerShot { x1, y1, x2, y2 } => Audience::Near {
+            chunks: vec![pos_chunk(*x1, *y1), pos_chunk(*x2, *y2)],
+            owner: None,
+        },
+        GameMessage::UnitCarry { owner_id, .. } => Audience::Player(*owner_id),
+        GameMessage::ResourceUpdate { player_id, .. } => Audience::Player(*player_id),
+        _ => Audience::Everyone,
+    }
+}
+
+/// Chunk of every unit in the world, keyed by unit id.
+pub fn unit_chunks(gs: &GlobalState) -> HashMap<u64, Chunk> {
+    gs.units
+        .values()
+        .flatten()
+        .map(|u| (u.id, pos_chunk(u.x, u.y)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::Balance;
+
+    #[test]
+    fn views_at_the_ends_of_the_map_stay_in_range() {
+        let gs = GlobalState::new(Balance::default(), 0);
+        let side = (MAX_VIEW + 2 * MARGIN) as usize;
+        let views = [
+            View::new(i32::MIN, i32::MIN, i32::MAX, i32::MAX),
+            View::new(i32::MAX, i32::MAX, i32::MAX, i32::MAX),
+            View::new(i32::MAX, i32::MIN, i32::MIN, i32::MAX),
+        ];
+        for view in views {
+            assert!(
+                view.x1 - view.x0 < MAX_VIEW && view.y1 - view.y0 < MAX_VIEW,
+                "{:?}",
+                view
+            );
+            let chunks = interest(&gs, 1, view);
+            assert!(
+                chunks.len() <= side * side,
+                "{:?}: {} chunks",
+                view,
+                chunks.len()
+            );
+        }
+        assert_eq!(
+            interest(&gs, 1, View::new(i32::MIN, 0, i32::MIN, 0)).len(),
+            9
+        );
+    }
+}
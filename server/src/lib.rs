@@ -0,0 +1,873 @@
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use futures_util::{StreamExt, SinkExt};
+use tokio_tungstenite::accept_async_with_config;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use std::env;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Serialize, Deserialize};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tracing::{debug, field, info, info_span, warn, Instrument};
+
+mod admin;
+mod http;
+mod interest;
+mod limits;
+mod metrics;
+mod pathfinding;
+mod persist;
+pub mod replay;
+mod sim;
+mod step;
+
+use protocol::terrain;
+use protocol::wire::{self, Encoding};
+use protocol::{
+    feature, Balance, BuildKind, BuildingDTO, ClientCommand, GameMessage, PlayerInfo, RejectReason, ResourceKind, Resources,
+    UnitDTO, UnitKind, TOWN_CENTER_KIND,
+};
+use limits::{Limiter, Verdict};
+use sim::{Joined, SimCommand};
+
+pub use admin::DEFAULT_ADDR as DEFAULT_ADMIN_ADDR;
+pub use limits::{Limits, Rate};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct UnitState {
+    // Server-assigned and never reused; clients address units by this, not by Vec position.
+    id: u64,
+    x: f32,
+    y: f32,
+    hp: f32,
+    kind: u8,
+    carry_wood: f32,
+    carry_stone: f32,
+    carry_gold: f32,
+    carry_food: f32,
+    // Remaining waypoints (next is last()); advanced by the tick loop.
+    #[serde(skip)]
+    path: Vec<(f32, f32)>,
+}
+
+impl UnitState {
+    fn new(id: u64, x: f32, y: f32, kind: u8, hp: f32) -> Self {
+        UnitState { id, x, y, hp, kind, carry_wood: 0.0, carry_stone: 0.0, carry_gold: 0.0, carry_food: 0.0, path: Vec::new() }
+    }
+
+    fn to_dto(&self, owner_id: i32) -> UnitDTO {
+        UnitDTO {
+            owner_id,
+            unit_id: self.id,
+            x: self.x,
+            y: self.y,
+            kind: self.kind,
+            hp: self.hp,
+            carry_wood: self.carry_wood,
+            carry_stone: self.carry_stone,
+            carry_gold: self.carry_gold,
+            carry_food: self.carry_food,
+        }
+    }
+}
+
+fn command_ack(seq: u32, result: Result<(), RejectReason>) -> GameMessage {
+    GameMessage::CommandAck { seq, ok: result.is_ok(), reason: result.err() }
+}
+
+// Oldest protocol accepted in Hello; older clients get VersionRejected
+const MIN_CLIENT_VERSION_DEFAULT: u32 = 29;
+
+// Identifies this deployment in ServerHello: the crate version, plus the commit on Railway.
+fn build_id() -> String {
+    let version = env!("CARGO_PKG_VERSION");
+    match env::var("RAILWAY_GIT_COMMIT_SHA") {
+        Ok(sha) => format!("{}+{}", version, &sha[..sha.len().min(7)]),
+        Err(_) => version.to_string(),
+    }
+}
+
+// Suggested to clients in ServerShutdown: about how long a redeploy takes to come back up
+const RECONNECT_AFTER_MS: u32 = 3000;
+
+// How long shutdown waits for connections to write their last frames
+const DRAIN_SECS: u64 = 2;
+
+const WARRIOR_RANGE: f32 = 48.0;
+const POP_FROM_HOUSE: i32 = 1;
+const TILE_SIZE: f32 = 16.0;
+const TICK_MS: u64 = 200;
+// Matches the client's predicted walking speed (pixels per second)
+const UNIT_SPEED: f32 = 60.0;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[allow(dead_code)]
+struct ResourceNode {
+    kind: u8,
+    remaining: f32,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct BuildTask {
+    owner_id: i32,
+    kind: u8,
+    tile_x: i32,
+    tile_y: i32,
+    progress: f32,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct TrainTask {
+    owner_id: i32,
+    kind: u8,
+    progress: f32,
+    chunk_x: i32,
+    chunk_y: i32,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[allow(dead_code)]
+struct GatherTask {
+    kind: u8,
+    target_x: i32,
+    target_y: i32,
+    force_deposit: bool,
+}
+
+// Ordered maps throughout: the tick iterates them, and replays need the same order every run.
+struct GlobalState {
+    next_id: i32,
+    next_unit_id: u64,
+    players: BTreeMap<i32, PlayerInfo>,
+    units: BTreeMap<i32, Vec<UnitState>>,
+    // Memory mode persistence (Token -> PlayerID)
+    tokens: BTreeMap<String, i32>, 
+    resources: BTreeMap<i32, Resources>,
+    pop_cap: BTreeMap<i32, i32>,
+    building_progress: BTreeMap<(i32, i32), BuildTask>, // (tile_x, tile_y) -> task
+    training_tasks: Vec<TrainTask>,
+    gather_tasks: BTreeMap<(i32, u64), GatherTask>, // (owner_id, unit_id)
+    buildings: Vec<BuildingDTO>,
+    resource_nodes: BTreeMap<(i32, i32), ResourceNode>, // (tile_x, tile_y)
+    // Tokens refused at Join (set from the admin tool)
+    banned_tokens: BTreeSet<String>,
+    balance: Balance,
+    // The only randomness in the simulation (building ids). Seeded per run and recorded in the
+    // replay log; not part of snapshots.
+    rng: StdRng,
+}
+
+impl GlobalState {
+    fn new(balance: Balance, seed: u64) -> Self {
+        GlobalState {
+            next_id: 1,
+            next_unit_id: 1,
+            players: BTreeMap::new(),
+            units: BTreeMap::new(),
+            tokens: BTreeMap::new(),
+            resources: BTreeMap::new(),
+            pop_cap: BTreeMap::new(),
+            building_progress: BTreeMap::new(),
+            training_tasks: Vec::new(),
+            gather_tasks: BTreeMap::new(),
+            buildings: Vec::new(),
+            resource_nodes: BTreeMap::new(),
+            banned_tokens: BTreeSet::new(),
+            balance,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn assign_next_position(n: i32) -> (i32, i32) {
+        if n == 0 { return (0, 0); }
+        
+        // Tweak: Reduce distance between players
+        // Previously: 1 chunk per player.
+        // New: Pack 4 players into 1 chunk (2x2 grid within chunk?) 
+        // OR simpler: Just spiral but fill every chunk (which we do).
+        
+        // If the user feels map is "WAY TOO BIG", maybe our Chunks (32x32) are too huge?
+        // 32 tiles * 16px = 512px. That's barely a screen width.
+        // The issue might be that they spawn 1 chunk apart.
+        
+        // Let's keep the spiral but maybe we don't skip chunks?
+        // The current algorithm spirals 0,0 -> 1,0 -> 1,1 -> 0,1 ...
+        // This IS filling every chunk.
+        
+        // Maybe the issue is just visual "void".
+        // Let's try to put players closer by using "sub-chunk" addressing?
+        // No, let's just stick to 1 player per chunk for now but acknowledge
+        // that 512px is not "far".
+        
+        // Wait, the spiral logic might be spreading them out too much if I implemented it wrong.
+        // Let's check the spiral logic.
+        // It looks standard Ulam spiral.
+        
+        let mut x = 0;
+        let mut y = 0;
+        let mut d = 1;
+        let mut m = 1;
+        let mut count = 0;
+        loop {
+            for _ in 0..m {
+                x += d;
+                count += 1;
+                if count == n { return (x, y); }
+            }
+            for _ in 0..m {
+                y += d;
+                count += 1;
+                if count == n { return (x, y); }
+            }
+            d = -d;
+            m += 1;
+        }
+    }
+
+    fn alloc_unit_id(&mut self) -> u64 {
+        let id = self.next_unit_id;
+        self.next_unit_id += 1;
+        id
+    }
+
+    fn alloc_building_id(&mut self) -> i32 {
+        self.rng.gen_range(1..=i32::MAX)
+    }
+
+    fn spawn_units(&mut self, cx: i32, cy: i32) -> Vec<UnitState> {
+        let chunk_size = 32.0;
+        let tile_size = 16.0;
+        
+        // Town Center is at tile (cx * chunk_size + mid, cy * chunk_size + mid)
+        // where mid = chunk_size / 2 = 16
+        // Its TOP-LEFT in world coords is that tile * tile_size
+        let mid = chunk_size / 2.0;
+        let tc_tile_x = cx as f32 * chunk_size + mid;
+        let tc_tile_y = cy as f32 * chunk_size + mid;
+        
+        // Town Center occupies 1 tile (16x16 px) starting from its TOP-LEFT
+        // Spawn units below and to the right of the Town Center
+        let tc_world_x = tc_tile_x * tile_size;
+        let tc_world_y = tc_tile_y * tile_size;
+        
+        // Unit positions: offset from Town Center's top-left
+        // Place them 2 tiles below the TC, spread horizontally
+        vec![
+            UnitState::new(self.alloc_unit_id(), tc_world_x + tile_size * 0.5, tc_world_y + tile_size * 2.0, UnitKind::Worker.to_u8(), self.balance.hp.worker),
+            UnitState::new(self.alloc_unit_id(), tc_world_x + tile_size * 1.5, tc_world_y + tile_size * 2.0, UnitKind::Worker.to_u8(), self.balance.hp.worker),
+        ]
+    }
+
+    fn find_building(&self, owner: i32, id: i32) -> Option<BuildingDTO> {
+        self.buildings.iter().find(|b| b.owner_id == owner && b.id == id).cloned()
+    }
+
+    fn unit(&self, owner: i32, unit_id: u64) -> Option<&UnitState> {
+        self.units.get(&owner).and_then(|us| us.iter().find(|u| u.id == unit_id))
+    }
+
+    fn unit_mut(&mut self, owner: i32, unit_id: u64) -> Option<&mut UnitState> {
+        self.units.get_mut(&owner).and_then(|us| us.iter_mut().find(|u| u.id == unit_id))
+    }
+
+    fn resource_update(&self, player_id: i32) -> GameMessage {
+        GameMessage::ResourceUpdate {
+            player_id,
+            resources: *self.resources.get(&player_id).unwrap_or(&default_resources()),
+            pop_cap: *self.pop_cap.get(&player_id).unwrap_or(&default_pop_cap()),
+            pop_used: self.units.get(&player_id).map(|u| u.len() as i32).unwrap_or(0),
+        }
+    }
+
+    fn has_unit(&self, owner: i32, unit_id: u64) -> bool {
+        self.unit(owner, unit_id).is_some()
+    }
+
+    /// Why a command naming `unit_id` was refused: it belongs to someone else, or doesn't exist.
+    fn unit_reject_reason(&self, unit_id: u64) -> RejectReason {
+        if self.units.values().flatten().any(|u| u.id == unit_id) {
+            RejectReason::NotOwner
+        } else {
+            RejectReason::UnknownUnit
+        }
+    }
+
+    /// Removes a unit and any orders keyed by its id. Returns the removed unit.
+    fn remove_unit(&mut self, owner: i32, unit_id: u64) -> Option<UnitState> {
+        let units = self.units.get_mut(&owner)?;
+        let idx = units.iter().position(|u| u.id == unit_id)?;
+        self.gather_tasks.remove(&(owner, unit_id));
+        Some(units.remove(idx))
+    }
+
+    fn is_tile_blocked(&self, tx: i32, ty: i32) -> bool {
+        // Nothing goes on forest, rock or gold
+        if !terrain::tile_at(tx, ty).is_walkable() {
+            return true;
+        }
+        // Block if building already present
+        if self.buildings.iter().any(|b| b.tile_x == tx && b.tile_y == ty) {
+            return true;
+        }
+        // Block if a unit is standing on tile
+        for units in self.units.values() {
+            for u in units {
+                let utx = (u.x / TILE_SIZE).floor() as i32;
+                let uty = (u.y / TILE_SIZE).floor() as i32;
+                if utx == tx && uty == ty {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn is_tile_walkable(&self, tx: i32, ty: i32) -> bool {
+        if !terrain::tile_at(tx, ty).is_walkable() {
+            return false;
+        }
+        // Each building occupies exactly 1 tile
+        !self.buildings.iter().any(|b| b.tile_x == tx && b.tile_y == ty)
+    }
+
+    /// Whether `kind` can be gathered at a tile: wood, stone and gold from the matching terrain,
+    /// food from a farm.
+    fn is_gather_target(&self, kind: ResourceKind, tx: i32, ty: i32) -> bool {
+        if kind == ResourceKind::Food {
+            let farm = BuildKind::Farm.to_kind_id();
+            return self.buildings.iter().any(|b| b.tile_x == tx && b.tile_y == ty && b.kind == farm);
+        }
+        terrain::tile_at(tx, ty).gather_kind() == Some(kind)
+    }
+
+    /// Closest walkable neighbour of a tile (cardinal first, then diagonal), used as a standing spot
+    /// for gathering next to resources and buildings.
+    fn adjacent_walkable(&self, tx: i32, ty: i32, from_x: f32, from_y: f32) -> Option<(i32, i32)> {
+        let cardinal = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+        let diagonal = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+        for dirs in [&cardinal, &diagonal] {
+            let mut best: Option<(f32, (i32, i32))> = None;
+            for (dx, dy) in dirs.iter() {
+                let (nx, ny) = (tx + dx, ty + dy);
+                if !self.is_tile_walkable(nx, ny) { continue; }
+                let (wx, wy) = pathfinding::tile_center(nx, ny);
+                let d2 = (wx - from_x).powi(2) + (wy - from_y).powi(2);
+                if best.is_none_or(|(bd2, _)| d2 < bd2) {
+                    best = Some((d2, (nx, ny)));
+                }
+            }
+            if let Some((_, tile)) = best {
+                return Some(tile);
+            }
+        }
+        None
+    }
+
+    /// Plans a server-side path for one unit towards `goal`. Returns false if the unit does not
+    /// exist or no path was found (the unit keeps its current orders in that case).
+    fn path_unit_to(&mut self, owner: i32, unit_id: u64, goal: (i32, i32)) -> bool {
+        let Some(start) = self.unit(owner, unit_id).map(|u| (u.x, u.y)) else {
+            return false;
+        };
+        let path = pathfinding::find_path(start, goal, |x, y| self.is_tile_walkable(x, y));
+        if path.is_empty() {
+            return false;
+        }
+        if let Some(u) = self.unit_mut(owner, unit_id) {
+            u.path = path;
+        }
+        true
+    }
+
+    /// Advances every unit along its path by `dt` seconds and returns the positions that changed.
+    fn advance_units(&mut self, dt: f32) -> Vec<(i32, u64, f32, f32)> {
+        let step = UNIT_SPEED * dt;
+        let mut moved = Vec::new();
+        for (owner, units) in self.units.iter_mut() {
+            for u in units.iter_mut() {
+                if u.path.is_empty() { continue; }
+                let mut budget = step;
+                while budget > 0.0 {
+                    let Some(&(tx, ty)) = u.path.last() else { break };
+                    let dx = tx - u.x;
+                    let dy = ty - u.y;
+                    let dist = (dx * dx + dy * dy).sqrt();
+                    if dist <= budget {
+                        u.x = tx;
+                        u.y = ty;
+                        u.path.pop();
+                        budget -= dist;
+                    } else {
+                        u.x += dx / dist * budget;
+                        u.y += dy / dist * budget;
+                        budget = 0.0;
+                    }
+                }
+                moved.push((*owner, u.id, u.x, u.y));
+            }
+        }
+        moved
+    }
+}
+
+fn dropoff_near(gs: &GlobalState, owner: i32, ux: f32, uy: f32, res_kind: ResourceKind) -> bool {
+    let allowed = [TOWN_CENTER_KIND, res_kind.dropoff().to_kind_id()];
+    let mut ok = false;
+    let radius = TILE_SIZE * 1.2; // ~1 tile reach to avoid instant deposits
+    let r2 = radius * radius;
+    for b in &gs.buildings {
+        if b.owner_id != owner { continue; }
+        if !allowed.contains(&b.kind) { continue; }
+        let bx = b.tile_x as f32 * TILE_SIZE + TILE_SIZE / 2.0;
+        let by = b.tile_y as f32 * TILE_SIZE + TILE_SIZE / 2.0;
+        let dx = ux - bx;
+        let dy = uy - by;
+        if (dx*dx + dy*dy) <= r2 {
+            ok = true;
+            break;
+        }
+    }
+    ok
+}
+
+const MIN_START_RES: Resources = Resources { wood: 200.0, stone: 160.0, gold: 60.0, food: 300.0 };
+
+fn default_resources() -> Resources {
+    MIN_START_RES
+}
+
+fn default_pop_cap() -> i32 {
+    5
+}
+
+// Costs, HP and rates come from the JSON file named by BALANCE_FILE (fields it omits keep their
+// defaults); without it the built-in table is used.
+fn load_balance() -> Result<Balance, String> {
+    let Ok(path) = env::var("BALANCE_FILE") else {
+        info!("BALANCE_FILE not set; using default balance");
+        return Ok(Balance::default());
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let balance: Balance = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    balance.validate().map_err(|e| format!("{}: {}", path, e))?;
+    info!(path, "Loaded balance");
+    Ok(balance)
+}
+
+// Rate and size limits from the JSON file named by LIMITS_FILE, like BALANCE_FILE.
+fn load_limits() -> Result<Limits, String> {
+    let Ok(path) = env::var("LIMITS_FILE") else {
+        return Ok(Limits::default());
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let limits: Limits = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    limits.validate().map_err(|e| format!("{}: {}", path, e))?;
+    info!(path, "Loaded limits");
+    Ok(limits)
+}
+
+// Everything `serve` needs from the environment, gathered up front so tests can build one
+// directly instead of setting process-wide env vars.
+pub struct Config {
+    pub balance: Balance,
+    pub snapshot_path: PathBuf,
+    // Command log for offline replays, from REPLAY_FILE; None (the default) disables it
+    pub replay_path: Option<PathBuf>,
+    // Loopback operator socket for the `admin` binary; None disables it
+    pub admin_addr: Option<String>,
+    pub limits: Limits,
+    // Protocol of the web client deployed at startup; clients older than this that opted in are
+    // told to reload. `admin latest-client` raises it at runtime.
+    pub latest_client: u32,
+}
+
+impl Config {
+    // BALANCE_FILE, SNAPSHOT_FILE, REPLAY_FILE, ADMIN_ADDR, LIMITS_FILE and LATEST_CLIENT_VERSION,
+    // with their defaults. The latest client defaults to the one built from this tree.
+    pub fn from_env() -> Result<Config, String> {
+        Ok(Config {
+            limits: load_limits()?,
+            balance: load_balance()?,
+            snapshot_path: persist::snapshot_path(),
+            replay_path: replay::replay_path(),
+            admin_addr: Some(env::var("ADMIN_ADDR").unwrap_or_else(|_| DEFAULT_ADMIN_ADDR.to_string())),
+            latest_client: match env::var("LATEST_CLIENT_VERSION") {
+                Ok(v) => v.parse().map_err(|_| format!("LATEST_CLIENT_VERSION: not a version: {}", v))?,
+                Err(_) => protocol::PROTOCOL_VERSION,
+            },
+        })
+    }
+}
+
+/// Installs the log subscriber. `LOG` (or `RUST_LOG`) is the filter, e.g. `debug` or
+/// `info,chat_server=debug`; the default is `info`. `LOG_FORMAT=json` writes one JSON object per
+/// line, which is the default on Railway; `LOG_FORMAT=text` forces readable lines.
+pub fn init_logging() {
+    use tracing_subscriber::EnvFilter;
+
+    let filter = EnvFilter::try_from_env("LOG")
+        .or_else(|_| EnvFilter::try_from_default_env())
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+    let json = match env::var("LOG_FORMAT") {
+        Ok(format) => format == "json",
+        Err(_) => env::var("RAILWAY_ENVIRONMENT").is_ok(),
+    };
+    let logs = tracing_subscriber::fmt().with_env_filter(filter);
+    if json {
+        logs.json().with_current_span(true).with_span_list(false).init();
+    } else {
+        logs.init();
+    }
+}
+
+/// Runs the game server on `listener` until `shutdown` resolves, then saves the world. Errors
+/// only if the existing snapshot can't be loaded.
+pub async fn serve(listener: TcpListener, config: Config, shutdown: impl Future<Output = ()>) -> Result<(), String> {
+    // The whole event stream is only read by replays; clients are sent their share directly
+    let (tx, _) = broadcast::channel(1);
+
+    let snapshot_path = config.snapshot_path;
+    let seed = rand::random();
+    let mut state = GlobalState::new(config.balance, seed);
+    match persist::load(&mut state, &snapshot_path) {
+        Ok(true) => info!(path = %snapshot_path.display(), players = state.players.len(), "Restored world"),
+        Ok(false) => info!(path = %snapshot_path.display(), "No snapshot; starting a fresh world"),
+        Err(e) => return Err(format!("Failed to load snapshot: {}", e)),
+    }
+
+    let recorder = config.replay_path.and_then(|path| match replay::Recorder::start(&path, seed, &state) {
+        Ok(recorder) => {
+            info!(path = %path.display(), seed, "Recording replay");
+            Some(recorder)
+        }
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "Replay log disabled");
+            None
+        }
+    });
+
+    let started = std::time::Instant::now();
+    let limits = Arc::new(config.limits);
+
+    // The simulation task owns all game state; connections only enqueue commands
+    let (sim_tx, sim_rx) = mpsc::channel(sim::COMMAND_QUEUE);
+    let snapshots = Some(persist::Writer::spawn(snapshot_path));
+    let sim_task = tokio::spawn(sim::Simulation::new(state, tx, snapshots, recorder, config.latest_client).run(sim_rx));
+
+    if let Ok(addr) = listener.local_addr() {
+        info!(%addr, "Listening");
+    }
+
+    // Operator socket for the `admin` binary; loopback only, never exposed like PORT
+    if let Some(admin_addr) = config.admin_addr {
+        match TcpListener::bind(&admin_addr).await {
+            Ok(admin_listener) => {
+                info!(addr = %admin_addr, "Admin socket open");
+                tokio::spawn(admin::serve(admin_listener, sim_tx.clone()));
+            }
+            Err(e) => warn!(addr = %admin_addr, error = %e, "Admin socket disabled"),
+        }
+    }
+
+    let mut connections = tokio::task::JoinSet::new();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    connections.spawn(handle_connection(stream, sim_tx.clone(), limits.clone(), started));
+                }
+                Err(_) => break,
+            },
+            // Reaps finished connections so the set doesn't grow
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = &mut shutdown => break,
+        }
+    }
+    // No new connections from here on
+    drop(listener);
+
+    // Clients are told first, then the simulation writes a final snapshot and closes their sockets
+    info!("Shutting down; saving world");
+    let notice = GameMessage::ServerShutdown { reason: "Server restarting".to_string(), reconnect_after_ms: RECONNECT_AFTER_MS };
+    let (done_tx, done_rx) = oneshot::channel();
+    if sim_tx.send(SimCommand::Shutdown { notice, done: done_tx }).await.is_ok() {
+        let _ = done_rx.await;
+    }
+    let _ = sim_task.await;
+
+    let drained = timeout(Duration::from_secs(DRAIN_SECS), async {
+        while connections.join_next().await.is_some() {}
+    }).await;
+    if drained.is_err() {
+        warn!(open = connections.len(), "Closing connections that didn't finish in time");
+    }
+    Ok(())
+}
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+// Everything logged for a connection carries its id and address, and its player once known.
+async fn handle_connection(stream: TcpStream, sim: mpsc::Sender<SimCommand>, limits: Arc<Limits>, started: std::time::Instant) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let conn_id = NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed);
+    let span = info_span!("conn", conn_id, %peer, player_id = field::Empty, token = field::Empty);
+    connection(stream, conn_id, sim, limits, started).instrument(span).await
+}
+
+async fn connection(stream: TcpStream, conn_id: u64, sim: mpsc::Sender<SimCommand>, limits: Arc<Limits>, started: std::time::Instant) {
+    debug!("Incoming socket");
+
+    // Health checks and monitoring speak plain HTTP on this port; only upgrades join the game
+    match timeout(Duration::from_secs(2), http::peek_request(&stream)).await {
+        Ok(Ok(Some(http::Request::Upgrade))) => {}
+        Ok(Ok(Some(http::Request::Plain { method, path, head_len }))) => {
+            debug!(%method, %path, "HTTP request");
+            http::respond(stream, &method, &path, head_len, &sim, started).await;
+            return;
+        }
+        Ok(Ok(None)) => {
+            debug!("Not an HTTP request, closing");
+            return;
+        }
+        Ok(Err(e)) => {
+            debug!(error = %e, "Error reading request");
+            return;
+        }
+        Err(_) => {
+            debug!("Request timeout, closing");
+            return;
+        }
+    }
+
+    // Short timeout so a stalled handshake doesn't hold the task
+    let ws_config = WebSocketConfig {
+        max_message_size: Some(limits.max_frame_bytes),
+        max_frame_size: Some(limits.max_frame_bytes),
+        ..Default::default()
+    };
+    let ws_stream = match timeout(Duration::from_secs(2), accept_async_with_config(stream, Some(ws_config))).await {
+        Ok(Ok(ws)) => ws,
+        Ok(Err(e)) => {
+            debug!(error = %e, "WebSocket handshake failed");
+            return;
+        }
+        Err(_) => {
+            debug!("WebSocket handshake timeout, closing");
+            return;
+        }
+    };
+
+    let (mut write, mut read) = ws_stream.split();
+
+    // --- HANDSHAKE ---
+    let client_token: Option<String>;
+    let client_version: u32;
+    let client_encoding: Encoding;
+    let update_notice: bool;
+    let server_hello: GameMessage;
+
+    if let Some(Ok(msg)) = read.next().await {
+        if let Ok(text) = msg.to_text() {
+            match serde_json::from_str(text) {
+                Ok(GameMessage::Hello { version, features, token, encoding }) => {
+                    // CHECK VERSION (memory-only)
+                    let min_version = MIN_CLIENT_VERSION_DEFAULT;
+
+                    if version < min_version {
+                        info!(version, min_version, "Rejecting outdated client");
+                        let _ = write.send(Message::Text(serde_json::to_string(&GameMessage::VersionRejected {
+                            version, min_version, build: build_id(),
+                        }).unwrap())).await;
+                        return;
+                    }
+
+                    // Only features both sides know about are in play
+                    let features: Vec<String> = features.into_iter().filter(|f| feature::ALL.contains(&f.as_str())).collect();
+                    let has = |name: &str| features.iter().any(|f| f == name);
+                    client_token = token;
+                    client_version = version;
+                    client_encoding = if has(feature::MSGPACK) { encoding } else { Encoding::Json };
+                    update_notice = has(feature::UPDATE_NOTICE);
+                    info!(version, encoding = ?client_encoding, ?features, "Accepted handshake");
+                    server_hello = GameMessage::ServerHello {
+                        version: protocol::PROTOCOL_VERSION,
+                        min_version,
+                        features,
+                        build: build_id(),
+                    };
+                }
+                Ok(GameMessage::Join { version, .. }) => {
+                    // Clients from before Hello show their update screen on exactly this wording
+                    info!(version, "Rejecting legacy Join");
+                    let _ = write.send(Message::Text(serde_json::to_string(&GameMessage::Error {
+                        message: format!("Client version {} is too old. Minimum required: {}", version, MIN_CLIENT_VERSION_DEFAULT)
+                    }).unwrap())).await;
+                    return;
+                }
+                _ => {
+                    warn!(text, "Invalid handshake");
+                    let _ = write.send(Message::Text(serde_json::to_string(&GameMessage::Error {
+                        message: "Invalid handshake: expected Hello message".to_string()
+                    }).unwrap())).await;
+                    return;
+                }
+            }
+        } else {
+            return; 
+        }
+    } else {
+        return; 
+    }
+
+    // Authenticate or Register inside the simulation
+    let (outbox, mut outbox_rx) = mpsc::channel::<Message>(sim::OUTBOX);
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let join = SimCommand::Join {
+        conn_id,
+        token: client_token,
+        version: client_version,
+        encoding: client_encoding,
+        update_notice,
+        outbox,
+        reply: reply_tx,
+    };
+    if sim.send(join).await.is_err() {
+        return;
+    }
+    let joined = match reply_rx.await {
+        Ok(Ok(joined)) => joined,
+        Ok(Err(reason)) => {
+            info!(%reason, "Refused join");
+            let kicked = GameMessage::Kicked { reason, reconnect: false };
+            let _ = write.send(Message::Text(serde_json::to_string(&kicked).unwrap())).await;
+            return;
+        }
+        Err(_) => return,
+    };
+    let Joined { player_id, token, welcome } = joined;
+    let span = tracing::Span::current();
+    span.record("player_id", player_id);
+    // Enough to tell sessions apart in the logs without making the token usable
+    span.record("token", &token[..token.len().min(8)]);
+
+    let server_hello = match client_encoding {
+        Encoding::Json => Message::Text(serde_json::to_string(&server_hello).unwrap()),
+        Encoding::Binary => Message::Binary(wire::to_binary(&server_hello)),
+    };
+    if let Err(e) = write.send(server_hello).await {
+        debug!(error = %e, "Failed to send server hello");
+        let _ = sim.send(SimCommand::Leave { player_id, conn_id }).await;
+        return;
+    }
+    if let Err(e) = write.send(welcome).await {
+        debug!(error = %e, "Failed to send welcome");
+        let _ = sim.send(SimCommand::Leave { player_id, conn_id }).await;
+        return;
+    }
+
+    // Heartbeat
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(10)); // Reduced to 10s for better keepalive
+
+    // Set once this side has started the close handshake
+    let closing = Arc::new(AtomicBool::new(false));
+    let send_closing = closing.clone();
+    let mut send_task = tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                msg = outbox_rx.recv() => {
+                    // Closed when the simulation drops this connection (kicked, banned or
+                    // shutting down): say goodbye properly rather than just dropping the socket
+                    let Some(msg) = msg else {
+                        send_closing.store(true, Ordering::Relaxed);
+                        let _ = write.close().await;
+                        break;
+                    };
+                    if write.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+                _ = interval.tick() => {
+                    // Send Ping
+                    if write.send(Message::Ping(vec![])).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }.in_current_span());
+
+    let recv_sim = sim.clone();
+    // Ends true when it asked the simulation to drop this connection
+    let mut recv_task = tokio::spawn(async move {
+        debug!("Receiving commands");
+        let mut limiter = Limiter::new(limits);
+        let disconnect = |reason: &str| SimCommand::Disconnect { player_id, conn_id, reason: reason.to_string() };
+        while let Some(frame) = read.next().await {
+            let msg = match frame {
+                Ok(msg) => msg,
+                Err(WsError::Capacity(e)) => {
+                    warn!(error = %e, "Message too large");
+                    let _ = recv_sim.send(disconnect("Disconnected: message too large")).await;
+                    return true;
+                }
+                Err(_) => break,
+            };
+            // Still read while closing, so the peer's reply completes the handshake, but the
+            // simulation is done with this connection
+            if msg.is_ping() || msg.is_pong() || msg.is_close() || closing.load(Ordering::Relaxed) {
+                continue;
+            }
+            let command = msg.to_text().ok().and_then(|text| serde_json::from_str::<ClientCommand>(text).ok());
+            let verdict = match &command {
+                Some(ClientCommand { msg, .. }) => limiter.command(msg.name()),
+                None => limiter.strike(),
+            };
+            let sent = match (verdict, command) {
+                // Waits for queue space instead of dropping the command
+                (Verdict::Allow, Some(ClientCommand { seq, msg })) => recv_sim.send(SimCommand::Command { player_id, seq, msg }).await,
+                (Verdict::Throttle, Some(ClientCommand { seq, msg })) => {
+                    debug!(command = msg.name(), "Rate limited");
+                    recv_sim.send(SimCommand::Throttled { player_id, seq, command: msg.name() }).await
+                }
+                (Verdict::Disconnect, _) => {
+                    warn!("Too many refused messages; disconnecting");
+                    let _ = recv_sim.send(disconnect("Disconnected: too many messages")).await;
+                    return true;
+                }
+                // Not a command; costs a strike and is otherwise ignored
+                (_, None) => Ok(()),
+            };
+            if sent.is_err() {
+                break;
+            }
+        }
+        false
+    }.in_current_span());
+
+    tokio::select! {
+        _ = (&mut send_task) => {
+            // Wait for the peer to answer a close: dropping the socket with its reply unread
+            // would reset the connection under the close frame
+            let _ = timeout(Duration::from_secs(1), &mut recv_task).await;
+            recv_task.abort();
+        }
+        kicked = (&mut recv_task) => {
+            // Let the reason reach the client before the socket goes
+            if matches!(kicked, Ok(true)) {
+                let _ = timeout(Duration::from_secs(1), &mut send_task).await;
+            }
+            send_task.abort();
+        }
+    };
+
+    // Cleanup (keep player state in memory so positions/resources persist across reconnects)
+    let _ = sim.send(SimCommand::Leave { player_id, conn_id }).await;
+    info!("Player disconnected");
+}
@@ -0,0 +1,165 @@
+//! Per-connection abuse limits. Every command type has a token bucket; a command that finds its
+//! bucket empty is refused (`RejectReason::RateLimited`) instead of reaching the simulation.
+//! Each refusal, and each frame that isn't a command, costs a strike from another bucket that
+//! slowly refills: a client that keeps going until that one runs dry is disconnected.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+/// A steady `per_sec` rate, with up to `burst` at once.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_sec: f64,
+    pub burst: f64,
+}
+
+impl Rate {
+    const fn new(per_sec: f64, burst: f64) -> Rate {
+        Rate { per_sec, burst }
+    }
+}
+
+/// Loaded from the JSON file named by LIMITS_FILE; fields it omits keep these defaults.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Limits {
+    /// Largest WebSocket message accepted; bigger ones close the connection.
+    pub max_frame_bytes: usize,
+    /// Per command type (`GameMessage` variant name).
+    pub commands: BTreeMap<String, Rate>,
+    /// Any command type not listed in `commands`.
+    pub default_rate: Rate,
+    /// Refused or unreadable messages tolerated before disconnecting.
+    pub strikes: Rate,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        // Sized for the browser client at its busiest: moving a large group sends one MoveUnit
+        // per unit, and walls send a Build per tile.
+        let commands = [
+            ("MoveUnit", Rate::new(30.0, 100.0)),
+            ("AssignGather", Rate::new(10.0, 30.0)),
+            ("Build", Rate::new(10.0, 30.0)),
+            ("SpawnUnit", Rate::new(5.0, 20.0)),
+            ("TrainUnit", Rate::new(5.0, 20.0)),
+            ("SetView", Rate::new(10.0, 20.0)),
+            ("RequestResync", Rate::new(1.0, 3.0)),
+        ];
+        Limits {
+            max_frame_bytes: 16 * 1024,
+            commands: commands
+                .into_iter()
+                .map(|(name, rate)| (name.to_string(), rate))
+                .collect(),
+            default_rate: Rate::new(10.0, 30.0),
+            strikes: Rate::new(2.0, 100.0),
+        }
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), String> {
+        let rates = self
+            .commands
+            .iter()
+            .map(|(name, rate)| (name.as_str(), rate))
+            .chain([
+                ("default_rate", &self.default_rate),
+                ("strikes", &self.strikes),
+            ]);
+        for (name, rate) in rates {
+            if !(rate.per_sec >= 0.0 && rate.burst >= 1.0) {
+                return Err(format!("{}: per_sec must be >= 0 and burst >= 1", name));
+            }
+        }
+        if self.max_frame_bytes < 1024 {
+            return Err("max_frame_bytes must be at least 1024".to_string());
+        }
+        Ok(())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn full(rate: Rate, now: Instant) -> Bucket {
+        Bucket {
+            tokens: rate.burst,
+            last: now,
+        }
+    }
+
+    fn take(&mut self, rate: Rate, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_sec).min(rate.burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub enum Verdict {
+    Allow,
+    /// Refuse this one message.
+    Throttle,
+    /// Sustained abuse: drop the connection.
+    Disconnect,
+}
+
+/// One connection's buckets.
+pub struct Limiter {
+    limits: Arc<Limits>,
+    buckets: BTreeMap<&'static str, Bucket>,
+    strikes: Bucket,
+}
+
+impl Limiter {
+    pub fn new(limits: Arc<Limits>) -> Limiter {
+        let strikes = Bucket::full(limits.strikes, Instant::now());
+        Limiter {
+            limits,
+            buckets: BTreeMap::new(),
+            strikes,
+        }
+    }
+
+    /// A command of type `name` arrived.
+    pub fn command(&mut self, name: &'static str) -> Verdict {
+        let now = Instant::now();
+        let rate = self
+            .limits
+            .commands
+            .get(name)
+            .copied()
+            .unwrap_or(self.limits.default_rate);
+        let bucket = self
+            .buckets
+            .entry(name)
+            .or_insert_with(|| Bucket::full(rate, now));
+        if bucket.take(rate, now) {
+            Verdict::Allow
+        } else {
+            self.strike()
+        }
+    }
+
+    /// Something the client shouldn't have sent: refused, or not a command at all.
+    pub fn strike(&mut self) -> Verdict {
+        if self.strikes.take(self.limits.strikes, Instant::now()) {
+            Verdict::Throttle
+        } else {
+            Verdict::Disconnect
+        }
+    }
+}
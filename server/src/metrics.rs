@@ -0,0 +1,207 @@
+//! Server metrics for `GET /metrics`, in the Prometheus text format. The counters belong to the
+//! simulation task like the rest of its state, so they are plain cells rather than atomics; a
+//! scrape asks the simulation to render them (`SimCommand::Metrics`).
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Upper bounds of the tick duration buckets, in seconds. A tick has TICK_MS (0.2s) to run.
+const TICK_BUCKETS: [f64; 9] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.2];
+
+#[derive(Default)]
+struct Histogram {
+    // Cumulative count per bucket in TICK_BUCKETS
+    buckets: [u64; TICK_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        for (bound, n) in TICK_BUCKETS.iter().zip(&mut self.buckets) {
+            if secs <= *bound {
+                *n += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    tick_seconds: RefCell<Histogram>,
+    ticks_skipped: Cell<u64>,
+    // By message type
+    messages_in: RefCell<BTreeMap<&'static str, u64>>,
+    messages_out: RefCell<BTreeMap<&'static str, u64>>,
+    messages_throttled: RefCell<BTreeMap<&'static str, u64>>,
+    frames_out: Cell<u64>,
+    bytes_out: Cell<u64>,
+    lag_events: Cell<u64>,
+}
+
+/// Values read from the world at scrape time.
+pub struct Gauges {
+    pub clients: usize,
+    pub players: usize,
+    pub units: usize,
+    pub buildings: usize,
+    pub resource_nodes: usize,
+    pub tick: u64,
+}
+
+impl Metrics {
+    pub fn tick(&self, took: Duration) {
+        self.tick_seconds.borrow_mut().observe(took.as_secs_f64());
+    }
+
+    /// A tick started a whole tick period or more behind schedule.
+    pub fn tick_skipped(&self) {
+        self.ticks_skipped.set(self.ticks_skipped.get() + 1);
+    }
+
+    pub fn message_in(&self, name: &'static str) {
+        *self.messages_in.borrow_mut().entry(name).or_default() += 1;
+    }
+
+    pub fn message_out(&self, name: &'static str) {
+        *self.messages_out.borrow_mut().entry(name).or_default() += 1;
+    }
+
+    /// A command refused by its connection's rate limit.
+    pub fn throttled(&self, name: &'static str) {
+        *self
+            .messages_throttled
+            .borrow_mut()
+            .entry(name)
+            .or_default() += 1;
+    }
+
+    pub fn frame_out(&self, bytes: usize) {
+        self.frames_out.set(self.frames_out.get() + 1);
+        self.bytes_out.set(self.bytes_out.get() + bytes as u64);
+    }
+
+    /// A client's outbox was full and it started missing events.
+    pub fn lagged(&self) {
+        self.lag_events.set(self.lag_events.get() + 1);
+    }
+
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let one = |value: String| vec![(String::new(), value)];
+        let by_type = |counts: &BTreeMap<&'static str, u64>| {
+            counts
+                .iter()
+                .map(|(name, n)| (format!("{{type=\"{}\"}}", name), n.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        metric(
+            "temty_clients",
+            "gauge",
+            "Connected clients.",
+            &one(gauges.clients.to_string()),
+        );
+        metric(
+            "temty_players",
+            "gauge",
+            "Players in the world, online or not.",
+            &one(gauges.players.to_string()),
+        );
+        metric(
+            "temty_units",
+            "gauge",
+            "Units in the world.",
+            &one(gauges.units.to_string()),
+        );
+        metric(
+            "temty_buildings",
+            "gauge",
+            "Finished buildings in the world.",
+            &one(gauges.buildings.to_string()),
+        );
+        metric(
+            "temty_resource_nodes",
+            "gauge",
+            "Resource nodes being tracked.",
+            &one(gauges.resource_nodes.to_string()),
+        );
+        metric(
+            "temty_tick",
+            "counter",
+            "Simulation ticks run since startup.",
+            &one(gauges.tick.to_string()),
+        );
+
+        let h = self.tick_seconds.borrow();
+        let mut samples: Vec<_> = TICK_BUCKETS
+            .iter()
+            .zip(h.buckets)
+            .map(|(bound, n)| (format!("_bucket{{le=\"{}\"}}", bound), n.to_string()))
+            .collect();
+        samples.push(("_bucket{le=\"+Inf\"}".to_string(), h.count.to_string()));
+        samples.push(("_sum".to_string(), h.sum.to_string()));
+        samples.push(("_count".to_string(), h.count.to_string()));
+        metric(
+            "temty_tick_duration_seconds",
+            "histogram",
+            "Time spent running one tick.",
+            &samples,
+        );
+
+        metric(
+            "temty_ticks_skipped_total",
+            "counter",
+            "Ticks that started a whole tick period or more late.",
+            &one(self.ticks_skipped.get().to_string()),
+        );
+        metric(
+            "temty_messages_in_total",
+            "counter",
+            "Commands received from clients, by type.",
+            &by_type(&self.messages_in.borrow()),
+        );
+        metric(
+            "temty_messages_out_total",
+            "counter",
+            "Messages sent to clients, by type; a broadcast counts once per recipient.",
+            &by_type(&self.messages_out.borrow()),
+        );
+        metric(
+            "temty_messages_throttled_total",
+            "counter",
+            "Commands refused by rate limits, by type.",
+            &by_type(&self.messages_throttled.borrow()),
+        );
+        metric(
+            "temty_frames_out_total",
+            "counter",
+            "WebSocket frames queued to clients.",
+            &one(self.frames_out.get().to_string()),
+        );
+        metric(
+            "temty_bytes_out_total",
+            "counter",
+            "Payload bytes queued to clients.",
+            &one(self.bytes_out.get().to_string()),
+        );
+        metric(
+            "temty_lag_events_total",
+            "counter",
+            "Times a client fell behind and had events dropped.",
+            &one(self.lag_events.get().to_string()),
+        );
+        out
+    }
+}
@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::TILE_SIZE;
+
+// Same search budget as the client's predictor so both sides give up on the same targets.
+const MAX_SEARCH_STEPS: usize = 15000;
+
+/// Farthest a move order may reach, in tiles along either axis. Anything further would only burn
+/// the search budget before failing.
+pub const MAX_MOVE_TILES: u32 = 96;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Node {
+    cost: u32,
+    pos: (i32, i32),
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub fn tile_of(x: f32, y: f32) -> (i32, i32) {
+    (
+        (x / TILE_SIZE).floor() as i32,
+        (y / TILE_SIZE).floor() as i32,
+    )
+}
+
+pub fn tile_center(tx: i32, ty: i32) -> (f32, f32) {
+    (
+        tx as f32 * TILE_SIZE + TILE_SIZE / 2.0,
+        ty as f32 * TILE_SIZE + TILE_SIZE / 2.0,
+    )
+}
+
+/// Whether `to` is close enough to `from` to be worth searching for.
+pub fn within_reach(from: (i32, i32), to: (i32, i32)) -> bool {
+    distance(from, to) <= MAX_MOVE_TILES
+}
+
+// Chebyshev distance, exact for any two tiles: `abs_diff` can't overflow the way subtracting can.
+fn distance(a: (i32, i32), b: (i32, i32)) -> u32 {
+    a.0.abs_diff(b.0).max(a.1.abs_diff(b.1))
+}
+
+/// A* over the tile grid (8-way, no corner cutting), mirroring `GameState::find_path` on the client.
+/// Returns world-space waypoints in reverse order (next waypoint is `last()`), or an empty path if
+/// the goal is blocked or unreachable within the search budget.
+pub fn find_path<F>(start: (f32, f32), goal: (i32, i32), is_walkable: F) -> Vec<(f32, f32)>
+where
+    F: Fn(i32, i32) -> bool,
+{
+    let start_tile = tile_of(start.0, start.1);
+    let end = tile_center(goal.0, goal.1);
+
+    if start_tile == goal {
+        return vec![end];
+    }
+    if !is_walkable(goal.0, goal.1) {
+        return vec![];
+    }
+
+    let mut frontier = BinaryHeap::new();
+    frontier.push(Node {
+        cost: 0,
+        pos: start_tile,
+    });
+
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    let mut cost_so_far: HashMap<(i32, i32), u32> = HashMap::new();
+    came_from.insert(start_tile, start_tile);
+    cost_so_far.insert(start_tile, 0);
+
+    let mut found = false;
+    let mut steps = 0;
+
+    while let Some(Node { pos: current, .. }) = frontier.pop() {
+        steps += 1;
+        if steps > MAX_SEARCH_STEPS {
+            break;
+        }
+
+        if current == goal {
+            found = true;
+            break;
+        }
+
+        let dirs = [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (-1, -1),
+            (1, -1),
+            (-1, 1),
+        ];
+
+        for (dx, dy) in dirs {
+            let next = (current.0 + dx, current.1 + dy);
+            if !is_walkable(next.0, next.1) {
+                continue;
+            }
+            // Prevent corner cutting
+            if dx != 0
+                && dy != 0
+                && (!is_walkable(current.0 + dx, current.1)
+                    || !is_walkable(current.0, current.1 + dy))
+            {
+                continue;
+            }
+
+            let new_cost = cost_so_far[&current] + 1;
+            if cost_so_far.get(&next).is_none_or(|&c| new_cost < c) {
+                cost_so_far.insert(next, new_cost);
+                let h = distance(next, goal);
+                frontier.push(Node {
+                    cost: new_cost.saturating_add(h),
+                    pos: next,
+                });
+                came_from.insert(next, current);
+            }
+        }
+    }
+
+    if !found {
+        return vec![];
+    }
+
+    let mut path = vec![end];
+    let mut curr = came_from[&goal];
+    while curr != start_tile {
+        path.push(tile_center(curr.0, curr.1));
+        curr = came_from[&curr];
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goals_at_the_edge_of_the_map_give_up_instead_of_overflowing() {
+        let start = tile_center(0, 0);
+        for goal in [(i32::MAX, i32::MIN), (i32::MIN, 5), (3, i32::MAX)] {
+            assert!(!within_reach((0, 0), goal));
+            assert!(find_path(start, goal, |_, _| true).is_empty());
+        }
+        assert!(within_reach((i32::MAX, 0), (i32::MAX - 96, 96)));
+        assert_eq!(find_path(start, (3, -2), |_, _| true).len(), 3);
+    }
+}
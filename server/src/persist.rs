@@ -0,0 +1,247 @@
+//! World snapshots. `GlobalState` is written to a versioned JSON file periodically and on
+//! shutdown, and read back at startup, so a redeploy doesn't wipe players, tokens and bases.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use protocol::{BuildingDTO, PlayerInfo, Resources};
+
+use crate::{BuildTask, GatherTask, GlobalState, ResourceNode, TrainTask, UnitState};
+
+/// Bump when `Snapshot` changes shape, and teach `migrate` to upgrade the previous version.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+// Tuple-keyed maps are stored as lists; JSON object keys must be strings.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    next_id: i32,
+    next_unit_id: u64,
+    players: Vec<PlayerInfo>,
+    units: Vec<(i32, Vec<UnitState>)>,
+    tokens: Vec<(String, i32)>,
+    resources: Vec<(i32, Resources)>,
+    pop_cap: Vec<(i32, i32)>,
+    building_progress: Vec<BuildTask>,
+    training_tasks: Vec<TrainTask>,
+    gather_tasks: Vec<((i32, u64), GatherTask)>,
+    buildings: Vec<BuildingDTO>,
+    resource_nodes: Vec<((i32, i32), ResourceNode)>,
+    // Since version 2
+    banned_tokens: Vec<String>,
+}
+
+impl GlobalState {
+    /// Serializes everything except the balance table, which always comes from config.
+    pub fn snapshot_json(&self) -> String {
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            next_id: self.next_id,
+            next_unit_id: self.next_unit_id,
+            players: self.players.values().cloned().collect(),
+            units: self.units.iter().map(|(k, v)| (*k, v.clone())).collect(),
+            tokens: self.tokens.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            resources: self.resources.iter().map(|(k, v)| (*k, *v)).collect(),
+            pop_cap: self.pop_cap.iter().map(|(k, v)| (*k, *v)).collect(),
+            building_progress: self.building_progress.values().copied().collect(),
+            training_tasks: self.training_tasks.clone(),
+            gather_tasks: self.gather_tasks.iter().map(|(k, v)| (*k, *v)).collect(),
+            buildings: self.buildings.clone(),
+            resource_nodes: self.resource_nodes.iter().map(|(k, v)| (*k, *v)).collect(),
+            banned_tokens: self.banned_tokens.iter().cloned().collect(),
+        };
+        serde_json::to_string(&snapshot).unwrap()
+    }
+
+    fn restore(&mut self, s: Snapshot) {
+        self.next_id = s.next_id;
+        self.next_unit_id = s.next_unit_id;
+        self.players = s.players.into_iter().map(|p| (p.id, p)).collect();
+        self.units = s.units.into_iter().collect();
+        self.tokens = s.tokens.into_iter().collect();
+        self.resources = s.resources.into_iter().collect();
+        self.pop_cap = s.pop_cap.into_iter().collect();
+        self.building_progress = s
+            .building_progress
+            .into_iter()
+            .map(|t| ((t.tile_x, t.tile_y), t))
+            .collect();
+        self.training_tasks = s.training_tasks;
+        self.gather_tasks = s.gather_tasks.into_iter().collect();
+        self.buildings = s.buildings;
+        self.resource_nodes = s.resource_nodes.into_iter().collect();
+        self.banned_tokens = s.banned_tokens.into_iter().collect();
+    }
+}
+
+/// Where snapshots live: `SNAPSHOT_FILE`, or `world.snapshot.json` in the working directory.
+pub fn snapshot_path() -> PathBuf {
+    std::env::var("SNAPSHOT_FILE")
+        .unwrap_or_else(|_| "world.snapshot.json".to_string())
+        .into()
+}
+
+/// Restores `state` from `path` if a snapshot exists there. A missing file is a fresh world; a
+/// file that can't be read or migrated is an error, so a bad deploy never overwrites it.
+pub fn load(state: &mut GlobalState, path: &Path) -> Result<bool, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let value: Value =
+        serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    restore_json(state, value).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(true)
+}
+
+/// Restores `state` from an already parsed snapshot, migrating it from older versions first.
+pub fn restore_json(state: &mut GlobalState, mut value: Value) -> Result<(), String> {
+    let mut version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > SNAPSHOT_VERSION {
+        return Err(format!(
+            "snapshot version {} is newer than this server ({})",
+            version, SNAPSHOT_VERSION
+        ));
+    }
+    while version < SNAPSHOT_VERSION {
+        value = migrate(value, version)?;
+        version += 1;
+    }
+
+    let snapshot: Snapshot = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    state.restore(snapshot);
+    Ok(())
+}
+
+// Upgrades a snapshot from `from` to `from + 1`. Add a step here whenever SNAPSHOT_VERSION is
+// bumped.
+fn migrate(mut value: Value, from: u32) -> Result<Value, String> {
+    match from {
+        // 1 -> 2: admin bans
+        1 => {
+            value["banned_tokens"] = Value::Array(Vec::new());
+        }
+        _ => {
+            return Err(format!(
+                "don't know how to migrate snapshot version {}",
+                from
+            ))
+        }
+    }
+    value["version"] = Value::from(from + 1);
+    Ok(value)
+}
+
+/// Writes next to the target and renames over it, so a crash mid-write leaves the previous
+/// snapshot intact. Only `Writer` calls this, so there is never more than one write in flight.
+fn write_atomic(path: &Path, json: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&tmp, path)
+}
+
+type Save = (String, oneshot::Sender<Result<(), String>>);
+
+/// Every snapshot write goes through one writer task, which writes them one at a time in the
+/// order they were queued, on a blocking thread so the simulation never waits on the disk. A slow
+/// periodic save therefore can't finish after a newer one and put older state back on disk.
+pub struct Writer {
+    path: PathBuf,
+    saves: mpsc::UnboundedSender<Save>,
+    task: JoinHandle<()>,
+}
+
+impl Writer {
+    pub fn spawn(path: PathBuf) -> Writer {
+        let (saves, mut queue) = mpsc::unbounded_channel::<Save>();
+        let target = path.clone();
+        let task = tokio::spawn(async move {
+            while let Some((json, done)) = queue.recv().await {
+                let path = target.clone();
+                let result =
+                    match tokio::task::spawn_blocking(move || write_atomic(&path, &json)).await {
+                        Ok(written) => written.map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                if let Err(e) = &result {
+                    error!(path = %target.display(), error = %e, "Snapshot failed");
+                }
+                let _ = done.send(result);
+            }
+        });
+        Writer { path, saves, task }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues a snapshot; the receiver hears how the write went.
+    pub fn save(&self, json: String) -> oneshot::Receiver<Result<(), String>> {
+        let (done, saved) = oneshot::channel();
+        let _ = self.saves.send((json, done));
+        saved
+    }
+
+    /// Writes a last snapshot after everything already queued, and waits for all of them.
+    pub async fn finish(self, json: String) -> Result<(), String> {
+        let saved = self.save(json);
+        drop(self.saves);
+        let _ = self.task.await;
+        saved
+            .await
+            .unwrap_or_else(|_| Err("snapshot writer stopped".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::Balance;
+
+    #[test]
+    fn version_1_snapshots_load_with_no_bans() {
+        let mut gs = GlobalState::new(Balance::default(), 0);
+        gs.tokens.insert("alice".to_string(), 1);
+        gs.resources
+            .insert(1, Resources::new(10.0, 20.0, 30.0, 40.0));
+        gs.banned_tokens.insert("mallory".to_string());
+
+        // What a version 1 server wrote: no ban list yet
+        let mut v1: Value = serde_json::from_str(&gs.snapshot_json()).unwrap();
+        v1["version"] = Value::from(1);
+        v1.as_object_mut().unwrap().remove("banned_tokens");
+
+        let v2 = migrate(v1.clone(), 1).unwrap();
+        assert_eq!(v2["version"], 2);
+        assert_eq!(v2["banned_tokens"], Value::Array(Vec::new()));
+
+        let mut restored = GlobalState::new(Balance::default(), 0);
+        restore_json(&mut restored, v1).unwrap();
+        assert!(restored.banned_tokens.is_empty());
+        assert_eq!(restored.tokens.get("alice"), Some(&1));
+        assert_eq!(restored.resources[&1].food, 40.0);
+    }
+
+    #[test]
+    fn snapshots_from_newer_servers_are_refused() {
+        let gs = GlobalState::new(Balance::default(), 0);
+        let mut value: Value = serde_json::from_str(&gs.snapshot_json()).unwrap();
+        value["version"] = Value::from(SNAPSHOT_VERSION + 1);
+        assert!(restore_json(&mut GlobalState::new(Balance::default(), 0), value).is_err());
+    }
+}
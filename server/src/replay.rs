@@ -0,0 +1,321 @@
+//! Replay log. Each server run appends a `Start` entry (the world it began from and the seed of
+//! the simulation's RNG) followed by every accepted command, join, leave and admin change, each
+//! stamped with the tick it ran after. Feeding those back through a fresh `Simulation` produces
+//! the same broadcasts; periodic checksums of the live broadcasts pin down where a replay
+//! stops matching, so desyncs can be bisected offline with the `replay` binary.
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc as std_mpsc;
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use protocol::admin::AdminRequest;
+use protocol::{Balance, GameMessage};
+
+use crate::sim::Simulation;
+use crate::{persist, GlobalState};
+
+/// Bump when `Entry` changes shape.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// Ticks between `Checksum` entries (5 seconds).
+pub const CHECKSUM_TICKS: u64 = 25;
+
+/// One line of the log. `tick` is the number of ticks the simulation had run when the entry
+/// was applied.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "entry")]
+pub enum Entry {
+    /// First line of every server run. `world` is a snapshot with player tokens removed.
+    Start {
+        version: u32,
+        seed: u64,
+        balance: Balance,
+        world: Value,
+    },
+    Join {
+        tick: u64,
+        player_id: i32,
+        conn_id: u64,
+    },
+    Command {
+        tick: u64,
+        player_id: i32,
+        seq: u32,
+        msg: GameMessage,
+    },
+    Leave {
+        tick: u64,
+        player_id: i32,
+        conn_id: u64,
+    },
+    Admin {
+        tick: u64,
+        req: AdminRequest,
+    },
+    /// Count and hash of everything broadcast since the previous checksum.
+    Checksum {
+        tick: u64,
+        events: u64,
+        hash: u64,
+    },
+}
+
+/// Where the log lives: `REPLAY_FILE`. Recording is opt-in, since the log grows for as long as
+/// the server runs; unset or empty means no log.
+pub fn replay_path() -> Option<PathBuf> {
+    std::env::var("REPLAY_FILE")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+// Running FNV-1a over the JSON of each broadcast. Stable across builds and platforms, unlike
+// std's hasher, so logs can be checked by a different binary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Digest {
+    pub events: u64,
+    pub hash: u64,
+}
+
+impl Digest {
+    pub const fn new() -> Self {
+        Digest {
+            events: 0,
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    pub fn add(self, json: &str) -> Self {
+        let mut hash = self.hash;
+        for &b in json.as_bytes().iter().chain(b"\n") {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        Digest {
+            events: self.events + 1,
+            hash,
+        }
+    }
+}
+
+impl Default for Digest {
+    fn default() -> Self {
+        Digest::new()
+    }
+}
+
+/// Appends entries to the log from a background thread, so the tick never waits on the disk.
+/// Dropping it flushes whatever is still queued.
+pub(crate) struct Recorder {
+    lines: Option<std_mpsc::Sender<String>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Opens `path` for appending and writes the `Start` entry for this run.
+    pub fn start(path: &Path, seed: u64, state: &GlobalState) -> std::io::Result<Recorder> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let (lines, rx) = std_mpsc::channel::<String>();
+        let writer = std::thread::spawn(move || {
+            let mut out = BufWriter::new(file);
+            while let Ok(line) = rx.recv() {
+                let _ = writeln!(out, "{}", line);
+                // Batch whatever else is already queued into one flush
+                while let Ok(line) = rx.try_recv() {
+                    let _ = writeln!(out, "{}", line);
+                }
+                let _ = out.flush();
+            }
+        });
+
+        let recorder = Recorder {
+            lines: Some(lines),
+            writer: Some(writer),
+        };
+        recorder.record(&start_entry(seed, state));
+        Ok(recorder)
+    }
+
+    pub fn record(&self, entry: &Entry) {
+        if let (Some(lines), Ok(json)) = (&self.lines, serde_json::to_string(entry)) {
+            let _ = lines.send(json);
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.lines = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn start_entry(seed: u64, state: &GlobalState) -> Entry {
+    // Tokens are credentials and play no part in the simulation; keep them out of a file that
+    // gets attached to bug reports.
+    let mut world: Value = serde_json::from_str(&state.snapshot_json()).unwrap();
+    world["tokens"] = Value::Array(Vec::new());
+    world["banned_tokens"] = Value::Array(Vec::new());
+    Entry::Start {
+        version: REPLAY_VERSION,
+        seed,
+        balance: state.balance.clone(),
+        world,
+    }
+}
+
+/// One server run: its `Start` entry and everything logged after it.
+pub struct Session {
+    /// Line of the `Start` entry in the file (1-based).
+    pub line: usize,
+    pub seed: u64,
+    balance: Balance,
+    world: Value,
+    entries: Vec<Entry>,
+}
+
+/// Reads every session in a log.
+pub fn read(path: &Path) -> Result<Vec<Session>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut sessions: Vec<Session> = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+        match entry {
+            Entry::Start {
+                version,
+                seed,
+                balance,
+                world,
+            } => {
+                if version > REPLAY_VERSION {
+                    return Err(format!(
+                        "{}:{}: replay version {} is newer than this build ({})",
+                        path.display(),
+                        i + 1,
+                        version,
+                        REPLAY_VERSION
+                    ));
+                }
+                sessions.push(Session {
+                    line: i + 1,
+                    seed,
+                    balance,
+                    world,
+                    entries: Vec::new(),
+                });
+            }
+            entry => match sessions.last_mut() {
+                Some(session) => session.entries.push(entry),
+                None => {
+                    return Err(format!(
+                        "{}:{}: entry before the first Start",
+                        path.display(),
+                        i + 1
+                    ))
+                }
+            },
+        }
+    }
+    Ok(sessions)
+}
+
+/// What re-running a session produced.
+#[derive(Debug)]
+pub struct Report {
+    /// Ticks simulated (up to the last logged entry).
+    pub ticks: u64,
+    /// Joins, commands, leaves and admin changes applied.
+    pub applied: usize,
+    /// Checksums that matched.
+    pub checked: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// The first checksum that didn't match. The difference lies in the broadcasts of ticks
+/// `since_tick + 1 ..= tick`, or the commands applied between them.
+#[derive(Debug)]
+pub struct Divergence {
+    pub since_tick: u64,
+    pub tick: u64,
+    pub expected_events: u64,
+    pub expected_hash: u64,
+    pub replayed_events: u64,
+    pub replayed_hash: u64,
+}
+
+/// Re-runs a session from its starting world, passing every broadcast to `on_event` with the
+/// tick it happened after. Stops at the first checksum that doesn't match.
+pub fn run(session: &Session, mut on_event: impl FnMut(u64, &str)) -> Result<Report, String> {
+    let mut state = GlobalState::new(session.balance.clone(), session.seed);
+    persist::restore_json(&mut state, session.world.clone())?;
+
+    // Big enough that a single tick's broadcasts never lag the reader below
+    let (tx, mut rx) = broadcast::channel(1 << 16);
+    let mut sim = Simulation::new(state, tx, None, None, protocol::PROTOCOL_VERSION);
+    let mut report = Report {
+        ticks: 0,
+        applied: 0,
+        checked: 0,
+        divergence: None,
+    };
+    let mut since_tick = 0;
+
+    let mut drain = |tick: u64, rx: &mut broadcast::Receiver<String>| {
+        while let Ok(json) = rx.try_recv() {
+            on_event(tick, &json);
+        }
+    };
+
+    for entry in &session.entries {
+        let tick = match entry {
+            Entry::Join { tick, .. }
+            | Entry::Command { tick, .. }
+            | Entry::Leave { tick, .. }
+            | Entry::Admin { tick, .. }
+            | Entry::Checksum { tick, .. } => *tick,
+            Entry::Start { .. } => continue,
+        };
+        while sim.tick_count() < tick {
+            sim.tick();
+            drain(sim.tick_count(), &mut rx);
+        }
+
+        if let Entry::Checksum { events, hash, .. } = *entry {
+            let replayed = sim.take_digest();
+            if replayed != (Digest { events, hash }) {
+                report.divergence = Some(Divergence {
+                    since_tick,
+                    tick,
+                    expected_events: events,
+                    expected_hash: hash,
+                    replayed_events: replayed.events,
+                    replayed_hash: replayed.hash,
+                });
+                break;
+            }
+            report.checked += 1;
+            since_tick = tick;
+        } else {
+            sim.replay(entry.clone());
+            report.applied += 1;
+            drain(tick, &mut rx);
+        }
+    }
+    report.ticks = sim.tick_count();
+    Ok(report)
+}
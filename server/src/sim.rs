@@ -0,0 +1,1415 @@
+//! Simulation actor. `GlobalState` is owned by a single task; connections feed it through a
+//! command queue instead of locking shared state, so commands are never dropped on contention
+//! and every tick runs.
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use rand::Rng;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use protocol::admin::{AdminRequest, AdminResponse, PlayerSummary};
+use protocol::wire::{self, Encoding};
+use protocol::{
+    BuildKind, BuildingDTO, GameMessage, PlayerInfo, RejectReason, ResourceKind, UnitDTO, UnitKind,
+    TOWN_CENTER_KIND,
+};
+
+use crate::interest::{self, Chunk, View};
+use crate::metrics::{Gauges, Metrics};
+use crate::pathfinding;
+use crate::replay::{Digest, Entry, Recorder, CHECKSUM_TICKS};
+use crate::step::step;
+use crate::{
+    command_ack, default_pop_cap, default_resources, persist, BuildTask, GatherTask, GlobalState,
+    UnitState, MIN_START_RES, POP_FROM_HOUSE, TICK_MS, TILE_SIZE,
+};
+
+/// Queue depth between connections and the simulation. Senders wait when it is full rather than
+/// dropping commands.
+pub const COMMAND_QUEUE: usize = 1024;
+
+/// Frames queued per connection, about one per tick (~13s). A client that lets it fill up has
+/// events dropped until it catches up, then gets a FullState.
+pub const OUTBOX: usize = 64;
+
+/// How often the world is written to the snapshot file while running.
+const SNAPSHOT_SECS: u64 = 30;
+
+pub enum SimCommand {
+    /// Handshake passed; authenticate (or register) and reply with the Welcome payload.
+    /// Fails with a reason for the client when the token is banned; it isn't worth retrying.
+    Join {
+        conn_id: u64,
+        token: Option<String>,
+        /// Protocol version from the client's Hello.
+        version: u32,
+        encoding: Encoding,
+        /// The client wants `NewClientAvailable` pushes.
+        update_notice: bool,
+        outbox: mpsc::Sender<Message>,
+        reply: oneshot::Sender<Result<Joined, String>>,
+    },
+    /// A gameplay message from an authenticated connection; always answered with a CommandAck.
+    Command {
+        player_id: i32,
+        seq: u32,
+        msg: GameMessage,
+    },
+    /// A command refused by the connection's rate limit (see `limits`); answered with a
+    /// RateLimited CommandAck so the client can roll back.
+    Throttled {
+        player_id: i32,
+        seq: u32,
+        command: &'static str,
+    },
+    /// The connection is misbehaving: tell the client `reason` and close it. It may reconnect.
+    Disconnect {
+        player_id: i32,
+        conn_id: u64,
+        reason: String,
+    },
+    /// Connection closed. `conn_id` identifies which connection, in case the player already reconnected.
+    Leave { player_id: i32, conn_id: u64 },
+    /// Operator request from the local admin socket.
+    Admin {
+        req: AdminRequest,
+        reply: oneshot::Sender<AdminResponse>,
+    },
+    /// Counts for the health endpoints (see `http`).
+    Status { reply: oneshot::Sender<Status> },
+    /// Everything in `metrics`, rendered for a Prometheus scrape.
+    Metrics { reply: oneshot::Sender<String> },
+    /// Tell every client, write a final snapshot and stop; `done` fires once it is on disk.
+    Shutdown {
+        notice: GameMessage,
+        done: oneshot::Sender<()>,
+    },
+}
+
+pub struct Joined {
+    pub player_id: i32,
+    pub token: String,
+    pub welcome: Message,
+}
+
+pub struct Status {
+    /// Every player the world knows, online or not.
+    pub players: usize,
+    /// Players with a connection.
+    pub online: usize,
+    pub tick: u64,
+}
+
+// A live connection. Dropping `outbox` closes the socket: the connection's writer stops when
+// the channel does.
+struct Client {
+    conn_id: u64,
+    // Protocol version from Hello, and whether it wants to hear about newer clients
+    version: u32,
+    update_notice: bool,
+    encoding: Encoding,
+    // Everything this player is sent after the Welcome
+    outbox: mpsc::Sender<Message>,
+    view: View,
+    // Chunks this client was last sent (see `interest::interest`)
+    interest: BTreeSet<Chunk>,
+    // The outbox overflowed and messages were dropped; owed a FullState
+    lagged: Cell<bool>,
+    // Messages since the last `deliver`, which sends them as one frame
+    queue: RefCell<Vec<Arc<Outgoing>>>,
+}
+
+// A message on its way to clients. A broadcast is shared by every client it goes to, along with
+// the JSON it was already serialized to.
+struct Outgoing {
+    msg: GameMessage,
+    json: Option<String>,
+}
+
+impl Outgoing {
+    fn json(&self) -> Cow<'_, str> {
+        match &self.json {
+            Some(json) => Cow::Borrowed(json),
+            None => Cow::Owned(serde_json::to_string(&self.msg).unwrap()),
+        }
+    }
+}
+
+impl Client {
+    fn send(&self, msg: &GameMessage) {
+        self.forward(&Arc::new(Outgoing {
+            msg: msg.clone(),
+            json: None,
+        }));
+    }
+
+    fn forward(&self, out: &Arc<Outgoing>) {
+        if !self.lagged.get() {
+            self.queue.borrow_mut().push(out.clone());
+        }
+    }
+
+    // Sends everything queued as a single frame: the message itself if there is only one, a
+    // TickBatch otherwise.
+    fn deliver(&self, tick: u64, metrics: &Metrics) {
+        let queued = self.queue.take();
+        if queued.is_empty() || self.lagged.get() {
+            return;
+        }
+        for out in &queued {
+            metrics.message_out(out.msg.name());
+        }
+        let frame = match (self.encoding, queued.as_slice()) {
+            (Encoding::Json, [one]) => Message::Text(one.json().into_owned()),
+            (Encoding::Binary, [one]) => Message::Binary(wire::to_binary(&one.msg)),
+            (Encoding::Json, many) => {
+                // Spliced by hand so each broadcast's JSON is reused rather than re-serialized
+                let events: Vec<Cow<str>> = many.iter().map(|o| o.json()).collect();
+                Message::Text(format!(
+                    r#"{{"type":"TickBatch","tick":{},"events":[{}]}}"#,
+                    tick,
+                    events.join(",")
+                ))
+            }
+            (Encoding::Binary, many) => {
+                let events = many.iter().map(|o| o.msg.clone()).collect();
+                Message::Binary(wire::to_binary(&GameMessage::TickBatch { tick, events }))
+            }
+        };
+        let bytes = frame.len();
+        match self.outbox.try_send(frame) {
+            Ok(()) => metrics.frame_out(bytes),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.lagged.set(true);
+                metrics.lagged();
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+// Every broadcast goes through here, so the replay log can fingerprint exactly what was sent.
+// `sender` carries the whole event stream (replays read it); clients get their share of
+// `pending` when the simulation flushes.
+struct Broadcaster {
+    sender: broadcast::Sender<String>,
+    digest: Cell<Digest>,
+    pending: RefCell<Vec<Arc<Outgoing>>>,
+}
+
+// How an admin request is answered: right away, or once the snapshot it queued is written.
+enum Answer {
+    Now(AdminResponse),
+    AfterSave {
+        saved: oneshot::Receiver<Result<(), String>>,
+        ok: String,
+        failed: String,
+    },
+}
+
+pub struct Simulation {
+    state: GlobalState,
+    tx: Broadcaster,
+    clients: HashMap<i32, Client>,
+    // Chunk each unit was in when clients were last told about it
+    unit_chunks: HashMap<u64, Chunk>,
+    // Protocol of the deployed web client (`Config::latest_client`)
+    latest_client: u32,
+    metrics: Metrics,
+    tick_count: u64,
+    // None when replaying: nothing is written to disk
+    snapshots: Option<persist::Writer>,
+    recorder: Option<Recorder>,
+}
+
+fn broadcast(tx: &Broadcaster, msg: &GameMessage) {
+    if let Ok(json) = serde_json::to_string(msg) {
+        tx.digest.set(tx.digest.get().add(&json));
+        let _ = tx.sender.send(json.clone());
+        tx.pending.borrow_mut().push(Arc::new(Outgoing {
+            msg: msg.clone(),
+            json: Some(json),
+        }));
+    }
+}
+
+impl Simulation {
+    pub fn new(
+        state: GlobalState,
+        tx: broadcast::Sender<String>,
+        snapshots: Option<persist::Writer>,
+        recorder: Option<Recorder>,
+        latest_client: u32,
+    ) -> Self {
+        let tx = Broadcaster {
+            sender: tx,
+            digest: Cell::new(Digest::new()),
+            pending: RefCell::new(Vec::new()),
+        };
+        let unit_chunks = interest::unit_chunks(&state);
+        Simulation {
+            state,
+            tx,
+            clients: HashMap::new(),
+            unit_chunks,
+            latest_client,
+            metrics: Metrics::default(),
+            tick_count: 0,
+            snapshots,
+            recorder,
+        }
+    }
+
+    pub async fn run(mut self, mut commands: mpsc::Receiver<SimCommand>) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_millis(TICK_MS));
+        let mut snapshots = tokio::time::interval(std::time::Duration::from_secs(SNAPSHOT_SECS));
+        snapshots.tick().await; // first tick is immediate; nothing worth saving yet
+        loop {
+            tokio::select! {
+                scheduled = interval.tick() => {
+                    let started = tokio::time::Instant::now();
+                    if started - scheduled >= std::time::Duration::from_millis(TICK_MS) {
+                        self.metrics.tick_skipped();
+                    }
+                    self.tick();
+                    if self.tick_count.is_multiple_of(CHECKSUM_TICKS) {
+                        self.record_checksum();
+                    }
+                    self.metrics.tick(started.elapsed());
+                }
+                _ = snapshots.tick() => self.save_snapshot(),
+                cmd = commands.recv() => match cmd {
+                    Some(SimCommand::Shutdown { notice, done }) => {
+                        for client in self.clients.values() {
+                            client.send(&notice);
+                        }
+                        self.deliver();
+                        // Lands after any periodic save still being written
+                        if let Some(writer) = self.snapshots.take() {
+                            if let Err(e) = writer.finish(self.state.snapshot_json()).await {
+                                error!(error = %e, "Final snapshot failed");
+                            }
+                        }
+                        // Covers the ticks since the last periodic checksum, then flushes the log
+                        self.record_checksum();
+                        self.recorder = None;
+                        // Closes every socket once the notice is written
+                        self.clients.clear();
+                        let _ = done.send(());
+                        break;
+                    }
+                    Some(cmd) => self.handle(cmd),
+                    None => break,
+                },
+            }
+        }
+    }
+
+    // Serializes here (the state is ours) and leaves the disk write to the snapshot writer, which
+    // logs failures.
+    fn save_snapshot(&self) {
+        if let Some(writer) = &self.snapshots {
+            writer.save(self.state.snapshot_json());
+        }
+    }
+
+    fn handle(&mut self, cmd: SimCommand) {
+        match cmd {
+            SimCommand::Join {
+                conn_id,
+                token,
+                version,
+                encoding,
+                update_notice,
+                outbox,
+                reply,
+            } => {
+                self.metrics.message_in("Hello");
+                if token
+                    .as_ref()
+                    .is_some_and(|t| self.state.banned_tokens.contains(t))
+                {
+                    let _ = reply.send(Err("This account has been banned".to_string()));
+                    return;
+                }
+                let joined = self.join(conn_id, token, encoding, outbox);
+                let player_id = joined.player_id;
+                self.record(Entry::Join {
+                    tick: self.tick_count,
+                    player_id,
+                    conn_id,
+                });
+                let _ = reply.send(Ok(joined));
+                self.flush();
+                self.refresh_interest(player_id);
+                self.note_client_version(player_id, version, update_notice);
+                self.deliver_to(player_id);
+            }
+            SimCommand::Command {
+                player_id,
+                seq,
+                msg,
+            } => {
+                self.metrics.message_in(msg.name());
+                // Rejected commands change nothing, so only accepted ones are logged; views and
+                // resyncs only affect what is sent
+                let resync = matches!(msg, GameMessage::RequestResync);
+                let world_change = !resync && !matches!(msg, GameMessage::SetView { .. });
+                let logged = (self.recorder.is_some() && world_change).then(|| msg.clone());
+                let result = self.apply(player_id, msg);
+                if let (Some(msg), Ok(())) = (logged, result) {
+                    self.record(Entry::Command {
+                        tick: self.tick_count,
+                        player_id,
+                        seq,
+                        msg,
+                    });
+                }
+                self.flush();
+                if let Some(client) = self.clients.get(&player_id) {
+                    client.send(&command_ack(seq, result));
+                }
+                if resync {
+                    self.resync(player_id);
+                } else {
+                    self.refresh_interest(player_id);
+                }
+                self.deliver_to(player_id);
+            }
+            SimCommand::Throttled {
+                player_id,
+                seq,
+                command,
+            } => {
+                self.metrics.throttled(command);
+                if let Some(client) = self.clients.get(&player_id) {
+                    client.send(&command_ack(seq, Err(RejectReason::RateLimited)));
+                }
+                self.deliver_to(player_id);
+            }
+            SimCommand::Disconnect {
+                player_id,
+                conn_id,
+                reason,
+            } => {
+                if self
+                    .clients
+                    .get(&player_id)
+                    .is_some_and(|c| c.conn_id == conn_id)
+                {
+                    self.kick(player_id, &reason, true);
+                }
+            }
+            SimCommand::Leave { player_id, conn_id } => {
+                self.record(Entry::Leave {
+                    tick: self.tick_count,
+                    player_id,
+                    conn_id,
+                });
+                if self
+                    .clients
+                    .get(&player_id)
+                    .is_some_and(|c| c.conn_id == conn_id)
+                {
+                    self.clients.remove(&player_id);
+                }
+            }
+            SimCommand::Admin { req, reply } => {
+                let read_only = matches!(
+                    req,
+                    AdminRequest::ListPlayers
+                        | AdminRequest::InspectPlayer { .. }
+                        | AdminRequest::SaveSnapshot
+                        | AdminRequest::SetLatestClient { .. }
+                );
+                if !read_only {
+                    // Tokens are credentials and stay out of the log. A replayed world has none
+                    // anyway, so a ban is logged as the kick it causes, which is all it changes.
+                    let logged = match &req {
+                        AdminRequest::BanToken { token } => self
+                            .state
+                            .tokens
+                            .get(token)
+                            .map(|&player_id| AdminRequest::Kick { player_id }),
+                        req => Some(req.clone()),
+                    };
+                    if let Some(req) = logged {
+                        self.record(Entry::Admin {
+                            tick: self.tick_count,
+                            req,
+                        });
+                    }
+                }
+                let answer = self.admin(req);
+                self.flush();
+                match answer {
+                    Answer::Now(resp) => {
+                        let _ = reply.send(resp);
+                    }
+                    // Answered from another task so the simulation doesn't wait on the disk
+                    Answer::AfterSave { saved, ok, failed } => {
+                        tokio::spawn(async move {
+                            let resp = match saved.await {
+                                Ok(Ok(())) => AdminResponse::Ok { message: ok },
+                                Ok(Err(e)) => AdminResponse::Error {
+                                    message: format!("{}: {}", failed, e),
+                                },
+                                Err(_) => AdminResponse::Error {
+                                    message: format!("{}: snapshot writer stopped", failed),
+                                },
+                            };
+                            let _ = reply.send(resp);
+                        });
+                    }
+                }
+            }
+            SimCommand::Status { reply } => {
+                let _ = reply.send(Status {
+                    players: self.state.players.len(),
+                    online: self.clients.len(),
+                    tick: self.tick_count,
+                });
+            }
+            SimCommand::Metrics { reply } => {
+                let gs = &self.state;
+                let _ = reply.send(self.metrics.render(&Gauges {
+                    clients: self.clients.len(),
+                    players: gs.players.len(),
+                    units: gs.units.values().map(Vec::len).sum(),
+                    buildings: gs.buildings.len(),
+                    resource_nodes: gs.resource_nodes.len(),
+                    tick: self.tick_count,
+                }));
+            }
+            SimCommand::Shutdown { .. } => unreachable!("handled in run"),
+        }
+    }
+
+    fn join(
+        &mut self,
+        conn_id: u64,
+        token: Option<String>,
+        encoding: Encoding,
+        outbox: mpsc::Sender<Message>,
+    ) -> Joined {
+        let gs = &mut self.state;
+
+        // Authenticate or Register (in-memory only)
+        let known = token
+            .as_ref()
+            .and_then(|t| gs.tokens.get(t).map(|&pid| (pid, t.clone())));
+        let (player_id, token) = match known {
+            Some(found) => found,
+            None => {
+                let id = gs.next_id;
+                gs.next_id += 1;
+                let new_token = Uuid::new_v4().to_string();
+                gs.tokens.insert(new_token.clone(), id);
+                (id, new_token)
+            }
+        };
+        self.enter(player_id, token, conn_id, encoding, outbox)
+    }
+
+    // Everything about joining except the token, which is all a replay can't reproduce.
+    fn enter(
+        &mut self,
+        player_id: i32,
+        token: String,
+        conn_id: u64,
+        encoding: Encoding,
+        outbox: mpsc::Sender<Message>,
+    ) -> Joined {
+        let gs = &mut self.state;
+        let (chunk_x, chunk_y) = GlobalState::assign_next_position(player_id);
+
+        gs.players.insert(
+            player_id,
+            PlayerInfo {
+                id: player_id,
+                chunk_x,
+                chunk_y,
+            },
+        );
+
+        // Initialize economy if missing, and ensure minimum starting resources
+        {
+            let entry = gs.resources.entry(player_id).or_insert(default_resources());
+            entry.wood = entry.wood.max(MIN_START_RES.wood);
+            entry.stone = entry.stone.max(MIN_START_RES.stone);
+            entry.gold = entry.gold.max(MIN_START_RES.gold);
+            entry.food = entry.food.max(MIN_START_RES.food);
+        }
+
+        // Handle Units (memory only)
+        if !gs.units.contains_key(&player_id) {
+            let starting = gs.spawn_units(chunk_x, chunk_y);
+            gs.units.insert(player_id, starting);
+        }
+
+        // Ensure Town Center exists
+        let has_tc = gs
+            .buildings
+            .iter()
+            .any(|b| b.owner_id == player_id && b.kind == TOWN_CENTER_KIND);
+        if !has_tc {
+            let id = gs.alloc_building_id();
+            gs.buildings.push(BuildingDTO {
+                id,
+                owner_id: player_id,
+                kind: TOWN_CENTER_KIND,
+                tile_x: chunk_x * 32 + 16,
+                tile_y: chunk_y * 32 + 16,
+                hp: gs.balance.hp.town_center,
+            });
+        }
+
+        // Pop cap follows the houses this player still has standing
+        let house = BuildKind::House.to_kind_id();
+        let house_count = gs
+            .buildings
+            .iter()
+            .filter(|b| b.owner_id == player_id && b.kind == house)
+            .count() as i32;
+        gs.pop_cap
+            .insert(player_id, default_pop_cap() + house_count);
+
+        // Only this player's own things; everyone else's arrive chunk by chunk (ChunkEnter)
+        let my_units: Vec<UnitDTO> = gs
+            .units
+            .get(&player_id)
+            .map(|us| us.iter().map(|u| u.to_dto(player_id)).collect())
+            .unwrap_or_default();
+        let my_buildings: Vec<BuildingDTO> = gs
+            .buildings
+            .iter()
+            .filter(|b| b.owner_id == player_id)
+            .cloned()
+            .collect();
+
+        info!(player_id, chunk_x, chunk_y, "Player connected");
+        debug!(
+            player_id,
+            units = my_units.len(),
+            buildings = my_buildings.len(),
+            "Sending Welcome"
+        );
+
+        let welcome = GameMessage::Welcome {
+            player_id,
+            chunk_x,
+            chunk_y,
+            players: gs.players.values().cloned().collect(),
+            units: my_units.clone(),
+            buildings: my_buildings,
+            token: token.clone(),
+            resources: *gs.resources.get(&player_id).unwrap_or(&default_resources()),
+            pop_cap: *gs.pop_cap.get(&player_id).unwrap_or(&default_pop_cap()),
+            pop_used: gs
+                .units
+                .get(&player_id)
+                .map(|u| u.len() as i32)
+                .unwrap_or(0),
+            balance: Box::new(gs.balance.clone()),
+        };
+        let welcome = match encoding {
+            Encoding::Json => Message::Text(serde_json::to_string(&welcome).unwrap()),
+            Encoding::Binary => Message::Binary(wire::to_binary(&welcome)),
+        };
+        self.metrics.message_out("Welcome");
+        self.metrics.frame_out(welcome.len());
+
+        for u in &my_units {
+            self.unit_chunks
+                .insert(u.unit_id, interest::pos_chunk(u.x, u.y));
+        }
+
+        // Broadcast New Player, then initial resources to self
+        broadcast(
+            &self.tx,
+            &GameMessage::NewPlayer {
+                player: PlayerInfo {
+                    id: player_id,
+                    chunk_x,
+                    chunk_y,
+                },
+                units: my_units,
+            },
+        );
+        broadcast(&self.tx, &gs.resource_update(player_id));
+
+        let view = View::around((chunk_x, chunk_y));
+        self.clients.insert(
+            player_id,
+            Client {
+                conn_id,
+                version: 0,
+                update_notice: false,
+                encoding,
+                outbox,
+                view,
+                interest: BTreeSet::new(),
+                lagged: Cell::new(false),
+                queue: RefCell::new(Vec::new()),
+            },
+        );
+        Joined {
+            player_id,
+            token,
+            welcome,
+        }
+    }
+
+    // Remembers what a joining client speaks, and tells it if the deployed web client is newer.
+    // The latest version comes from config or the admin socket, never from what clients claim;
+    // nobody is disconnected over it.
+    fn note_client_version(&mut self, player_id: i32, version: u32, update_notice: bool) {
+        let latest = self.latest_client;
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            client.version = version;
+            client.update_notice = update_notice;
+            if update_notice && version < latest {
+                client.send(&GameMessage::NewClientAvailable { version: latest });
+            }
+        }
+    }
+
+    // A new web client has been deployed: every connected client on an older one that opted in
+    // is told now, and later joiners when they arrive. Returns how many were told.
+    fn announce_client(&mut self, version: u32) -> usize {
+        self.latest_client = version;
+        let notice = GameMessage::NewClientAvailable { version };
+        let outdated: Vec<&Client> = self
+            .clients
+            .values()
+            .filter(|c| c.update_notice && c.version < version)
+            .collect();
+        for client in &outdated {
+            client.send(&notice);
+        }
+        outdated.len()
+    }
+
+    /// Applies one command on behalf of `player_id`. Ownership always comes from the connection.
+    /// Nothing is changed when a command is rejected.
+    fn apply(&mut self, player_id: i32, msg: GameMessage) -> Result<(), RejectReason> {
+        let gs = &mut self.state;
+        let tx = &self.tx;
+
+        match msg {
+            GameMessage::MoveUnit {
+                unit_id,
+                tile_x,
+                tile_y,
+            } => {
+                // Clients only send the target tile; the server plans and walks the path.
+                let Some(unit) = gs.unit(player_id, unit_id) else {
+                    return Err(gs.unit_reject_reason(unit_id));
+                };
+                // Checked before searching: far targets would exhaust the budget anyway
+                if !pathfinding::within_reach(
+                    pathfinding::tile_of(unit.x, unit.y),
+                    (tile_x, tile_y),
+                ) || !gs.path_unit_to(player_id, unit_id, (tile_x, tile_y))
+                {
+                    return Err(RejectReason::NoPath);
+                }
+                // Let other clients predict the walk; positions follow via UnitSync.
+                broadcast(
+                    tx,
+                    &GameMessage::UnitMove {
+                        player_id,
+                        unit_id,
+                        tile_x,
+                        tile_y,
+                    },
+                );
+            }
+            GameMessage::Build {
+                kind,
+                tile_x,
+                tile_y,
+            } => {
+                // Town Centers are never player-built
+                if BuildKind::from_kind_id(kind).is_none() {
+                    return Err(RejectReason::InvalidKind);
+                }
+                // Resource check and simple tile occupancy check
+                if gs.is_tile_blocked(tile_x, tile_y)
+                    || gs.building_progress.contains_key(&(tile_x, tile_y))
+                {
+                    return Err(RejectReason::TileBlocked);
+                }
+                let entry = gs.resources.entry(player_id).or_insert(default_resources());
+                if !entry.spend(&gs.balance.build_cost(kind)) {
+                    return Err(RejectReason::CannotAfford);
+                }
+                // Update pop cap if house built
+                if kind == BuildKind::House.to_kind_id() {
+                    *gs.pop_cap.entry(player_id).or_insert(default_pop_cap()) += POP_FROM_HOUSE;
+                }
+                // Track progress start
+                gs.building_progress.insert(
+                    (tile_x, tile_y),
+                    BuildTask {
+                        owner_id: player_id,
+                        kind,
+                        tile_x,
+                        tile_y,
+                        progress: 0.0,
+                    },
+                );
+
+                broadcast(
+                    tx,
+                    &GameMessage::BuildProgress {
+                        tile_x,
+                        tile_y,
+                        kind,
+                        progress: 0.0,
+                    },
+                );
+                broadcast(tx, &gs.resource_update(player_id));
+            }
+            GameMessage::AssignGather {
+                unit_ids,
+                target_x,
+                target_y,
+                kind,
+            } => {
+                if let Some(&bad) = unit_ids.iter().find(|&&uid| !gs.has_unit(player_id, uid)) {
+                    return Err(gs.unit_reject_reason(bad));
+                }
+                let resource = ResourceKind::from_kind_id(kind);
+                if !resource.is_some_and(|r| gs.is_gather_target(r, target_x, target_y)) {
+                    return Err(RejectReason::InvalidKind);
+                }
+                for uid in unit_ids {
+                    gs.gather_tasks.insert(
+                        (player_id, uid),
+                        GatherTask {
+                            kind,
+                            target_x,
+                            target_y,
+                            force_deposit: false,
+                        },
+                    );
+                    // Walk the gatherer to a free tile next to the resource
+                    let Some((ux, uy)) = gs.unit(player_id, uid).map(|u| (u.x, u.y)) else {
+                        continue;
+                    };
+                    if let Some((tile_x, tile_y)) = gs.adjacent_walkable(target_x, target_y, ux, uy)
+                    {
+                        if gs.path_unit_to(player_id, uid, (tile_x, tile_y)) {
+                            broadcast(
+                                tx,
+                                &GameMessage::UnitMove {
+                                    player_id,
+                                    unit_id: uid,
+                                    tile_x,
+                                    tile_y,
+                                },
+                            );
+                        }
+                    }
+                }
+            }
+            GameMessage::DepositNow { unit_ids } => {
+                if let Some(&bad) = unit_ids.iter().find(|&&uid| !gs.has_unit(player_id, uid)) {
+                    return Err(gs.unit_reject_reason(bad));
+                }
+                for uid in unit_ids {
+                    if let Some(task) = gs.gather_tasks.get_mut(&(player_id, uid)) {
+                        task.force_deposit = true;
+                    }
+                }
+            }
+            GameMessage::TrainUnit { building_id, kind } => {
+                // Only warrior supported for now
+                if UnitKind::from_u8(kind) != Some(UnitKind::Warrior) {
+                    return Err(RejectReason::InvalidKind);
+                }
+                let Some(b) = gs.find_building(player_id, building_id) else {
+                    let reason = if gs.buildings.iter().any(|b| b.id == building_id) {
+                        RejectReason::NotOwner
+                    } else {
+                        RejectReason::UnknownBuilding
+                    };
+                    return Err(reason);
+                };
+                let pop_cap = *gs.pop_cap.get(&player_id).unwrap_or(&default_pop_cap());
+                let pop_used = gs
+                    .units
+                    .get(&player_id)
+                    .map(|u| u.len() as i32)
+                    .unwrap_or(0);
+                if pop_used >= pop_cap {
+                    return Err(RejectReason::PopCapReached);
+                }
+                if !gs
+                    .resources
+                    .entry(player_id)
+                    .or_insert(default_resources())
+                    .spend(&gs.balance.costs.warrior)
+                {
+                    return Err(RejectReason::CannotAfford);
+                }
+
+                // Spawn next to the building
+                let spawn_x = b.tile_x as f32 * TILE_SIZE + TILE_SIZE;
+                let spawn_y = b.tile_y as f32 * TILE_SIZE + TILE_SIZE * 0.5;
+                let unit_id = gs.alloc_unit_id();
+                let unit = UnitState::new(
+                    unit_id,
+                    spawn_x,
+                    spawn_y,
+                    UnitKind::Warrior.to_u8(),
+                    gs.balance.hp.warrior,
+                );
+                let dto = unit.to_dto(player_id);
+                gs.units.entry(player_id).or_default().push(unit);
+
+                broadcast(tx, &GameMessage::UnitSpawned { unit: dto });
+                broadcast(tx, &gs.resource_update(player_id));
+            }
+            GameMessage::SpawnUnit => {
+                let (chunk_x, chunk_y) = gs
+                    .players
+                    .get(&player_id)
+                    .map(|p| (p.chunk_x, p.chunk_y))
+                    .unwrap_or((0, 0));
+                let unit_count = gs.units.get(&player_id).map(|u| u.len()).unwrap_or(0);
+                let pop_cap = *gs.pop_cap.get(&player_id).unwrap_or(&default_pop_cap());
+                if unit_count as i32 >= pop_cap {
+                    return Err(RejectReason::PopCapReached);
+                }
+                if !gs
+                    .resources
+                    .entry(player_id)
+                    .or_insert(default_resources())
+                    .spend(&gs.balance.costs.worker)
+                {
+                    return Err(RejectReason::CannotAfford);
+                }
+
+                // INSTANT SPAWN (no training delay), in rows of 3 below the Town Center
+                let chunk_size = 32.0;
+                let mid = chunk_size / 2.0;
+                let tc_tile_x = chunk_x as f32 * chunk_size + mid;
+                let tc_tile_y = chunk_y as f32 * chunk_size + mid;
+                let col = (unit_count % 3) as f32;
+                let row = (unit_count / 3) as f32;
+                let spawn_x = (tc_tile_x * TILE_SIZE) + (col * TILE_SIZE);
+                let spawn_y = (tc_tile_y * TILE_SIZE) + TILE_SIZE * 2.0 + (row * TILE_SIZE);
+
+                let unit_id = gs.alloc_unit_id();
+                let unit = UnitState::new(
+                    unit_id,
+                    spawn_x,
+                    spawn_y,
+                    UnitKind::Worker.to_u8(),
+                    gs.balance.hp.worker,
+                );
+                let dto = unit.to_dto(player_id);
+                gs.units.entry(player_id).or_default().push(unit);
+
+                broadcast(tx, &GameMessage::UnitSpawned { unit: dto });
+                // Resources already spent; include updated pop_used
+                broadcast(tx, &gs.resource_update(player_id));
+            }
+            GameMessage::DeleteUnit { unit_id } => {
+                let Some(removed) = gs.remove_unit(player_id, unit_id) else {
+                    return Err(gs.unit_reject_reason(unit_id));
+                };
+                if removed.kind == UnitKind::Worker.to_u8() {
+                    // Workers refund their food
+                    if let Some(res) = gs.resources.get_mut(&player_id) {
+                        res.food += gs.balance.costs.worker.food;
+                    }
+                }
+                broadcast(
+                    tx,
+                    &GameMessage::UnitDied {
+                        owner_id: player_id,
+                        unit_id,
+                    },
+                );
+                broadcast(tx, &gs.resource_update(player_id));
+            }
+            GameMessage::SetView {
+                chunk_x0,
+                chunk_y0,
+                chunk_x1,
+                chunk_y1,
+            } => {
+                if let Some(client) = self.clients.get_mut(&player_id) {
+                    client.view = View::new(chunk_x0, chunk_y0, chunk_x1, chunk_y1);
+                }
+            }
+            // Answered in `handle`, once the ack is queued
+            GameMessage::RequestResync => {}
+            GameMessage::DeleteBuilding { tile_x, tile_y } => {
+                let Some(idx) = gs
+                    .buildings
+                    .iter()
+                    .position(|b| b.tile_x == tile_x && b.tile_y == tile_y)
+                else {
+                    return Err(RejectReason::UnknownBuilding);
+                };
+                let b = gs.buildings[idx].clone();
+                if b.owner_id != player_id {
+                    return Err(RejectReason::NotOwner);
+                }
+                if b.kind == TOWN_CENTER_KIND {
+                    return Err(RejectReason::ProtectedBuilding);
+                }
+                gs.buildings.remove(idx);
+                if b.kind == BuildKind::House.to_kind_id() {
+                    let cap = gs.pop_cap.entry(player_id).or_insert(default_pop_cap());
+                    *cap = (*cap - POP_FROM_HOUSE).max(default_pop_cap());
+                }
+                let refund = gs.balance.build_cost(b.kind);
+                let entry = gs.resources.entry(player_id).or_insert(default_resources());
+                entry.wood += refund.wood;
+                entry.stone += refund.stone;
+                entry.gold += refund.gold;
+                entry.food += refund.food;
+
+                broadcast(tx, &GameMessage::BuildingDestroyed { tile_x, tile_y });
+                broadcast(tx, &gs.resource_update(player_id));
+            }
+            _ => return Err(RejectReason::NotACommand),
+        }
+        Ok(())
+    }
+
+    fn admin(&mut self, req: AdminRequest) -> Answer {
+        let ok = |message: String| Answer::Now(AdminResponse::Ok { message });
+        let err = |message: String| Answer::Now(AdminResponse::Error { message });
+
+        match req {
+            AdminRequest::ListPlayers => {
+                let mut ids: Vec<i32> = self.state.players.keys().copied().collect();
+                ids.sort_unstable();
+                let players = ids
+                    .into_iter()
+                    .filter_map(|pid| self.player_summary(pid))
+                    .collect();
+                Answer::Now(AdminResponse::Players { players })
+            }
+            AdminRequest::InspectPlayer { player_id } => {
+                let Some(player) = self.player_summary(player_id) else {
+                    return err(format!("No player {}", player_id));
+                };
+                let gs = &self.state;
+                let units = gs
+                    .units
+                    .get(&player_id)
+                    .map(|us| us.iter().map(|u| u.to_dto(player_id)).collect())
+                    .unwrap_or_default();
+                let buildings = gs
+                    .buildings
+                    .iter()
+                    .filter(|b| b.owner_id == player_id)
+                    .cloned()
+                    .collect();
+                Answer::Now(AdminResponse::Player {
+                    player,
+                    units,
+                    buildings,
+                })
+            }
+            AdminRequest::GrantResources { player_id, delta } => {
+                if !self.state.players.contains_key(&player_id) {
+                    return err(format!("No player {}", player_id));
+                }
+                if ![delta.wood, delta.stone, delta.gold, delta.food]
+                    .iter()
+                    .all(|v| v.is_finite())
+                {
+                    return err("Amounts must be finite numbers".to_string());
+                }
+                let res = self
+                    .state
+                    .resources
+                    .entry(player_id)
+                    .or_insert(default_resources());
+                res.wood = (res.wood + delta.wood).max(0.0);
+                res.stone = (res.stone + delta.stone).max(0.0);
+                res.gold = (res.gold + delta.gold).max(0.0);
+                res.food = (res.food + delta.food).max(0.0);
+                let now = *res;
+                broadcast(&self.tx, &self.state.resource_update(player_id));
+                ok(format!(
+                    "Player {} now has wood {:.0}, stone {:.0}, gold {:.0}, food {:.0}",
+                    player_id, now.wood, now.stone, now.gold, now.food
+                ))
+            }
+            AdminRequest::Kick { player_id } => {
+                if self.kick(player_id, "Disconnected by an administrator", true) {
+                    ok(format!("Kicked player {}", player_id))
+                } else {
+                    err(format!("Player {} is not connected", player_id))
+                }
+            }
+            AdminRequest::BanToken { token } => {
+                let player = self.state.tokens.get(&token).copied();
+                self.state.banned_tokens.insert(token);
+                match player {
+                    Some(pid) => {
+                        let kicked = self.kick(pid, "This account has been banned", false);
+                        ok(format!(
+                            "Banned token of player {}{}",
+                            pid,
+                            if kicked { " (kicked)" } else { "" }
+                        ))
+                    }
+                    None => ok("Banned token (no player uses it yet)".to_string()),
+                }
+            }
+            AdminRequest::SetLatestClient { version } => {
+                let told = self.announce_client(version);
+                // Sent now rather than with the next tick, like an admin kick
+                self.deliver();
+                ok(format!(
+                    "Latest client is now protocol {}; told {} connected clients",
+                    version, told
+                ))
+            }
+            AdminRequest::ResetWorld => {
+                let ids: Vec<i32> = self.clients.keys().copied().collect();
+                for pid in ids {
+                    self.kick(pid, "The world has been reset", true);
+                }
+                let seed = self.state.rng.gen();
+                self.state = GlobalState::new(self.state.balance.clone(), seed);
+                self.unit_chunks.clear();
+                let Some(writer) = &self.snapshots else {
+                    return ok("World reset".to_string());
+                };
+                Answer::AfterSave {
+                    saved: writer.save(self.state.snapshot_json()),
+                    ok: "World reset".to_string(),
+                    failed: "World reset, but saving the empty snapshot failed".to_string(),
+                }
+            }
+            AdminRequest::SaveSnapshot => {
+                let Some(writer) = &self.snapshots else {
+                    return err("Snapshots are disabled".to_string());
+                };
+                let path = writer.path().display().to_string();
+                Answer::AfterSave {
+                    saved: writer.save(self.state.snapshot_json()),
+                    ok: format!("Saved {}", path),
+                    failed: format!("Saving {} failed", path),
+                }
+            }
+        }
+    }
+
+    fn player_summary(&self, player_id: i32) -> Option<PlayerSummary> {
+        let gs = &self.state;
+        let p = gs.players.get(&player_id)?;
+        Some(PlayerSummary {
+            id: p.id,
+            chunk_x: p.chunk_x,
+            chunk_y: p.chunk_y,
+            online: self.clients.contains_key(&player_id),
+            resources: *gs.resources.get(&player_id).unwrap_or(&default_resources()),
+            units: gs.units.get(&player_id).map(|u| u.len()).unwrap_or(0),
+            buildings: gs
+                .buildings
+                .iter()
+                .filter(|b| b.owner_id == player_id)
+                .count(),
+        })
+    }
+
+    // Tells the client why and whether to come back, then drops its outbox, which closes the
+    // socket.
+    fn kick(&mut self, player_id: i32, reason: &str, reconnect: bool) -> bool {
+        let Some(client) = self.clients.remove(&player_id) else {
+            return false;
+        };
+        client.send(&GameMessage::Kicked {
+            reason: reason.to_string(),
+            reconnect,
+        });
+        client.deliver(self.tick_count, &self.metrics);
+        true
+    }
+
+    fn record(&self, entry: Entry) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&entry);
+        }
+    }
+
+    fn record_checksum(&mut self) {
+        if self.recorder.is_some() {
+            let digest = self.take_digest();
+            self.record(Entry::Checksum {
+                tick: self.tick_count,
+                events: digest.events,
+                hash: digest.hash,
+            });
+        }
+    }
+
+    /// Everything broadcast since the last call.
+    pub(crate) fn take_digest(&mut self) -> Digest {
+        self.tx.digest.take()
+    }
+
+    pub(crate) fn tick_count(&self) -> u64 {
+        self.tick_count
+    }
+
+    /// Re-applies a logged join, command, leave or admin change (see `replay::run`). Replayed
+    /// clients get outboxes nobody reads.
+    pub(crate) fn replay(&mut self, entry: Entry) {
+        match entry {
+            Entry::Join {
+                player_id, conn_id, ..
+            } => {
+                let gs = &mut self.state;
+                gs.next_id = gs.next_id.max(player_id + 1);
+                let (outbox, _) = mpsc::channel(1);
+                self.enter(player_id, String::new(), conn_id, Encoding::Json, outbox);
+                self.flush();
+                self.refresh_interest(player_id);
+            }
+            Entry::Command {
+                player_id,
+                seq,
+                msg,
+                ..
+            } => self.handle(SimCommand::Command {
+                player_id,
+                seq,
+                msg,
+            }),
+            Entry::Leave {
+                player_id, conn_id, ..
+            } => self.handle(SimCommand::Leave { player_id, conn_id }),
+            Entry::Admin { req, .. } => {
+                let _ = self.admin(req);
+            }
+            Entry::Start { .. } | Entry::Checksum { .. } => {}
+        }
+    }
+
+    pub(crate) fn tick(&mut self) {
+        self.tick_count += 1;
+        let tick_count = self.tick_count;
+        if tick_count.is_multiple_of(150) {
+            debug!(tick = tick_count, "Game loop alive");
+        }
+
+        for event in step(&mut self.state, TICK_MS as f32 / 1000.0) {
+            broadcast(&self.tx, &event.into_message());
+        }
+        self.flush();
+        let ids: Vec<i32> = self.clients.keys().copied().collect();
+        for pid in ids {
+            // Wait until a lagging client has drained half its queue, so the FullState fits
+            let caught_up = self
+                .clients
+                .get(&pid)
+                .is_some_and(|c| c.lagged.get() && c.outbox.capacity() >= OUTBOX / 2);
+            if caught_up {
+                warn!(player_id = pid, "Client fell behind; resending full state");
+                self.resync(pid);
+            } else {
+                self.refresh_interest(pid);
+            }
+        }
+        self.deliver();
+    }
+
+    // The issuer of a command hears its ack and effects right away, along with anything else
+    // queued for it; everyone else gets them in the tick's frame.
+    fn deliver_to(&self, player_id: i32) {
+        if let Some(client) = self.clients.get(&player_id) {
+            client.deliver(self.tick_count, &self.metrics);
+        }
+    }
+
+    // One frame per client for everything queued since the last delivery.
+    fn deliver(&self) {
+        for client in self.clients.values() {
+            client.deliver(self.tick_count, &self.metrics);
+        }
+    }
+
+    // Queues everything broadcast since the last flush for the clients it concerns.
+    fn flush(&mut self) {
+        let pending = self.tx.pending.take();
+        for out in pending {
+            match &out.msg {
+                GameMessage::UnitSync {
+                    player_id,
+                    unit_id,
+                    x,
+                    y,
+                } => {
+                    let to = interest::pos_chunk(*x, *y);
+                    let from = self.unit_chunks.insert(*unit_id, to);
+                    self.route_unit_sync(&out, *player_id, *unit_id, from, to);
+                    continue;
+                }
+                GameMessage::NewPlayer { player, units } if !units.is_empty() => {
+                    // Only clients that can see the new base get its units
+                    let home = (player.chunk_x, player.chunk_y);
+                    let bare = Arc::new(Outgoing {
+                        msg: GameMessage::NewPlayer {
+                            player: player.clone(),
+                            units: Vec::new(),
+                        },
+                        json: None,
+                    });
+                    for (&pid, client) in &self.clients {
+                        if pid == player.id || client.interest.contains(&home) {
+                            client.forward(&out);
+                        } else {
+                            client.forward(&bare);
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let audience = interest::audience(&out.msg, &self.unit_chunks);
+            for (&pid, client) in &self.clients {
+                if audience.includes(pid, &client.interest) {
+                    client.forward(&out);
+                }
+            }
+            match &out.msg {
+                GameMessage::UnitSpawned { unit } => {
+                    self.unit_chunks
+                        .insert(unit.unit_id, interest::pos_chunk(unit.x, unit.y));
+                }
+                GameMessage::UnitDied { unit_id, .. } => {
+                    self.unit_chunks.remove(unit_id);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // A unit crossing a chunk border appears (UnitEnter) or disappears (UnitLeave) for clients
+    // that see only one side of it. Its owner always gets the plain sync.
+    fn route_unit_sync(
+        &self,
+        sync: &Arc<Outgoing>,
+        owner: i32,
+        unit_id: u64,
+        from: Option<Chunk>,
+        to: Chunk,
+    ) {
+        let mut enter = None;
+        let mut leave = None;
+        for (&pid, client) in &self.clients {
+            let saw = from.is_some_and(|c| client.interest.contains(&c));
+            let sees = client.interest.contains(&to);
+            if pid == owner || (saw && sees) {
+                client.forward(sync);
+            } else if sees {
+                let entered = enter.get_or_insert_with(|| {
+                    let unit = self.state.unit(owner, unit_id).map(|u| u.to_dto(owner));
+                    unit.map(|unit| {
+                        Arc::new(Outgoing {
+                            msg: GameMessage::UnitEnter { unit },
+                            json: None,
+                        })
+                    })
+                });
+                if let Some(entered) = entered {
+                    client.forward(entered);
+                }
+            } else if saw {
+                client.forward(leave.get_or_insert_with(|| {
+                    Arc::new(Outgoing {
+                        msg: GameMessage::UnitLeave {
+                            owner_id: owner,
+                            unit_id,
+                        },
+                        json: None,
+                    })
+                }));
+            }
+        }
+    }
+
+    // Recomputes which chunks `player_id` receives and sends the difference.
+    fn refresh_interest(&mut self, player_id: i32) {
+        let Some(client) = self.clients.get(&player_id) else {
+            return;
+        };
+        if client.lagged.get() {
+            return;
+        }
+        let now = interest::interest(&self.state, player_id, client.view);
+        if now == client.interest {
+            return;
+        }
+        for &(chunk_x, chunk_y) in client.interest.difference(&now) {
+            client.send(&GameMessage::ChunkLeave { chunk_x, chunk_y });
+        }
+        for &chunk in now.difference(&client.interest) {
+            client.send(&interest::chunk_enter(&self.state, chunk));
+        }
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            client.interest = now;
+        }
+    }
+
+    // Sends `player_id` everything it can see in one FullState, replacing whatever it has.
+    fn resync(&mut self, player_id: i32) {
+        let Some(client) = self.clients.get_mut(&player_id) else {
+            return;
+        };
+        let gs = &self.state;
+        let chunks = interest::interest(gs, player_id, client.view);
+        let mut units = Vec::new();
+        let mut buildings = Vec::new();
+        let mut sites = Vec::new();
+        for &chunk in &chunks {
+            if let GameMessage::ChunkEnter {
+                units: u,
+                buildings: b,
+                sites: s,
+                ..
+            } = interest::chunk_enter(gs, chunk)
+            {
+                units.extend(u);
+                buildings.extend(b);
+                sites.extend(s);
+            }
+        }
+
+        client.lagged.set(false);
+        client.interest = chunks;
+        client.send(&GameMessage::FullState {
+            players: gs.players.values().cloned().collect(),
+            units,
+            buildings,
+            sites,
+            resources: *gs.resources.get(&player_id).unwrap_or(&default_resources()),
+            pop_cap: *gs.pop_cap.get(&player_id).unwrap_or(&default_pop_cap()),
+            pop_used: gs
+                .units
+                .get(&player_id)
+                .map(|u| u.len() as i32)
+                .unwrap_or(0),
+        });
+    }
+}
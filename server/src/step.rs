@@ -0,0 +1,1078 @@
+//! One simulation step with no I/O. `step` advances building, training, movement, gathering and
+//! combat by `dt` seconds and reports what changed as `GameEvent`s; the simulation actor turns
+//! those into broadcasts. Nothing here touches sockets or channels, so it runs in unit tests.
+
+use std::collections::HashMap;
+
+use protocol::{BuildKind, BuildingDTO, GameMessage, ResourceKind, Resources, UnitDTO, UnitKind};
+
+use crate::{
+    default_pop_cap, default_resources, dropoff_near, BuildTask, GatherTask, GlobalState,
+    ResourceNode, TrainTask, UnitState, POP_FROM_HOUSE, TICK_MS, TILE_SIZE, WARRIOR_RANGE,
+};
+
+/// Something a step changed that clients need to hear about. Each maps onto the server -> client
+/// `GameMessage` of the same name.
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+    // `progress` of -1.0 means the site was abandoned
+    BuildProgress {
+        tile_x: i32,
+        tile_y: i32,
+        kind: u8,
+        progress: f32,
+    },
+    BuildingSpawned {
+        building: BuildingDTO,
+    },
+    UnitSpawned {
+        unit: UnitDTO,
+    },
+    UnitSync {
+        player_id: i32,
+        unit_id: u64,
+        x: f32,
+        y: f32,
+    },
+    UnitCarry {
+        owner_id: i32,
+        unit_id: u64,
+        carry_wood: f32,
+        carry_stone: f32,
+        carry_gold: f32,
+        carry_food: f32,
+    },
+    ResourceUpdate {
+        player_id: i32,
+        resources: Resources,
+        pop_cap: i32,
+        pop_used: i32,
+    },
+    UnitHp {
+        owner_id: i32,
+        unit_id: u64,
+        hp: f32,
+    },
+    UnitDied {
+        owner_id: i32,
+        unit_id: u64,
+    },
+    BuildingHp {
+        tile_x: i32,
+        tile_y: i32,
+        hp: f32,
+    },
+    BuildingDestroyed {
+        tile_x: i32,
+        tile_y: i32,
+    },
+    TowerShot {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+    },
+}
+
+impl GameEvent {
+    pub fn into_message(self) -> GameMessage {
+        match self {
+            GameEvent::BuildProgress {
+                tile_x,
+                tile_y,
+                kind,
+                progress,
+            } => GameMessage::BuildProgress {
+                tile_x,
+                tile_y,
+                kind,
+                progress,
+            },
+            GameEvent::BuildingSpawned { building } => GameMessage::BuildingSpawned { building },
+            GameEvent::UnitSpawned { unit } => GameMessage::UnitSpawned { unit },
+            GameEvent::UnitSync {
+                player_id,
+                unit_id,
+                x,
+                y,
+            } => GameMessage::UnitSync {
+                player_id,
+                unit_id,
+                x,
+                y,
+            },
+            GameEvent::UnitCarry {
+                owner_id,
+                unit_id,
+                carry_wood,
+                carry_stone,
+                carry_gold,
+                carry_food,
+            } => GameMessage::UnitCarry {
+                owner_id,
+                unit_id,
+                carry_wood,
+                carry_stone,
+                carry_gold,
+                carry_food,
+            },
+            GameEvent::ResourceUpdate {
+                player_id,
+                resources,
+                pop_cap,
+                pop_used,
+            } => GameMessage::ResourceUpdate {
+                player_id,
+                resources,
+                pop_cap,
+                pop_used,
+            },
+            GameEvent::UnitHp {
+                owner_id,
+                unit_id,
+                hp,
+            } => GameMessage::UnitHp {
+                owner_id,
+                unit_id,
+                hp,
+            },
+            GameEvent::UnitDied { owner_id, unit_id } => {
+                GameMessage::UnitDied { owner_id, unit_id }
+            }
+            GameEvent::BuildingHp { tile_x, tile_y, hp } => {
+                GameMessage::BuildingHp { tile_x, tile_y, hp }
+            }
+            GameEvent::BuildingDestroyed { tile_x, tile_y } => {
+                GameMessage::BuildingDestroyed { tile_x, tile_y }
+            }
+            GameEvent::TowerShot { x1, y1, x2, y2 } => GameMessage::TowerShot { x1, y1, x2, y2 },
+        }
+    }
+}
+
+pub fn step(gs: &mut GlobalState, dt: f32) -> Vec<GameEvent> {
+    let mut events = Vec::new();
+    // Copied out so the per-unit loops below can read it while mutating `gs`
+    let balance = gs.balance.clone();
+    // Rates below are tuned per 200ms server tick; scale them to the step length
+    let ticks = dt * 1000.0 / TICK_MS as f32;
+
+    let mut to_spawn: Vec<BuildTask> = Vec::new();
+    let mut to_spawn_units: Vec<TrainTask> = Vec::new();
+    let mut resource_updates: Vec<(i32, Resources, i32, i32)> = Vec::new();
+    let mut shots: Vec<(f32, f32, f32, f32, i32)> = Vec::new(); // shot with owner
+    let mut unit_hp_updates: Vec<(i32, u64, f32)> = Vec::new();
+    let mut unit_deaths: Vec<(i32, u64)> = Vec::new();
+    let mut pop_updates: Vec<i32> = Vec::new(); // owners needing pop recount
+    let mut building_hp_updates: Vec<(i32, i32, f32)> = Vec::new();
+    let mut building_deaths: Vec<(i32, i32, i32)> = Vec::new(); // tile_x, tile_y, owner
+    let mut canceled_builds: Vec<BuildTask> = Vec::new();
+
+    // Snapshot phase
+    let gather_tasks: Vec<(i32, u64, GatherTask)>;
+    let units_snapshot: Vec<(i32, u64, f32, f32, u8)>;
+    let buildings_snapshot: Vec<(usize, i32, i32, i32, f32, u8)>;
+    let towers_snapshot: Vec<(i32, f32, f32)>;
+    {
+        let mut finished = Vec::new();
+        // Snapshot worker positions to avoid borrowing conflicts while mutating build progress.
+        let mut worker_positions: HashMap<i32, Vec<(f32, f32)>> = HashMap::new();
+        for (owner, units) in gs.units.iter() {
+            for u in units {
+                if u.kind == UnitKind::Worker.to_u8() {
+                    worker_positions.entry(*owner).or_default().push((u.x, u.y));
+                }
+            }
+        }
+
+        for (key, task) in gs.building_progress.iter_mut() {
+            // Require at least one friendly worker near the build site to advance progress.
+            let bx = task.tile_x as f32 * TILE_SIZE + TILE_SIZE / 2.0;
+            let by = task.tile_y as f32 * TILE_SIZE + TILE_SIZE / 2.0;
+            let mut worker_count = 0usize;
+            if let Some(units) = worker_positions.get(&task.owner_id) {
+                for (ux, uy) in units {
+                    let dx = *ux - bx;
+                    let dy = *uy - by;
+                    // Allow ~1.5 tiles radius for building
+                    if (dx * dx + dy * dy) <= (TILE_SIZE * 1.5).powi(2) {
+                        worker_count += 1;
+                    }
+                }
+            }
+            if worker_count == 0 {
+                // No worker in range; cancel wall build and refund
+                if task.kind == BuildKind::Wall.to_kind_id() {
+                    // Notify clients to clear progress
+                    events.push(GameEvent::BuildProgress {
+                        tile_x: task.tile_x,
+                        tile_y: task.tile_y,
+                        kind: task.kind,
+                        progress: -1.0,
+                    });
+                    canceled_builds.push(*task);
+                    finished.push(*key); // remove from progress map
+                }
+                continue;
+            }
+
+            // Scale progress with nearby workers (diminishing cap at 4 workers)
+            let effective_workers = worker_count.min(4) as f32;
+            task.progress += 0.12 * effective_workers * ticks; // base ~1.6s per worker, faster with helpers
+            events.push(GameEvent::BuildProgress {
+                tile_x: task.tile_x,
+                tile_y: task.tile_y,
+                kind: task.kind,
+                progress: task.progress.min(1.0),
+            });
+            if task.progress >= 1.0 {
+                finished.push(*key);
+                to_spawn.push(*task);
+            }
+        }
+        for k in finished {
+            gs.building_progress.remove(&k);
+        }
+
+        // Unit Training Progress
+        let mut finished_train = Vec::new();
+        for (idx, task) in gs.training_tasks.iter_mut().enumerate() {
+            task.progress += 0.05 * ticks; // 4s training
+            if task.progress >= 1.0 {
+                finished_train.push(idx);
+                to_spawn_units.push(*task);
+            }
+        }
+        for idx in finished_train.iter().rev() {
+            gs.training_tasks.remove(*idx);
+        }
+
+        gather_tasks = gs
+            .gather_tasks
+            .iter()
+            .map(|((owner, uid), g)| (*owner, *uid, *g))
+            .collect();
+        units_snapshot = gs
+            .units
+            .iter()
+            .flat_map(|(owner, us)| us.iter().map(move |u| (*owner, u.id, u.x, u.y, u.kind)))
+            .collect();
+        buildings_snapshot = gs
+            .buildings
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i, b.owner_id, b.tile_x, b.tile_y, b.hp, b.kind))
+            .collect();
+        towers_snapshot = gs
+            .buildings
+            .iter()
+            .filter(|b| b.kind == BuildKind::Tower.to_kind_id())
+            .map(|b| {
+                (
+                    b.owner_id,
+                    b.tile_x as f32 * 16.0 + 8.0,
+                    b.tile_y as f32 * 16.0 + 8.0,
+                )
+            })
+            .collect();
+    }
+
+    // Movement tick (server authoritative positions)
+    {
+        let moved = gs.advance_units(dt);
+        for (owner, id, x, y) in moved {
+            events.push(GameEvent::UnitSync {
+                player_id: owner,
+                unit_id: id,
+                x,
+                y,
+            });
+        }
+    }
+
+    // Gathering tick (carry + deposit)
+    {
+        for (owner, uid, gtask) in gather_tasks {
+            // Nodes only exist where the terrain (or a farm) has the resource; an order pointing
+            // anywhere else, such as a destroyed farm, is dropped
+            let key = (gtask.target_x, gtask.target_y);
+            let resource = ResourceKind::from_kind_id(gtask.kind);
+            let Some(resource) = resource.filter(|&r| gs.is_gather_target(r, key.0, key.1)) else {
+                gs.gather_tasks.remove(&(owner, uid));
+                continue;
+            };
+            // Ensure resource node exists and get remaining (scope 1)
+            let mut node_remaining = {
+                let entry = gs.resource_nodes.entry(key).or_insert(ResourceNode {
+                    kind: gtask.kind,
+                    remaining: resource.amount(&balance.node_amount),
+                });
+                entry.remaining
+            };
+
+            // Snapshot unit state (immutable) to avoid overlapping borrows
+            let (ux, uy, gathered_kind, mut c_wood, mut c_stone, mut c_gold, mut c_food) = {
+                let Some(u) = gs.unit(owner, uid) else {
+                    continue;
+                };
+                if u.kind != UnitKind::Worker.to_u8() {
+                    continue;
+                } // only workers gather
+                (
+                    u.x,
+                    u.y,
+                    resource,
+                    u.carry_wood,
+                    u.carry_stone,
+                    u.carry_gold,
+                    u.carry_food,
+                )
+            };
+
+            // Distance to target for gather range
+            let tx = gtask.target_x as f32 * TILE_SIZE + TILE_SIZE / 2.0;
+            let ty = gtask.target_y as f32 * TILE_SIZE + TILE_SIZE / 2.0;
+            let dx = ux - tx;
+            let dy = uy - ty;
+            let in_gather_range = (dx * dx + dy * dy) <= (TILE_SIZE * 2.5).powi(2);
+
+            let total_carry_now = c_wood + c_stone + c_gold + c_food;
+            let force_deposit = gtask.force_deposit;
+            // Partial deposit if at dropoff; allow manual deposit even when still in gather range
+            let at_drop = dropoff_near(gs, owner, ux, uy, resource);
+            if at_drop && total_carry_now > 0.0 && (force_deposit || !in_gather_range) {
+                if let Some(u) = gs.unit_mut(owner, uid) {
+                    let dw = u.carry_wood;
+                    let ds = u.carry_stone;
+                    let dg = u.carry_gold;
+                    let df = u.carry_food;
+                    if (dw + ds + dg + df) > 0.0 {
+                        u.carry_wood = 0.0;
+                        u.carry_stone = 0.0;
+                        u.carry_gold = 0.0;
+                        u.carry_food = 0.0;
+                        let entry = gs.resources.entry(owner).or_insert(default_resources());
+                        entry.wood += dw;
+                        entry.stone += ds;
+                        entry.gold += dg;
+                        entry.food += df;
+                        let res_snapshot = *entry;
+                        let pop_cap = *gs.pop_cap.get(&owner).unwrap_or(&default_pop_cap());
+                        let pop_used = gs.units.get(&owner).map(|u2| u2.len() as i32).unwrap_or(0);
+                        events.push(GameEvent::UnitCarry {
+                            owner_id: owner,
+                            unit_id: uid,
+                            carry_wood: 0.0,
+                            carry_stone: 0.0,
+                            carry_gold: 0.0,
+                            carry_food: 0.0,
+                        });
+                        resource_updates.push((owner, res_snapshot, pop_cap, pop_used));
+                        if force_deposit {
+                            if let Some(task) = gs.gather_tasks.get_mut(&(owner, uid)) {
+                                task.force_deposit = false;
+                            }
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            // No early deposit beyond the above; main deposit happens when full/node-empty and near dropoff
+
+            // Must be near the target to gather (lenient radius)
+            if !in_gather_range {
+                continue;
+            }
+
+            // Re-borrow unit mutably for gathering updates
+            let Some(u) = gs.unit_mut(owner, uid) else {
+                continue;
+            };
+
+            // Choose carry target and rate (fast per-tick gather)
+            {
+                let target = match resource {
+                    ResourceKind::Wood => &mut c_wood,
+                    ResourceKind::Stone => &mut c_stone,
+                    ResourceKind::Gold => &mut c_gold,
+                    ResourceKind::Food => &mut c_food,
+                };
+                let rate = resource.amount(&balance.gather_per_tick);
+                let room = (balance.carry_cap - *target).max(0.0);
+                if room > 0.0 && node_remaining > 0.0 {
+                    let amt = (rate * ticks).min(room).min(node_remaining);
+                    *target += amt;
+                    node_remaining -= amt;
+                }
+            }
+
+            // Write back carries to unit
+            u.carry_wood = c_wood;
+            u.carry_stone = c_stone;
+            u.carry_gold = c_gold;
+            u.carry_food = c_food;
+
+            // Deposit (scope 3) after unit borrow released
+            let total_carry = c_wood + c_stone + c_gold + c_food;
+            let carried = match gathered_kind {
+                ResourceKind::Wood => c_wood,
+                ResourceKind::Stone => c_stone,
+                ResourceKind::Gold => c_gold,
+                ResourceKind::Food => c_food,
+            };
+            let target_filled = carried >= balance.carry_cap;
+            let mut carry_changed = false;
+            let at_dropoff = dropoff_near(gs, owner, ux, uy, gathered_kind);
+            // Deposit only when full (or node empty) AND near dropoff
+            let should_deposit =
+                total_carry > 0.0 && (target_filled || node_remaining <= 0.0) && at_dropoff;
+            if should_deposit {
+                let entry = gs.resources.entry(owner).or_insert(default_resources());
+                entry.wood += c_wood;
+                entry.stone += c_stone;
+                entry.gold += c_gold;
+                entry.food += c_food;
+                c_wood = 0.0;
+                c_stone = 0.0;
+                c_gold = 0.0;
+                c_food = 0.0;
+                // Empty the unit too, or it deposits the same load again next step
+                if let Some(u) = gs.unit_mut(owner, uid) {
+                    u.carry_wood = 0.0;
+                    u.carry_stone = 0.0;
+                    u.carry_gold = 0.0;
+                    u.carry_food = 0.0;
+                }
+                carry_changed = true;
+            } else if total_carry > 0.0 {
+                carry_changed = true;
+            }
+
+            // Update node (scope 4)
+            if node_remaining <= 0.0 {
+                gs.resource_nodes.remove(&key);
+                gs.gather_tasks.remove(&(owner, uid));
+            } else if let Some(entry) = gs.resource_nodes.get_mut(&key) {
+                entry.remaining = node_remaining;
+            }
+
+            if carry_changed {
+                events.push(GameEvent::UnitCarry {
+                    owner_id: owner,
+                    unit_id: uid,
+                    carry_wood: c_wood,
+                    carry_stone: c_stone,
+                    carry_gold: c_gold,
+                    carry_food: c_food,
+                });
+            }
+
+            let res_snapshot = *gs.resources.get(&owner).unwrap_or(&default_resources());
+            let pop_cap = *gs.pop_cap.get(&owner).unwrap_or(&default_pop_cap());
+            let pop_used = gs.units.get(&owner).map(|u2| u2.len() as i32).unwrap_or(0);
+            resource_updates.push((owner, res_snapshot, pop_cap, pop_used));
+        }
+    }
+
+    // Warrior targeting using snapshots
+    let mut unit_damage: Vec<(i32, u64, f32)> = Vec::new();
+    let mut building_damage: Vec<(usize, f32)> = Vec::new();
+    for (owner, _idx, ux, uy, kind) in &units_snapshot {
+        if *kind != UnitKind::Warrior.to_u8() {
+            continue;
+        }
+        let mut best_unit: Option<(i32, u64, f32)> = None;
+        for (opid, oidx, ox, oy, _ok) in &units_snapshot {
+            if *opid == *owner {
+                continue;
+            }
+            let dx = ox - ux;
+            let dy = oy - uy;
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist < WARRIOR_RANGE && (best_unit.is_none() || dist < best_unit.unwrap().2) {
+                best_unit = Some((*opid, *oidx, dist));
+            }
+        }
+        if let Some((opid, oidx, _)) = best_unit {
+            unit_damage.push((opid, oidx, balance.warrior_dps * dt));
+            continue;
+        }
+        let mut best_build: Option<(usize, f32)> = None;
+        for (bidx, bowner, bx, by, _bhp, _bkind) in &buildings_snapshot {
+            if *bowner == *owner {
+                continue;
+            }
+            let dx = *bx as f32 * 16.0 + 8.0 - ux;
+            let dy = *by as f32 * 16.0 + 8.0 - uy;
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist < WARRIOR_RANGE && (best_build.is_none() || dist < best_build.unwrap().1) {
+                best_build = Some((*bidx, dist));
+            }
+        }
+        if let Some((bidx, _)) = best_build {
+            building_damage.push((bidx, balance.warrior_dps * dt));
+        }
+    }
+
+    // Apply warrior damage
+    {
+        for (pid, id, dmg) in unit_damage {
+            let Some(u) = gs.unit_mut(pid, id) else {
+                continue;
+            };
+            u.hp -= dmg;
+            if u.hp <= 0.0 {
+                gs.remove_unit(pid, id);
+                unit_deaths.push((pid, id));
+                pop_updates.push(pid);
+            } else {
+                unit_hp_updates.push((pid, id, u.hp));
+            }
+        }
+        if !building_damage.is_empty() {
+            building_damage.sort_by_key(|(idx, _)| std::cmp::Reverse(*idx));
+            for (bidx, dmg) in building_damage {
+                if bidx < gs.buildings.len() {
+                    let b = &mut gs.buildings[bidx];
+                    b.hp -= dmg;
+                    if b.hp <= 0.0 {
+                        let dead = gs.buildings.remove(bidx);
+                        building_deaths.push((dead.tile_x, dead.tile_y, dead.owner_id));
+                        if dead.kind == BuildKind::House.to_kind_id() {
+                            let cap = gs.pop_cap.entry(dead.owner_id).or_insert(default_pop_cap());
+                            *cap = (*cap - POP_FROM_HOUSE).max(default_pop_cap());
+                        }
+                    } else {
+                        building_hp_updates.push((b.tile_x, b.tile_y, b.hp));
+                    }
+                }
+            }
+        }
+    }
+
+    // Tower shots using snapshots
+    for (owner, tx, ty) in towers_snapshot {
+        let mut best: Option<(f32, f32, f32)> = None; // dist, x, y
+        for (pid, _idx, ux, uy, _kind) in &units_snapshot {
+            if pid == &owner {
+                continue;
+            }
+            let dx = ux - tx;
+            let dy = uy - ty;
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist < 120.0 && best.is_none_or(|(bd, _, _)| dist < bd) {
+                best = Some((dist, *ux, *uy));
+            }
+        }
+        if let Some((_d, txp, typ)) = best {
+            shots.push((tx, ty, txp, typ, owner));
+        }
+    }
+
+    for task in to_spawn {
+        // Report building spawn (memory-only ID)
+        let id = gs.alloc_building_id();
+
+        events.push(GameEvent::BuildingSpawned {
+            building: BuildingDTO {
+                id,
+                owner_id: task.owner_id,
+                kind: task.kind,
+                tile_x: task.tile_x,
+                tile_y: task.tile_y,
+                hp: gs.balance.building_hp(task.kind),
+            },
+        });
+
+        // Cache building
+        gs.buildings.push(BuildingDTO {
+            id,
+            owner_id: task.owner_id,
+            kind: task.kind,
+            tile_x: task.tile_x,
+            tile_y: task.tile_y,
+            hp: gs.balance.building_hp(task.kind),
+        });
+    }
+
+    // Refund canceled builds (no worker present). Only walls are canceled above, but handle generically.
+    for task in canceled_builds {
+        let cost = gs.balance.build_cost(task.kind);
+        {
+            let entry = gs
+                .resources
+                .entry(task.owner_id)
+                .or_insert(default_resources());
+            entry.wood += cost.wood;
+            entry.stone += cost.stone;
+            entry.gold += cost.gold;
+            entry.food += cost.food;
+            gs.building_progress.remove(&(task.tile_x, task.tile_y));
+        }
+        let res = *gs
+            .resources
+            .get(&task.owner_id)
+            .unwrap_or(&default_resources());
+        let pop_cap = *gs.pop_cap.get(&task.owner_id).unwrap_or(&default_pop_cap());
+        let pop_used = gs
+            .units
+            .get(&task.owner_id)
+            .map(|u| u.len() as i32)
+            .unwrap_or(0);
+        events.push(GameEvent::ResourceUpdate {
+            player_id: task.owner_id,
+            resources: res,
+            pop_cap,
+            pop_used,
+        });
+    }
+
+    for task in to_spawn_units {
+        let unit_id = gs.alloc_unit_id();
+        let units = gs.units.entry(task.owner_id).or_default();
+        let next_idx = units.len();
+
+        let tile_size = 16.0;
+        let chunk_size = 32.0;
+        let mid = chunk_size / 2.0;
+        let tc_tile_x = task.chunk_x as f32 * chunk_size + mid;
+        let tc_tile_y = task.chunk_y as f32 * chunk_size + mid;
+
+        let col = (next_idx % 3) as f32;
+        let row = (next_idx / 3) as f32;
+        let spawn_x = (tc_tile_x * tile_size) + (col * tile_size);
+        let spawn_y = (tc_tile_y * tile_size) + tile_size * 2.0 + (row * tile_size);
+
+        // Update Memory
+        units.push(UnitState::new(
+            unit_id,
+            spawn_x,
+            spawn_y,
+            task.kind,
+            gs.balance.unit_hp(task.kind),
+        ));
+
+        // Report
+        events.push(GameEvent::UnitSpawned {
+            unit: UnitDTO {
+                owner_id: task.owner_id,
+                unit_id,
+                x: spawn_x,
+                y: spawn_y,
+                kind: task.kind,
+                hp: gs.balance.unit_hp(task.kind),
+                carry_wood: 0.0,
+                carry_stone: 0.0,
+                carry_gold: 0.0,
+                carry_food: 0.0,
+            },
+        });
+    }
+
+    for (pid, res, cap, used) in resource_updates {
+        events.push(GameEvent::ResourceUpdate {
+            player_id: pid,
+            resources: res,
+            pop_cap: cap,
+            pop_used: used,
+        });
+    }
+
+    for (pid, id, hp) in unit_hp_updates {
+        events.push(GameEvent::UnitHp {
+            owner_id: pid,
+            unit_id: id,
+            hp,
+        });
+    }
+    for (pid, id) in unit_deaths {
+        events.push(GameEvent::UnitDied {
+            owner_id: pid,
+            unit_id: id,
+        });
+        pop_updates.push(pid);
+    }
+    // Pop/resource updates after deaths
+    {
+        {
+            for owner in pop_updates {
+                let pop_used = gs.units.get(&owner).map(|u| u.len() as i32).unwrap_or(0);
+                let pop_cap = *gs.pop_cap.get(&owner).unwrap_or(&default_pop_cap());
+                let res = *gs.resources.get(&owner).unwrap_or(&default_resources());
+                events.push(GameEvent::ResourceUpdate {
+                    player_id: owner,
+                    resources: res,
+                    pop_cap,
+                    pop_used,
+                });
+            }
+        }
+    }
+    for (txi, tyi, hp) in building_hp_updates {
+        events.push(GameEvent::BuildingHp {
+            tile_x: txi,
+            tile_y: tyi,
+            hp,
+        });
+    }
+    for (txi, tyi, _owner) in building_deaths {
+        events.push(GameEvent::BuildingDestroyed {
+            tile_x: txi,
+            tile_y: tyi,
+        });
+    }
+
+    for (sx, sy, txp, typ, owner) in shots {
+        // Apply damage to nearest target (units prioritized)
+        let mut hit_unit: Option<(i32, u64)> = None;
+        let mut hit_building: Option<usize> = None;
+        let mut best_dist = 999999.0;
+        for (pid, units) in gs.units.iter_mut() {
+            if *pid == owner {
+                continue;
+            }
+            for u in units.iter_mut() {
+                let dx = u.x - txp;
+                let dy = u.y - typ;
+                let dist = (dx * dx + dy * dy).sqrt();
+                if dist < 16.0 && dist < best_dist {
+                    best_dist = dist;
+                    hit_unit = Some((*pid, u.id));
+                }
+            }
+        }
+        if hit_unit.is_none() {
+            for (idx, b2) in gs.buildings.iter_mut().enumerate() {
+                let dx = b2.tile_x as f32 * 16.0 + 8.0 - txp;
+                let dy = b2.tile_y as f32 * 16.0 + 8.0 - typ;
+                let dist = (dx * dx + dy * dy).sqrt();
+                if dist < 16.0 && dist < best_dist && b2.owner_id != owner {
+                    best_dist = dist;
+                    hit_building = Some(idx);
+                }
+            }
+        }
+
+        if let Some((pid, id)) = hit_unit {
+            if let Some(u) = gs.unit_mut(pid, id) {
+                u.hp -= balance.tower_damage * ticks;
+                let hp = u.hp;
+                events.push(GameEvent::UnitHp {
+                    owner_id: pid,
+                    unit_id: id,
+                    hp,
+                });
+                if hp <= 0.0 {
+                    gs.remove_unit(pid, id);
+                    events.push(GameEvent::UnitDied {
+                        owner_id: pid,
+                        unit_id: id,
+                    });
+                }
+            }
+        } else if let Some(idx) = hit_building {
+            if idx < gs.buildings.len() {
+                let b = &mut gs.buildings[idx];
+                b.hp -= balance.tower_damage * ticks;
+                events.push(GameEvent::BuildingHp {
+                    tile_x: b.tile_x,
+                    tile_y: b.tile_y,
+                    hp: b.hp,
+                });
+                if b.hp <= 0.0 {
+                    let dead = gs.buildings.remove(idx);
+                    events.push(GameEvent::BuildingDestroyed {
+                        tile_x: dead.tile_x,
+                        tile_y: dead.tile_y,
+                    });
+                    if dead.kind == BuildKind::House.to_kind_id() {
+                        let cap = gs.pop_cap.entry(dead.owner_id).or_insert(default_pop_cap());
+                        *cap = (*cap - POP_FROM_HOUSE).max(default_pop_cap());
+                    }
+                }
+            }
+        }
+
+        events.push(GameEvent::TowerShot {
+            x1: sx,
+            y1: sy,
+            x2: txp,
+            y2: typ,
+        });
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::Balance;
+
+    const DT: f32 = TICK_MS as f32 / 1000.0;
+
+    fn world() -> GlobalState {
+        GlobalState::new(Balance::default(), 0)
+    }
+
+    fn center(tile: i32) -> f32 {
+        tile as f32 * TILE_SIZE + TILE_SIZE / 2.0
+    }
+
+    fn add_unit(gs: &mut GlobalState, owner: i32, kind: u8, x: f32, y: f32) -> u64 {
+        let id = gs.alloc_unit_id();
+        let hp = gs.balance.unit_hp(kind);
+        gs.units
+            .entry(owner)
+            .or_default()
+            .push(UnitState::new(id, x, y, kind, hp));
+        id
+    }
+
+    fn add_building(gs: &mut GlobalState, owner: i32, kind: u8, tile_x: i32, tile_y: i32) {
+        let hp = gs.balance.building_hp(kind);
+        gs.buildings.push(BuildingDTO {
+            id: gs.buildings.len() as i32 + 1,
+            owner_id: owner,
+            kind,
+            tile_x,
+            tile_y,
+            hp,
+        });
+    }
+
+    // Forest tiles in chunk (0, 0), near the grass at (20, 20)
+    const FOREST: (i32, i32) = (21, 19);
+    const FOREST_EAST: (i32, i32) = (23, 20);
+
+    fn run(gs: &mut GlobalState, steps: usize) -> Vec<GameEvent> {
+        (0..steps).flat_map(|_| step(gs, DT)).collect()
+    }
+
+    #[test]
+    fn building_completes_when_a_worker_is_on_site() {
+        let mut gs = world();
+        add_unit(&mut gs, 1, 0, center(10), center(10));
+        gs.building_progress.insert(
+            (10, 10),
+            BuildTask {
+                owner_id: 1,
+                kind: 3,
+                tile_x: 10,
+                tile_y: 10,
+                progress: 0.0,
+            },
+        );
+
+        let events = run(&mut gs, 10);
+
+        let spawned: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                GameEvent::BuildingSpawned { building } => Some(building),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(
+            (spawned[0].kind, spawned[0].tile_x, spawned[0].tile_y),
+            (3, 10, 10)
+        );
+        assert!(gs.building_progress.is_empty());
+        assert!(gs
+            .buildings
+            .iter()
+            .any(|b| b.kind == 3 && b.hp == gs.balance.hp.house));
+    }
+
+    #[test]
+    fn unattended_wall_is_cancelled_and_refunded() {
+        let mut gs = world();
+        gs.resources.insert(1, Resources::new(0.0, 0.0, 0.0, 0.0));
+        gs.building_progress.insert(
+            (4, 4),
+            BuildTask {
+                owner_id: 1,
+                kind: 1,
+                tile_x: 4,
+                tile_y: 4,
+                progress: 0.0,
+            },
+        );
+
+        let events = step(&mut gs, DT);
+
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, GameEvent::BuildProgress { progress, .. } if *progress < 0.0)));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, GameEvent::ResourceUpdate { player_id: 1, .. })));
+        assert!(gs.building_progress.is_empty());
+        let res = gs.resources[&1];
+        assert_eq!(
+            (res.wood, res.stone),
+            (gs.balance.costs.wall.wood, gs.balance.costs.wall.stone)
+        );
+    }
+
+    #[test]
+    fn training_spawns_the_unit_after_four_seconds() {
+        let mut gs = world();
+        gs.training_tasks.push(TrainTask {
+            owner_id: 1,
+            kind: 1,
+            progress: 0.0,
+            chunk_x: 0,
+            chunk_y: 0,
+        });
+
+        let early = run(&mut gs, 15);
+        assert!(!early
+            .iter()
+            .any(|e| matches!(e, GameEvent::UnitSpawned { .. })));
+
+        let late = run(&mut gs, 6);
+        let spawned: Vec<_> = late
+            .iter()
+            .filter_map(|e| match e {
+                GameEvent::UnitSpawned { unit } => Some(unit),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(spawned[0].kind, 1);
+        assert_eq!(spawned[0].hp, gs.balance.hp.warrior);
+        assert_eq!(gs.units[&1].len(), 1);
+        assert!(gs.training_tasks.is_empty());
+    }
+
+    #[test]
+    fn step_length_scales_progress() {
+        let mut gs = world();
+        gs.training_tasks.push(TrainTask {
+            owner_id: 1,
+            kind: 0,
+            progress: 0.0,
+            chunk_x: 0,
+            chunk_y: 0,
+        });
+
+        let events = step(&mut gs, 4.0);
+
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, GameEvent::UnitSpawned { .. })));
+    }
+
+    #[test]
+    fn worker_gathers_into_its_carry() {
+        let mut gs = world();
+        let id = add_unit(&mut gs, 1, 0, center(FOREST.0), center(FOREST.1));
+        gs.gather_tasks.insert(
+            (1, id),
+            GatherTask {
+                kind: 2,
+                target_x: FOREST.0,
+                target_y: FOREST.1,
+                force_deposit: false,
+            },
+        );
+
+        run(&mut gs, 5);
+
+        let per_tick = gs.balance.gather_per_tick.wood;
+        assert_eq!(gs.unit(1, id).unwrap().carry_wood, per_tick * 5.0);
+        assert_eq!(
+            gs.resource_nodes[&FOREST].remaining,
+            gs.balance.node_amount.wood - per_tick * 5.0
+        );
+    }
+
+    #[test]
+    fn gathering_where_the_terrain_has_nothing_is_dropped() {
+        let mut gs = world();
+        let id = add_unit(&mut gs, 1, 0, center(20), center(20));
+        gs.gather_tasks.insert(
+            (1, id),
+            GatherTask {
+                kind: 2,
+                target_x: 20,
+                target_y: 20,
+                force_deposit: false,
+            },
+        );
+        // Stone from a forest is no better
+        let other = add_unit(&mut gs, 1, 0, center(FOREST.0), center(FOREST.1));
+        gs.gather_tasks.insert(
+            (1, other),
+            GatherTask {
+                kind: 3,
+                target_x: FOREST.0,
+                target_y: FOREST.1,
+                force_deposit: false,
+            },
+        );
+
+        run(&mut gs, 5);
+
+        assert_eq!(gs.unit(1, id).unwrap().carry_wood, 0.0);
+        assert_eq!(gs.unit(1, other).unwrap().carry_stone, 0.0);
+        assert!(gs.gather_tasks.is_empty());
+        assert!(gs.resource_nodes.is_empty());
+    }
+
+    #[test]
+    fn full_worker_deposits_at_town_center() {
+        let mut gs = world();
+        gs.resources.insert(1, Resources::new(0.0, 0.0, 0.0, 0.0));
+        add_building(&mut gs, 1, 0, 20, 20);
+        let id = add_unit(&mut gs, 1, 0, center(20), center(20));
+        gs.unit_mut(1, id).unwrap().carry_wood = gs.balance.carry_cap;
+        gs.gather_tasks.insert(
+            (1, id),
+            GatherTask {
+                kind: 2,
+                target_x: FOREST_EAST.0,
+                target_y: FOREST_EAST.1,
+                force_deposit: false,
+            },
+        );
+
+        step(&mut gs, DT);
+
+        assert_eq!(gs.resources[&1].wood, gs.balance.carry_cap);
+        assert_eq!(gs.unit(1, id).unwrap().carry_wood, 0.0);
+    }
+
+    #[test]
+    fn warrior_kills_an_adjacent_enemy_worker() {
+        let mut gs = world();
+        add_unit(&mut gs, 1, 1, 100.0, 100.0);
+        let victim = add_unit(&mut gs, 2, 0, 110.0, 100.0);
+
+        let events = run(&mut gs, 10);
+
+        assert!(events.iter().any(
+            |e| matches!(e, GameEvent::UnitDied { owner_id: 2, unit_id } if *unit_id == victim)
+        ));
+        assert!(gs.unit(2, victim).is_none());
+        assert!(gs.unit(1, 1).is_some());
+    }
+
+    #[test]
+    fn tower_shoots_enemies_in_range() {
+        let mut gs = world();
+        add_building(&mut gs, 1, 4, 5, 5);
+        let target = add_unit(&mut gs, 2, 0, center(5) + 40.0, center(5));
+
+        let events = step(&mut gs, DT);
+
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, GameEvent::TowerShot { .. })));
+        let expected = gs.balance.hp.worker - gs.balance.tower_damage;
+        assert_eq!(gs.unit(2, target).unwrap().hp, expected);
+    }
+}
@@ -0,0 +1,861 @@
+// End-to-end tests: each test boots its own server on an ephemeral port and drives it with
+// scripted WebSocket clients, the same way the browser does.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use chat_server::{replay, Config, Limits, Rate};
+use protocol::admin::{AdminRequest, AdminResponse};
+use protocol::terrain::{self, TileType};
+use protocol::wire::{self, Encoding};
+use protocol::{
+    feature, Balance, BuildKind, BuildingDTO, ClientCommand, GameMessage, RejectReason, Resources, UnitDTO, UnitKind,
+    PROTOCOL_VERSION,
+};
+
+// Generous: builds take ~2s and training 4s of simulated time
+const WAIT: Duration = Duration::from_secs(15);
+
+struct TestServer {
+    url: String,
+    admin_addr: String,
+    shutdown: oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<Result<(), String>>,
+    // Deleted on drop, after the server's final save
+    dir: tempfile::TempDir,
+}
+
+impl TestServer {
+    async fn start(balance: Balance) -> TestServer {
+        TestServer::start_with(balance, Limits::default()).await
+    }
+
+    async fn start_with(balance: Balance, limits: Limits) -> TestServer {
+        TestServer::start_in(tempfile::tempdir().unwrap(), balance, limits).await
+    }
+
+    // Boots on an existing data directory, as a redeploy with a mounted volume does.
+    async fn start_in(dir: tempfile::TempDir, balance: Balance, limits: Limits) -> TestServer {
+        // A port that was free a moment ago; the server binds it again itself
+        let admin_addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let config = Config {
+            balance,
+            snapshot_path: dir.path().join("world.snapshot.json"),
+            replay_path: Some(dir.path().join("world.replay.jsonl")),
+            admin_addr: Some(admin_addr.clone()),
+            limits,
+            latest_client: PROTOCOL_VERSION,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (shutdown, stop) = oneshot::channel::<()>();
+        let task = tokio::spawn(chat_server::serve(listener, config, async move {
+            let _ = stop.await;
+        }));
+        TestServer { url, admin_addr, shutdown, task, dir }
+    }
+
+    // Shuts down like SIGTERM would and checks the world was saved on the way out. Returns the
+    // data directory for tests that inspect the files.
+    async fn stop(self) -> tempfile::TempDir {
+        let _ = self.shutdown.send(());
+        self.task.await.unwrap().unwrap();
+        assert!(self.dir.path().join("world.snapshot.json").exists());
+        self.dir
+    }
+}
+
+// A plain HTTP GET on the game port, as a health check would send it. Returns the status code
+// and body.
+async fn http_get(server: &TestServer, path: &str) -> (u16, String) {
+    let addr = server.url.trim_start_matches("ws://");
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: probe\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    timeout(WAIT, stream.read_to_string(&mut response)).await.expect("response in time").unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").expect("complete response");
+    let code = head.split(' ').nth(1).and_then(|c| c.parse().ok()).expect("status line");
+    (code, body.to_string())
+}
+
+// Sends one request over the admin socket, as the `admin` binary does, and returns the answer.
+async fn admin(server: &TestServer, req: AdminRequest) -> AdminResponse {
+    let stream = timeout(WAIT, async {
+        loop {
+            // The socket opens alongside the game port, possibly just after this test starts
+            match TcpStream::connect(&server.admin_addr).await {
+                Ok(stream) => return stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+    })
+    .await
+    .expect("admin socket open in time");
+    let (read, mut write) = stream.into_split();
+    let mut line = serde_json::to_string(&req).unwrap();
+    line.push('\n');
+    write.write_all(line.as_bytes()).await.unwrap();
+    let answer = timeout(WAIT, BufReader::new(read).lines().next_line()).await.expect("answer in time").unwrap();
+    serde_json::from_str(&answer.expect("an answer line")).unwrap()
+}
+
+// Opens a connection and sends the handshake, offering every feature.
+async fn hello(
+    server: &TestServer,
+    version: u32,
+    encoding: Encoding,
+    token: Option<String>,
+) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    let (mut ws, _) = connect_async(server.url.as_str()).await.unwrap();
+    let features = feature::ALL.iter().map(|f| f.to_string()).collect();
+    let hello = GameMessage::Hello { version, features, token, encoding };
+    ws.send(Message::Text(serde_json::to_string(&hello).unwrap())).await.unwrap();
+    ws
+}
+
+// A scripted player, holding what it learned from Welcome.
+struct Bot {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    // Broadcasts a command causes can arrive before its ack; `command` keeps them here
+    backlog: VecDeque<GameMessage>,
+    next_seq: u32,
+    player_id: i32,
+    // Rejoining with this gets the same player back
+    token: String,
+    tc: BuildingDTO,
+    units: Vec<UnitDTO>,
+    resources: Resources,
+}
+
+impl Bot {
+    async fn join(server: &TestServer) -> Bot {
+        Bot::join_with(server, Encoding::Json).await
+    }
+
+    async fn join_with(server: &TestServer, encoding: Encoding) -> Bot {
+        Bot::join_as(server, PROTOCOL_VERSION, encoding).await
+    }
+
+    async fn join_as(server: &TestServer, version: u32, encoding: Encoding) -> Bot {
+        Bot::connect(server, version, encoding, None).await
+    }
+
+    async fn rejoin(server: &TestServer, token: String) -> Bot {
+        Bot::connect(server, PROTOCOL_VERSION, Encoding::Json, Some(token)).await
+    }
+
+    async fn connect(server: &TestServer, version: u32, encoding: Encoding, token: Option<String>) -> Bot {
+        let ws = hello(server, version, encoding, token).await;
+
+        let mut bot = Bot {
+            ws,
+            backlog: VecDeque::new(),
+            next_seq: 1,
+            player_id: 0,
+            token: String::new(),
+            tc: BuildingDTO { id: 0, owner_id: 0, kind: 0, tile_x: 0, tile_y: 0, hp: 0.0 },
+            units: Vec::new(),
+            resources: Resources::default(),
+        };
+        let GameMessage::ServerHello { features, .. } = bot.recv().await else { panic!("expected ServerHello first") };
+        assert!(features.iter().all(|f| feature::ALL.contains(&f.as_str())));
+        let welcome = bot.recv_until(|m| matches!(m, GameMessage::Welcome { .. })).await;
+        let GameMessage::Welcome { player_id, units, buildings, resources, token, .. } = welcome else { unreachable!() };
+        bot.player_id = player_id;
+        bot.token = token;
+        bot.tc = buildings.into_iter().find(|b| b.owner_id == player_id && b.kind == 0).expect("Welcome has our Town Center");
+        bot.units = units.into_iter().filter(|u| u.owner_id == player_id).collect();
+        bot.resources = resources;
+        bot
+    }
+
+    async fn send(&mut self, msg: GameMessage) -> u32 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let text = serde_json::to_string(&ClientCommand { seq, msg }).unwrap();
+        self.ws.send(Message::Text(text)).await.unwrap();
+        seq
+    }
+
+    async fn recv(&mut self) -> GameMessage {
+        if let Some(msg) = self.backlog.pop_front() {
+            return msg;
+        }
+        // Batches are taken apart so tests can wait for the individual events
+        loop {
+            match self.recv_frame().await {
+                GameMessage::TickBatch { events, .. } => {
+                    self.backlog.extend(events);
+                    if let Some(msg) = self.backlog.pop_front() {
+                        return msg;
+                    }
+                }
+                msg => return msg,
+            }
+        }
+    }
+
+    // The next frame as sent, bypassing the backlog.
+    async fn recv_frame(&mut self) -> GameMessage {
+        loop {
+            match self.ws.next().await.expect("server closed the connection").unwrap() {
+                Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                Message::Binary(bytes) => return wire::from_binary(&bytes).unwrap(),
+                Message::Close(_) => panic!("server closed the connection"),
+                _ => {}
+            }
+        }
+    }
+
+    // Waits for the server to end the connection, ignoring anything sent before it does.
+    async fn expect_closed(&mut self) {
+        timeout(WAIT, async {
+            while let Some(Ok(msg)) = self.ws.next().await {
+                if msg.is_close() {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("connection closed in time");
+    }
+
+    // Skips messages until one matches, failing the test if none arrives within WAIT.
+    async fn recv_until(&mut self, mut pred: impl FnMut(&GameMessage) -> bool) -> GameMessage {
+        timeout(WAIT, async {
+            loop {
+                let msg = self.recv().await;
+                if pred(&msg) {
+                    return msg;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for message")
+    }
+
+    // Sends a command and returns the reason it was rejected, if it was.
+    async fn command(&mut self, msg: GameMessage) -> Result<(), RejectReason> {
+        let seq = self.send(msg).await;
+        let mut before_ack = VecDeque::new();
+        let ack = self
+            .recv_until(|m| {
+                let is_ack = matches!(m, GameMessage::CommandAck { seq: s, .. } if *s == seq);
+                if !is_ack {
+                    before_ack.push_back(m.clone());
+                }
+                is_ack
+            })
+            .await;
+        // Ahead of anything that came after the ack in the same batch
+        for msg in before_ack.into_iter().rev() {
+            self.backlog.push_front(msg);
+        }
+        let GameMessage::CommandAck { ok, reason, .. } = ack else { unreachable!() };
+        if ok { Ok(()) } else { Err(reason.expect("rejections carry a reason")) }
+    }
+
+    // Places a building `(dx, dy)` tiles from the Town Center and waits for it to finish.
+    async fn build(&mut self, kind: BuildKind, dx: i32, dy: i32) -> BuildingDTO {
+        let (tile_x, tile_y) = (self.tc.tile_x + dx, self.tc.tile_y + dy);
+        self.command(GameMessage::Build { kind: kind.to_kind_id(), tile_x, tile_y }).await.unwrap();
+        let spawned = self
+            .recv_until(|m| matches!(m, GameMessage::BuildingSpawned { building } if building.tile_x == tile_x && building.tile_y == tile_y))
+            .await;
+        let GameMessage::BuildingSpawned { building } = spawned else { unreachable!() };
+        building
+    }
+}
+
+// The resources in a ResourceUpdate for `player`, if that's what `msg` is.
+fn resources_of(msg: &GameMessage, player: i32) -> Option<Resources> {
+    match msg {
+        GameMessage::ResourceUpdate { player_id, resources, .. } if *player_id == player => Some(*resources),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn join_receives_welcome_with_starting_base() {
+    let server = TestServer::start(Balance::default()).await;
+    let bot = Bot::join(&server).await;
+
+    assert_eq!(bot.tc.owner_id, bot.player_id);
+    assert_eq!(bot.units.len(), 2);
+    assert!(bot.units.iter().all(|u| u.kind == UnitKind::Worker.to_u8()));
+    assert!(bot.resources.wood > 0.0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn outdated_clients_are_turned_away() {
+    let server = TestServer::start(Balance::default()).await;
+
+    // The first version to say Hello, now below the minimum
+    let mut ws = hello(&server, 28, Encoding::Binary, None).await;
+    let Some(Ok(Message::Text(text))) = ws.next().await else { panic!("expected a text frame") };
+    let GameMessage::VersionRejected { version, min_version, .. } = serde_json::from_str(&text).unwrap() else { panic!("{}", text) };
+    assert_eq!(version, 28);
+    assert!(min_version > version);
+    assert!(!matches!(ws.next().await, Some(Ok(Message::Text(_) | Message::Binary(_)))), "connection should close");
+
+    // Clients from before Hello get the error their update screen looks for
+    let (mut ws, _) = connect_async(server.url.as_str()).await.unwrap();
+    let join = GameMessage::Join { version: 27, token: None, encoding: Encoding::Json };
+    ws.send(Message::Text(serde_json::to_string(&join).unwrap())).await.unwrap();
+    let Some(Ok(Message::Text(text))) = ws.next().await else { panic!("expected a text frame") };
+    let GameMessage::Error { message } = serde_json::from_str(&text).unwrap() else { panic!("{}", text) };
+    assert!(message.contains("Client version") && message.contains("too old"), "{}", message);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn older_clients_hear_about_a_newer_one() {
+    let server = TestServer::start(Balance::default()).await;
+    // Claiming a version nobody has released changes nothing for anyone else
+    let mut spoofer = Bot::join_as(&server, u32::MAX, Encoding::Json).await;
+    let mut current = Bot::join(&server).await;
+
+    // Still accepted, but told about the deployed client, and keeps playing
+    let mut old = Bot::join_as(&server, PROTOCOL_VERSION - 1, Encoding::Json).await;
+    let notice = old.recv_until(|m| matches!(m, GameMessage::NewClientAvailable { .. })).await;
+    assert!(matches!(notice, GameMessage::NewClientAvailable { version } if version == PROTOCOL_VERSION));
+    let tile = (old.tc.tile_x, old.tc.tile_y + 1);
+    old.command(GameMessage::Build { kind: BuildKind::House.to_kind_id(), tile_x: tile.0, tile_y: tile.1 }).await.unwrap();
+
+    // Clients on the deployed version, or claiming a newer one, have nothing to update to
+    for bot in [&mut spoofer, &mut current, &mut old] {
+        bot.command(GameMessage::RequestResync).await.unwrap();
+        assert!(!bot.backlog.iter().any(|m| matches!(m, GameMessage::NewClientAvailable { .. })));
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn deploying_a_new_client_tells_those_already_playing() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut current = Bot::join(&server).await;
+    let mut spoofer = Bot::join_as(&server, u32::MAX, Encoding::Json).await;
+
+    let next = PROTOCOL_VERSION + 1;
+    let answer = admin(&server, AdminRequest::SetLatestClient { version: next }).await;
+    assert!(matches!(&answer, AdminResponse::Ok { message } if message.contains("told 1 ")), "{:?}", answer);
+
+    // Without reconnecting, and not to the client already claiming to be newer
+    let notice = current.recv_until(|m| matches!(m, GameMessage::NewClientAvailable { .. })).await;
+    assert!(matches!(notice, GameMessage::NewClientAvailable { version } if version == next));
+    spoofer.command(GameMessage::RequestResync).await.unwrap();
+    assert!(!spoofer.backlog.iter().any(|m| matches!(m, GameMessage::NewClientAvailable { .. })));
+
+    // Later joiners on the old client hear it at join, like with the configured version
+    let mut late = Bot::join(&server).await;
+    let notice = late.recv_until(|m| matches!(m, GameMessage::NewClientAvailable { .. })).await;
+    assert!(matches!(notice, GameMessage::NewClientAvailable { version } if version == next));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn health_endpoints_answer_plain_http() {
+    let server = TestServer::start(Balance::default()).await;
+    let _bot = Bot::join(&server).await;
+
+    assert_eq!(http_get(&server, "/healthz").await, (200, "ok".to_string()));
+    assert_eq!(http_get(&server, "/readyz").await.0, 200);
+    assert_eq!(http_get(&server, "/nope").await.0, 404);
+
+    let (code, body) = http_get(&server, "/info?verbose=1").await;
+    assert_eq!(code, 200);
+    let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(info["players"], 1);
+    assert_eq!(info["online"], 1);
+    assert_eq!(info["protocol"], PROTOCOL_VERSION);
+    assert!(info["build"].is_string() && info["uptime_secs"].is_u64() && info["tick_ms"].is_u64());
+
+    // The game still upgrades on the same port
+    let _second = Bot::join(&server).await;
+
+    let (code, metrics) = http_get(&server, "/metrics").await;
+    assert_eq!(code, 200);
+    assert!(metrics.contains("temty_clients 2"), "{}", metrics);
+    assert!(metrics.contains("temty_messages_in_total{type=\"Hello\"} 2"), "{}", metrics);
+    assert!(metrics.contains("temty_messages_out_total{type=\"Welcome\"} 2"), "{}", metrics);
+    assert!(metrics.contains("# TYPE temty_tick_duration_seconds histogram"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn spammers_are_throttled_then_disconnected() {
+    let mut limits = Limits::default();
+    limits.commands.insert("SpawnUnit".to_string(), Rate { per_sec: 0.0, burst: 2.0 });
+    limits.strikes = Rate { per_sec: 0.0, burst: 3.0 };
+    let server = TestServer::start_with(Balance::default(), limits).await;
+    let mut bot = Bot::join(&server).await;
+
+    // Refused commands are still acknowledged, so the client can roll back
+    assert!(bot.command(GameMessage::SpawnUnit).await.is_ok());
+    assert!(bot.command(GameMessage::SpawnUnit).await.is_ok());
+    assert_eq!(bot.command(GameMessage::SpawnUnit).await, Err(RejectReason::RateLimited));
+    // Other command types have their own allowance
+    bot.command(GameMessage::SetView { chunk_x0: 0, chunk_y0: 0, chunk_x1: 0, chunk_y1: 0 }).await.unwrap();
+
+    // The third strike was the refusal above; two more are tolerated, then the connection goes
+    for _ in 0..3 {
+        bot.send(GameMessage::SpawnUnit).await;
+    }
+    let kicked = bot.recv_until(|m| matches!(m, GameMessage::Kicked { .. })).await;
+    let GameMessage::Kicked { reason, reconnect } = kicked else { unreachable!() };
+    assert!(reason.contains("too many messages") && reconnect, "{}", reason);
+    bot.expect_closed().await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn oversized_frames_close_the_connection() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut bot = Bot::join(&server).await;
+
+    let huge = "x".repeat(Limits::default().max_frame_bytes + 1);
+    bot.ws.send(Message::Text(huge)).await.unwrap();
+    let kicked = bot.recv_until(|m| matches!(m, GameMessage::Kicked { .. })).await;
+    let GameMessage::Kicked { reason, reconnect } = kicked else { unreachable!() };
+    assert!(reason.contains("too large") && reconnect, "{}", reason);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn banned_players_are_told_not_to_come_back() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut bot = Bot::join(&server).await;
+
+    let answer = admin(&server, AdminRequest::BanToken { token: bot.token.clone() }).await;
+    assert!(matches!(answer, AdminResponse::Ok { .. }), "{:?}", answer);
+    let kicked = bot.recv_until(|m| matches!(m, GameMessage::Kicked { .. })).await;
+    assert!(matches!(kicked, GameMessage::Kicked { reconnect: false, .. }), "{:?}", kicked);
+    bot.expect_closed().await;
+
+    // And the same again if it tries anyway
+    let mut ws = hello(&server, PROTOCOL_VERSION, Encoding::Json, Some(bot.token.clone())).await;
+    let Some(Ok(Message::Text(text))) = ws.next().await else { panic!("expected a text frame") };
+    let GameMessage::Kicked { reason, reconnect } = serde_json::from_str(&text).unwrap() else { panic!("{}", text) };
+    assert!(reason.contains("banned") && !reconnect, "{}", reason);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn shutdown_tells_clients_and_closes_cleanly() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut bot = Bot::join(&server).await;
+
+    let stopping = tokio::spawn(server.stop());
+    let notice = bot.recv_until(|m| matches!(m, GameMessage::ServerShutdown { .. })).await;
+    let GameMessage::ServerShutdown { reconnect_after_ms, .. } = notice else { unreachable!() };
+    assert!(reconnect_after_ms > 0);
+    // A close frame, not a dropped socket
+    let closing = timeout(WAIT, bot.ws.next()).await.expect("closed in time");
+    assert!(matches!(closing, Some(Ok(Message::Close(_)))), "{:?}", closing);
+    // stop() also checks the world was saved
+    stopping.await.unwrap();
+}
+
+#[tokio::test]
+async fn build_spawns_building_and_charges_cost() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut bot = Bot::join(&server).await;
+    let wood_before = bot.resources.wood;
+
+    // Right below the Town Center, next to the starting workers
+    let tile = (bot.tc.tile_x, bot.tc.tile_y + 1);
+    bot.command(GameMessage::Build { kind: BuildKind::House.to_kind_id(), tile_x: tile.0, tile_y: tile.1 }).await.unwrap();
+    let me = bot.player_id;
+    let update = bot.recv_until(|m| resources_of(m, me).is_some_and(|r| r.wood < wood_before)).await;
+    let GameMessage::ResourceUpdate { resources, pop_cap, .. } = update else { unreachable!() };
+    assert_eq!(resources.wood, wood_before - Balance::default().costs.house.wood);
+    assert_eq!(pop_cap, 6);
+
+    let spawned = bot.recv_until(|m| matches!(m, GameMessage::BuildingSpawned { .. })).await;
+    let GameMessage::BuildingSpawned { building } = spawned else { unreachable!() };
+    assert_eq!((building.tile_x, building.tile_y), tile);
+    assert_eq!(building.kind, BuildKind::House.to_kind_id());
+    assert_eq!(building.owner_id, bot.player_id);
+
+    // The tile is taken now
+    let again = bot.command(GameMessage::Build { kind: BuildKind::Wall.to_kind_id(), tile_x: tile.0, tile_y: tile.1 }).await;
+    assert_eq!(again, Err(RejectReason::TileBlocked));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn barracks_trains_a_warrior() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut bot = Bot::join(&server).await;
+
+    let barracks = bot.build(BuildKind::Barracks, 1, 1).await;
+    bot.command(GameMessage::TrainUnit { building_id: barracks.id, kind: UnitKind::Warrior.to_u8() }).await.unwrap();
+
+    let spawned = bot.recv_until(|m| matches!(m, GameMessage::UnitSpawned { .. })).await;
+    let GameMessage::UnitSpawned { unit } = spawned else { unreachable!() };
+    assert_eq!(unit.owner_id, bot.player_id);
+    assert_eq!(unit.kind, UnitKind::Warrior.to_u8());
+    assert_eq!(unit.hp, Balance::default().hp.warrior);
+    assert!(bot.units.iter().all(|u| u.unit_id != unit.unit_id));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn gathering_from_a_farm_deposits_food() {
+    // A small carry capacity makes the first deposit happen within a few ticks
+    let balance = Balance { carry_cap: 10.0, ..Balance::default() };
+    let server = TestServer::start(balance).await;
+    let mut bot = Bot::join(&server).await;
+
+    // Left of the tile below the Town Center, so the gatherer stands within drop-off reach
+    let farm = bot.build(BuildKind::Farm, -1, 1).await;
+    let food_before = bot.resources.food;
+    let worker = bot.units[0].unit_id;
+    bot.command(GameMessage::AssignGather { unit_ids: vec![worker], target_x: farm.tile_x, target_y: farm.tile_y, kind: 5 })
+        .await
+        .unwrap();
+
+    let carry = bot
+        .recv_until(|m| matches!(m, GameMessage::UnitCarry { unit_id, carry_food, .. } if *unit_id == worker && *carry_food > 0.0))
+        .await;
+    let GameMessage::UnitCarry { carry_wood, .. } = carry else { unreachable!() };
+    assert_eq!(carry_wood, 0.0);
+
+    let me = bot.player_id;
+    let update = bot.recv_until(|m| resources_of(m, me).is_some_and(|r| r.food > food_before)).await;
+    let resources = resources_of(&update, me).unwrap();
+    assert_eq!(resources.food, food_before + 10.0);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn binary_clients_play_the_same_game() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut alice = Bot::join_with(&server, Encoding::Binary).await;
+    let bob = Bot::join(&server).await;
+    assert_eq!(alice.units.len(), 2);
+
+    // Bob's base is next door, so his units come with the NewPlayer
+    let bob_id = bob.player_id;
+    let joined = alice.recv_until(|m| matches!(m, GameMessage::NewPlayer { player, .. } if player.id == bob_id)).await;
+    let GameMessage::NewPlayer { units, .. } = joined else { unreachable!() };
+    assert_eq!(units.len(), 2);
+
+    let worker = alice.units[0].clone();
+    let target = (alice.tc.tile_x + 3, alice.tc.tile_y + 3);
+    alice.command(GameMessage::MoveUnit { unit_id: worker.unit_id, tile_x: target.0, tile_y: target.1 }).await.unwrap();
+    let synced = alice
+        .recv_until(|m| matches!(m, GameMessage::UnitSync { unit_id, x, .. } if *unit_id == worker.unit_id && *x != worker.x))
+        .await;
+    // Positions arrive quantized
+    let GameMessage::UnitSync { x, y, .. } = synced else { unreachable!() };
+    assert_eq!((x * wire::POSITION_SCALE).fract(), 0.0);
+    assert_eq!((y * wire::POSITION_SCALE).fract(), 0.0);
+
+    let house = alice.build(BuildKind::House, 0, 1).await;
+    assert_eq!(house.owner_id, alice.player_id);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn a_command_and_its_effects_arrive_in_one_frame() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut alice = Bot::join(&server).await;
+
+    let (tile_x, tile_y) = (alice.tc.tile_x, alice.tc.tile_y + 1);
+    let seq = alice.send(GameMessage::Build { kind: BuildKind::House.to_kind_id(), tile_x, tile_y }).await;
+    let acked = |events: &[GameMessage]| events.iter().any(|m| matches!(m, GameMessage::CommandAck { seq: s, .. } if *s == seq));
+    let events = timeout(WAIT, async {
+        loop {
+            if let GameMessage::TickBatch { events, .. } = alice.recv_frame().await {
+                if acked(&events) {
+                    return events;
+                }
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for the command's batch");
+
+    // Effects first, then the ack
+    let me = alice.player_id;
+    assert!(matches!(events[0], GameMessage::BuildProgress { tile_x: x, tile_y: y, .. } if (x, y) == (tile_x, tile_y)));
+    assert!(resources_of(&events[1], me).is_some());
+    assert!(matches!(events[2], GameMessage::CommandAck { ok: true, .. }));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn onlookers_get_one_frame_per_tick_however_busy_the_area() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut alice = Bot::join(&server).await;
+    let mut bob = Bot::join(&server).await;
+
+    // Bob watches Alice's base
+    let (cx, cy) = (alice.tc.tile_x.div_euclid(32), alice.tc.tile_y.div_euclid(32));
+    bob.command(GameMessage::SetView { chunk_x0: cx, chunk_y0: cy, chunk_x1: cx, chunk_y1: cy }).await.unwrap();
+    bob.backlog.clear();
+
+    // 30 commands in about a second, each broadcasting a UnitMove that Bob can see
+    let (unit, tc) = (alice.units[0].unit_id, (alice.tc.tile_x, alice.tc.tile_y));
+    let spam = async {
+        for i in 0..30 {
+            let tile_x = if i % 2 == 0 { tc.0 + 2 } else { tc.0 - 2 };
+            alice.send(GameMessage::MoveUnit { unit_id: unit, tile_x, tile_y: tc.1 + 2 }).await;
+            tokio::time::sleep(Duration::from_millis(35)).await;
+        }
+    };
+    let watch = async {
+        let mut frames = Vec::new();
+        let _ = timeout(Duration::from_millis(1200), async {
+            loop {
+                frames.push(bob.recv_frame().await);
+            }
+        })
+        .await;
+        frames
+    };
+    let ((), frames) = tokio::join!(spam, watch);
+
+    let moves: usize = frames
+        .iter()
+        .map(|f| match f {
+            GameMessage::TickBatch { events, .. } => events.iter().filter(|m| matches!(m, GameMessage::UnitMove { .. })).count(),
+            m => matches!(m, GameMessage::UnitMove { .. }) as usize,
+        })
+        .sum();
+    assert!(moves >= 10, "Bob should see Alice's moves, saw {}", moves);
+    // 1.2s is six ticks; allow one either side for the timing of the window
+    assert!(frames.len() <= 8, "{} frames for {} moves", frames.len(), moves);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn players_cannot_command_each_others_units() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut alice = Bot::join(&server).await;
+    let mut bob = Bot::join(&server).await;
+    assert_ne!(alice.player_id, bob.player_id);
+
+    // Alice hears about Bob's arrival (every client is also told about itself)
+    let bob_id = bob.player_id;
+    let joined = alice.recv_until(|m| matches!(m, GameMessage::NewPlayer { player, .. } if player.id == bob_id)).await;
+    let GameMessage::NewPlayer { player, units } = joined else { unreachable!() };
+    assert_eq!(player.id, bob.player_id);
+    assert_eq!(units.len(), 2);
+
+    let theirs = alice.units[0].unit_id;
+    let tile = (bob.tc.tile_x, bob.tc.tile_y - 2);
+    let moved = bob.command(GameMessage::MoveUnit { unit_id: theirs, tile_x: tile.0, tile_y: tile.1 }).await;
+    assert_eq!(moved, Err(RejectReason::NotOwner));
+    let gather = GameMessage::AssignGather { unit_ids: vec![theirs], target_x: tile.0, target_y: tile.1, kind: 2 };
+    assert_eq!(bob.command(gather).await, Err(RejectReason::NotOwner));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn moves_to_the_far_side_of_the_map_are_refused() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut bot = Bot::join(&server).await;
+
+    let unit_id = bot.units[0].unit_id;
+    for (tile_x, tile_y) in [(i32::MAX, i32::MAX), (i32::MIN, bot.tc.tile_y), (bot.tc.tile_x + 500, bot.tc.tile_y)] {
+        assert_eq!(bot.command(GameMessage::MoveUnit { unit_id, tile_x, tile_y }).await, Err(RejectReason::NoPath));
+    }
+    // Nearby targets still work
+    let tile = (bot.tc.tile_x, bot.tc.tile_y - 2);
+    bot.command(GameMessage::MoveUnit { unit_id, tile_x: tile.0, tile_y: tile.1 }).await.unwrap();
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn terrain_decides_what_can_be_built_and_gathered() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut bot = Bot::join(&server).await;
+
+    // The clearing around the Town Center is grass; the rest of the chunk is mostly forest
+    let (tc_x, tc_y) = (bot.tc.tile_x, bot.tc.tile_y);
+    let forest = (tc_x - 12..=tc_x + 12)
+        .flat_map(|x| (tc_y - 12..=tc_y + 12).map(move |y| (x, y)))
+        .find(|&(x, y)| terrain::tile_at(x, y) == TileType::Forest)
+        .expect("a forest tile near the Town Center");
+    let grass = (tc_x, tc_y + 2);
+
+    let build = GameMessage::Build { kind: BuildKind::House.to_kind_id(), tile_x: forest.0, tile_y: forest.1 };
+    assert_eq!(bot.command(build).await, Err(RejectReason::TileBlocked));
+
+    let worker = bot.units[0].unit_id;
+    let gather = |(target_x, target_y): (i32, i32), kind| GameMessage::AssignGather { unit_ids: vec![worker], target_x, target_y, kind };
+    assert_eq!(bot.command(gather(grass, 2)).await, Err(RejectReason::InvalidKind));
+    assert_eq!(bot.command(gather(forest, 3)).await, Err(RejectReason::InvalidKind));
+    // Food only comes from farms
+    assert_eq!(bot.command(gather(grass, 5)).await, Err(RejectReason::InvalidKind));
+    assert_eq!(bot.command(gather(forest, 2)).await, Ok(()));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn clients_only_hear_about_chunks_they_can_see() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut alice = Bot::join(&server).await;
+    let home = |bot: &Bot| (bot.tc.tile_x.div_euclid(32), bot.tc.tile_y.div_euclid(32));
+
+    // Players spiral outwards from the origin; join until one lands out of Alice's sight (her
+    // home chunk and the ring around it)
+    let mut others = Vec::new();
+    let mut far = loop {
+        let bot = Bot::join(&server).await;
+        let ((ax, ay), (bx, by)) = (home(&alice), home(&bot));
+        if (ax - bx).abs().max((ay - by).abs()) >= 2 {
+            break bot;
+        }
+        others.push(bot);
+        assert!(others.len() < 10, "no player spawned out of sight");
+    };
+    let (far_id, far_home) = (far.player_id, home(&far));
+
+    // Alice learns the player exists, but not what they have
+    let joined = alice.recv_until(|m| matches!(m, GameMessage::NewPlayer { player, .. } if player.id == far_id)).await;
+    let GameMessage::NewPlayer { units, .. } = joined else { unreachable!() };
+    assert!(units.is_empty());
+
+    let site = (far.tc.tile_x, far.tc.tile_y + 1);
+    far.command(GameMessage::Build { kind: BuildKind::House.to_kind_id(), tile_x: site.0, tile_y: site.1 }).await.unwrap();
+
+    // Looking there brings the whole chunk, and nothing from it leaked out before
+    alice.send(GameMessage::SetView { chunk_x0: far_home.0, chunk_y0: far_home.1, chunk_x1: far_home.0, chunk_y1: far_home.1 }).await;
+    let entered = alice
+        .recv_until(|m| {
+            if let GameMessage::BuildProgress { tile_x, tile_y, .. } = m {
+                assert_ne!((*tile_x, *tile_y), site, "got the far player's build before seeing the chunk");
+            }
+            matches!(m, GameMessage::ChunkEnter { chunk_x, chunk_y, .. } if (*chunk_x, *chunk_y) == far_home)
+        })
+        .await;
+    let GameMessage::ChunkEnter { units, buildings, sites, .. } = entered else { unreachable!() };
+    assert_eq!(units.iter().filter(|u| u.owner_id == far_id).count(), far.units.len());
+    assert!(buildings.iter().any(|b| b.id == far.tc.id));
+    let built = buildings.iter().any(|b| (b.tile_x, b.tile_y) == site);
+    assert!(built || sites.iter().any(|s| (s.tile_x, s.tile_y) == site));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn resync_request_returns_full_state() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut alice = Bot::join(&server).await;
+    let house = alice.build(BuildKind::House, 0, 1).await;
+
+    alice.command(GameMessage::RequestResync).await.unwrap();
+    let full = alice.recv_until(|m| matches!(m, GameMessage::FullState { .. })).await;
+    let GameMessage::FullState { players, units, buildings, pop_cap, .. } = full else { unreachable!() };
+    assert!(players.iter().any(|p| p.id == alice.player_id));
+    assert_eq!(units.iter().filter(|u| u.owner_id == alice.player_id).count(), alice.units.len());
+    assert!(buildings.iter().any(|b| b.id == alice.tc.id));
+    assert!(buildings.iter().any(|b| b.id == house.id));
+    assert_eq!(pop_cap, 6);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn returning_players_find_their_base_after_a_restart() {
+    let server = TestServer::start(Balance::default()).await;
+    let mut alice = Bot::join(&server).await;
+    let house = alice.build(BuildKind::House, 0, 1).await;
+    let AdminResponse::Player { player, .. } = admin(&server, AdminRequest::InspectPlayer { player_id: alice.player_id }).await
+    else {
+        panic!("expected the player")
+    };
+    drop(alice.ws);
+    let dir = server.stop().await;
+
+    let server = TestServer::start_in(dir, Balance::default(), Limits::default()).await;
+    // Read before rejoining, which tops a stockpile back up to the starting minimum
+    let AdminResponse::Player { player: restored, buildings, .. } =
+        admin(&server, AdminRequest::InspectPlayer { player_id: alice.player_id }).await
+    else {
+        panic!("expected the player")
+    };
+    assert_eq!(format!("{:?}", restored.resources), format!("{:?}", player.resources));
+    assert!(buildings.iter().any(|b| b.id == house.id && b.tile_x == house.tile_x && b.tile_y == house.tile_y));
+
+    let bob = Bot::join(&server).await;
+    let back = Bot::rejoin(&server, alice.token.clone()).await;
+    assert_eq!(back.player_id, alice.player_id);
+    assert_ne!(bob.player_id, alice.player_id, "ids handed out before the restart stay taken");
+    assert_eq!((back.tc.id, back.tc.tile_x, back.tc.tile_y), (alice.tc.id, alice.tc.tile_x, alice.tc.tile_y));
+    let ids = |units: &[UnitDTO]| units.iter().map(|u| u.unit_id).collect::<Vec<_>>();
+    assert_eq!(ids(&back.units), ids(&alice.units));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn replay_log_reproduces_the_recorded_game() {
+    let server = TestServer::start(Balance { carry_cap: 10.0, ..Balance::default() }).await;
+    let mut alice = Bot::join(&server).await;
+    let mut bob = Bot::join(&server).await;
+
+    let farm = alice.build(BuildKind::Farm, -1, 1).await;
+    let worker = alice.units[0].unit_id;
+    alice
+        .command(GameMessage::AssignGather { unit_ids: vec![worker], target_x: farm.tile_x, target_y: farm.tile_y, kind: 5 })
+        .await
+        .unwrap();
+    let barracks = bob.build(BuildKind::Barracks, 1, 1).await;
+    bob.command(GameMessage::TrainUnit { building_id: barracks.id, kind: UnitKind::Warrior.to_u8() }).await.unwrap();
+    bob.recv_until(|m| matches!(m, GameMessage::UnitSpawned { .. })).await;
+    // Rejected commands aren't logged, so they mustn't affect the replay either
+    let blocked = GameMessage::Build { kind: BuildKind::Wall.to_kind_id(), tile_x: farm.tile_x, tile_y: farm.tile_y };
+    assert_eq!(alice.command(blocked).await, Err(RejectReason::TileBlocked));
+    admin(&server, AdminRequest::BanToken { token: bob.token.clone() }).await;
+    bob.recv_until(|m| matches!(m, GameMessage::Kicked { .. })).await;
+    drop(alice);
+    drop(bob.ws);
+
+    let dir = server.stop().await;
+    let log = dir.path().join("world.replay.jsonl");
+    assert!(!std::fs::read_to_string(&log).unwrap().contains(&bob.token), "tokens stay out of the log");
+    let sessions = replay::read(&log).unwrap();
+    assert_eq!(sessions.len(), 1);
+
+    let mut spawned = Vec::new();
+    let report = replay::run(&sessions[0], |_, json| {
+        if let Ok(GameMessage::BuildingSpawned { building }) = serde_json::from_str(json) {
+            spawned.push(building.id);
+        }
+    })
+    .unwrap();
+    assert!(report.divergence.is_none(), "{:?}", report.divergence);
+    assert!(report.checked >= 1);
+    assert_eq!(spawned, vec![farm.id, barracks.id]);
+}
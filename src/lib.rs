@@ -1,12 +1,17 @@
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::cmp::Ordering;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::Clamped;
 use web_sys::{WebSocket, HtmlCanvasElement, CanvasRenderingContext2d, ImageData, MouseEvent, WheelEvent, TouchEvent, MessageEvent};
-use serde::{Serialize, Deserialize};
+use protocol::{
+    Balance, BuildKind, BuildSite, BuildingDTO, ClientCommand, GameMessage, PlayerInfo, RejectReason, ResourceKind,
+    Resources, UnitDTO, UnitKind, PROTOCOL_VERSION, TOWN_CENTER_KIND,
+};
+use protocol::terrain::{self, TileType, CHUNK_SIZE};
+use protocol::wire::{self, Encoding};
 
 // --- IMPORTS & LOGGING ---
 #[wasm_bindgen]
@@ -18,70 +23,81 @@ extern "C" {
 }
 
 // --- NETWORK PROTOCOL ---
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct PlayerInfo {
-    id: i32,
-    chunk_x: i32,
-    chunk_y: i32,
-}
+// Message types, costs and kind tables come from the shared `protocol` crate.
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct UnitDTO {
-    owner_id: i32,
-    unit_idx: usize,
-    x: f32,
-    y: f32,
-    kind: u8,
-    hp: f32,
-    #[serde(default)]
-    carry_wood: f32,
-    #[serde(default)]
-    carry_stone: f32,
-    #[serde(default)]
-    carry_gold: f32,
-    #[serde(default)]
-    carry_food: f32,
+// Optimistic client state to undo if the server rejects the command it was sent with
+#[derive(Debug, Clone, Copy)]
+enum PendingCommand {
+    Build { tile_x: i32, tile_y: i32, wall: bool },
+    SpawnWorker,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct BuildingDTO {
-    id: i32,
-    owner_id: i32,
-    kind: u8,
-    tile_x: i32,
-    tile_y: i32,
-    hp: f32,
+// Reconnect backoff: first retry after about this long, doubling up to the max
+const RECONNECT_BASE_MS: f64 = 500.0;
+const RECONNECT_MAX_MS: f64 = 30_000.0;
+
+// --- SERVER CONFIG ---
+
+const PRODUCTION_URL: &str = "wss://temty-server-production.up.railway.app";
+// The Railway service's staging environment, for QA
+const STAGING_URL: &str = "wss://temty-server-staging.up.railway.app";
+const LOCAL_URL: &str = "ws://localhost:9001";
+
+// Where to connect and how. Each setting is taken from the page's query string, then from the
+// object passed to `run_game`, then from the defaults:
+//   server  full WebSocket URL, e.g. a branch deployment
+//   env     "local", "staging" or "production", used when no server is given; defaults to local
+//           when the page itself is served from localhost
+//   wire    "json" for readable frames in devtools, "msgpack" (the default) otherwise
+// A value that isn't one of these is an error rather than a guess: a typo must not quietly put a
+// developer on production.
+#[derive(Clone, Debug)]
+struct ClientConfig {
+    server_url: String,
+    encoding: Encoding,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(tag = "type")]
-enum GameMessage {
-    Join { version: u32, token: Option<String> },
-    Welcome { player_id: i32, chunk_x: i32, chunk_y: i32, players: Vec<PlayerInfo>, units: Vec<UnitDTO>, buildings: Vec<BuildingDTO>, token: String, resources: Resources, pop_cap: i32, pop_used: i32 },
-    NewPlayer { player: PlayerInfo },
-    UnitMove { player_id: i32, unit_idx: usize, x: f32, y: f32 },
-    UnitSync { player_id: i32, unit_idx: usize, x: f32, y: f32 },
-    SpawnUnit, 
-    TrainUnit { building_id: i32, kind: u8 },
-    UnitSpawned { unit: UnitDTO },
-    Build { kind: u8, tile_x: i32, tile_y: i32 },
-    BuildProgress { tile_x: i32, tile_y: i32, kind: u8, progress: f32 },
-    BuildingSpawned { building: BuildingDTO },
-    AssignGather { unit_ids: Vec<usize>, target_x: i32, target_y: i32, kind: u8 },
-    DepositNow { unit_ids: Vec<usize> },
-    TowerShot { x1: f32, y1: f32, x2: f32, y2: f32 },
-    UnitDied { owner_id: i32, unit_idx: usize },
-    BuildingDestroyed { tile_x: i32, tile_y: i32 },
-    UnitHp { owner_id: i32, unit_idx: usize, hp: f32 },
-    BuildingHp { tile_x: i32, tile_y: i32, hp: f32 },
-    ResourceUpdate { player_id: i32, resources: Resources, pop_cap: i32, pop_used: i32 },
-    DeleteUnit { unit_idx: usize },
-    DeleteBuilding { tile_x: i32, tile_y: i32 },
-    UnitCarry { owner_id: i32, unit_idx: usize, carry_wood: f32, carry_stone: f32, carry_gold: f32, carry_food: f32 },
-    Error { message: String },
-}
+impl ClientConfig {
+    fn load(options: &JsValue) -> Result<ClientConfig, String> {
+        let window = web_sys::window().unwrap();
+        let location = window.location();
+        let query = web_sys::UrlSearchParams::new_with_str(&location.search().unwrap_or_default()).ok();
+        let setting = |name: &str| {
+            query.as_ref().and_then(|q| q.get(name))
+                .or_else(|| js_sys::Reflect::get(options, &JsValue::from_str(name)).ok().and_then(|v| v.as_string()))
+        };
 
-const CLIENT_VERSION: u32 = 22;
+        let env = setting("env").unwrap_or_else(|| {
+            let host = location.hostname().unwrap_or_default();
+            if host == "localhost" || host == "127.0.0.1" { "local".to_string() } else { "production".to_string() }
+        });
+        let server_url = match setting("server") {
+            Some(url) => url,
+            None => match env.as_str() {
+                "local" => LOCAL_URL.to_string(),
+                "staging" => STAGING_URL.to_string(),
+                "production" => PRODUCTION_URL.to_string(),
+                other => return Err(format!("Unknown env \"{}\" (expected local, staging or production)", other)),
+            },
+        };
+        let encoding = match setting("wire").as_deref() {
+            Some("json") => Encoding::Json,
+            None | Some("msgpack") => Encoding::Binary,
+            Some(other) => return Err(format!("Unknown wire \"{}\" (expected json or msgpack)", other)),
+        };
+        Ok(ClientConfig { server_url, encoding })
+    }
+
+    // Each server has its own players, so each gets its own saved token. Production keeps the
+    // original key so existing players resume.
+    fn token_key(&self) -> String {
+        if self.server_url == PRODUCTION_URL {
+            "temty_token".to_string()
+        } else {
+            format!("temty_token@{}", self.server_url)
+        }
+    }
+}
 
 // --- CHAT CLIENT ---
 #[wasm_bindgen]
@@ -107,108 +123,8 @@ impl ChatClient {
 // --- PIXEL BUFFER ENGINE ---
 const WIDTH: u32 = 360;
 const HEIGHT: u32 = 640;
-const CHUNK_SIZE: i32 = 32; // User requested 32x32 plot per player
 const TILE_SIZE_BASE: f32 = 16.0;
 
-// --- ECONOMY / COSTS ---
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-struct Resources {
-    wood: f32,
-    stone: f32,
-    gold: f32,
-    food: f32,
-}
-
-impl Resources {
-    fn new(wood: f32, stone: f32, gold: f32, food: f32) -> Self {
-        Resources { wood, stone, gold, food }
-    }
-
-    fn has(&self, cost: &Resources) -> bool {
-        self.wood >= cost.wood && self.stone >= cost.stone && self.gold >= cost.gold && self.food >= cost.food
-    }
-
-}
-
-const COST_WALL: Resources = Resources { wood: 1.0, stone: 5.0, gold: 0.0, food: 0.0 };
-const COST_FARM: Resources = Resources { wood: 30.0, stone: 0.0, gold: 0.0, food: 0.0 };
-const COST_HOUSE: Resources = Resources { wood: 25.0, stone: 0.0, gold: 0.0, food: 0.0 };
-const COST_TOWER: Resources = Resources { wood: 0.0, stone: 40.0, gold: 0.0, food: 0.0 };
-const COST_BARRACKS: Resources = Resources { wood: 60.0, stone: 0.0, gold: 0.0, food: 0.0 };
-const COST_LUMBER_MILL: Resources = Resources { wood: 30.0, stone: 10.0, gold: 0.0, food: 0.0 };
-const COST_MINING_CAMP: Resources = Resources { wood: 30.0, stone: 10.0, gold: 0.0, food: 0.0 };
-const COST_WHEAT_MILL: Resources = Resources { wood: 30.0, stone: 10.0, gold: 0.0, food: 0.0 };
-const COST_WORKER: Resources = Resources { wood: 0.0, stone: 0.0, gold: 0.0, food: 50.0 };
-const COST_WARRIOR: Resources = Resources { wood: 0.0, stone: 0.0, gold: 20.0, food: 40.0 };
-const WORKER_HP: f32 = 50.0;
-const WARRIOR_HP: f32 = 120.0;
-const TOWN_HP: f32 = 800.0;
-const WALL_HP: f32 = 200.0;
-const TOWER_HP: f32 = 300.0;
-const FARM_HP: f32 = 220.0;
-const HOUSE_HP: f32 = 220.0;
-const BARRACKS_HP: f32 = 260.0;
-const LUMBER_HP: f32 = 220.0;
-const MINING_HP: f32 = 220.0;
-const WHEAT_HP: f32 = 220.0;
-const CARRY_CAP: f32 = 80.0;
-
-// --- BUILDING & UNIT KINDS ---
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum BuildKind {
-    Wall,
-    Farm,
-    House,
-    Tower,
-    Barracks,
-    LumberMill,
-    MiningCamp,
-    WheatMill,
-}
-
-impl BuildKind {
-    fn to_kind_id(&self) -> u8 {
-        match self {
-            BuildKind::Wall => 1,
-            BuildKind::Farm => 2,
-            BuildKind::House => 3,
-            BuildKind::Tower => 4,
-            BuildKind::Barracks => 5,
-            BuildKind::LumberMill => 6,
-            BuildKind::MiningCamp => 7,
-            BuildKind::WheatMill => 8,
-        }
-    }
-
-    fn cost(&self) -> Resources {
-        match self {
-            BuildKind::Wall => COST_WALL,
-            BuildKind::Farm => COST_FARM,
-            BuildKind::House => COST_HOUSE,
-            BuildKind::Tower => COST_TOWER,
-            BuildKind::Barracks => COST_BARRACKS,
-            BuildKind::LumberMill => COST_LUMBER_MILL,
-            BuildKind::MiningCamp => COST_MINING_CAMP,
-            BuildKind::WheatMill => COST_WHEAT_MILL,
-        }
-    }
-}
-
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum UnitKind {
-    Worker,
-    Warrior,
-}
-
-impl UnitKind {
-    fn to_u8(&self) -> u8 {
-        match self {
-            UnitKind::Worker => 0,
-            UnitKind::Warrior => 1,
-        }
-    }
-}
-
 struct TowerShot {
     x1: f32,
     y1: f32,
@@ -228,6 +144,9 @@ enum WsState {
     Connecting,
     Connected,
     Error,
+    // Waiting to retry; `at` is the performance.now() time of the next attempt
+    Reconnecting { at: f64 },
+    // Gave up (outdated client or banned)
     Closed,
 }
 
@@ -277,6 +196,7 @@ impl PixelBuffer {
         self.pixels[idx+3] = 255;
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn rect(&mut self, x: i32, y: i32, w: i32, h: i32, r: u8, g: u8, b: u8) {
         // Clip to screen
         let start_x = x.max(0);
@@ -297,6 +217,7 @@ impl PixelBuffer {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn rect_outline(&mut self, x: i32, y: i32, w: i32, h: i32, r: u8, g: u8, b: u8) {
         self.rect(x, y, w, 1, r, g, b);         // Top
         self.rect(x, y + h - 1, w, 1, r, g, b); // Bottom
@@ -304,6 +225,7 @@ impl PixelBuffer {
         self.rect(x + w - 1, y, 1, h, r, g, b); // Right
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, r: u8, g: u8, b: u8, dashed: bool) {
         let mut x = x0;
         let mut y = y0;
@@ -351,15 +273,8 @@ impl PartialOrd for Node {
 
 // --- GAME STATE ---
 
-#[derive(Clone, Copy, PartialEq)]
-enum TileType {
-    Grass,
-    Forest,
-    Mountain,
-    Gold,
-}
-
 struct Unit {
+    id: u64, // Server-assigned, stable for the unit's lifetime
     x: f32, // Global World Pos
     y: f32,
     path: Vec<(f32, f32)>, // Global Waypoints
@@ -411,8 +326,20 @@ struct GameState {
     my_chunk_x: i32,
     my_chunk_y: i32,
     other_players: Vec<PlayerInfo>,
+    config: ClientConfig,
     socket: Option<WebSocket>, // For sending commands
     ws_state: WsState,
+    // Failed connection attempts since the last Welcome (drives the backoff)
+    reconnect_attempt: u32,
+    // Cleared when the server refuses us for good; the socket then stays closed
+    reconnect: bool,
+    // Protocol of a newer client the server told us about; the HUD suggests a reload
+    update_available: Option<u32>,
+    // Set by ServerShutdown: the close that follows is a restart, not a failure. Holds how long
+    // the server suggested waiting before reconnecting.
+    restarting: Option<f64>,
+    // Why the server dropped us (Kicked); the HUD shows it until the next Welcome
+    kicked: Option<String>,
 
     // Input State
     last_touch_dist: Option<f32>,
@@ -443,9 +370,6 @@ struct GameState {
     pending_wall: Vec<(i32, i32)>, 
     building_active: bool,
     
-    // Sync
-    last_sync_time: f64,
-    
     // Smooth Zoom
     target_zoom: f32,
     
@@ -459,13 +383,26 @@ struct GameState {
     // Town Center menu
     tc_menu_open: bool,
 
-    // Remember last gather target per unit (owner_id, owner_local_idx) -> (tile_x, tile_y, kind)
-    gather_targets: HashMap<(i32, usize), (i32, i32, u8)>,
+    // Remember last gather target per unit (owner_id, unit_id) -> (tile_x, tile_y, kind)
+    gather_targets: HashMap<(i32, u64), (i32, i32, u8)>,
+
+    // Command acks: next seq to send, and what to roll back per outstanding seq
+    next_seq: u32,
+    pending_commands: HashMap<u32, PendingCommand>,
+    // Why the last command failed, shown until the timestamp (performance.now() ms)
+    notice: Option<(String, f64)>,
+
+    // Costs/HP/rates; replaced by the server's table on Welcome
+    balance: Balance,
+
+    // Chunk range last reported with SetView (x0, y0, x1, y1)
+    sent_view: Option<(i32, i32, i32, i32)>,
 }
 
 impl GameState {
-    fn new() -> GameState {
+    fn new(config: ClientConfig) -> GameState {
         let mut gs = GameState { 
+            config,
             chunks: HashMap::new(),
             units: Vec::new(),
             buildings: Vec::new(),
@@ -485,6 +422,11 @@ impl GameState {
             other_players: Vec::new(),
             socket: None,
             ws_state: WsState::Connecting,
+            reconnect_attempt: 0,
+            reconnect: true,
+            update_available: None,
+            restarting: None,
+            kicked: None,
             last_touch_dist: None,
             last_pan_x: None,
             last_pan_y: None,
@@ -502,13 +444,17 @@ impl GameState {
             wall_preview: Vec::new(),
             pending_wall: Vec::new(),
             building_active: false,
-            last_sync_time: 0.0,
             target_zoom: 1.5,
             show_delete_confirm: false,
             pending_single_build: None,
             training_queue: Vec::new(),
             tc_menu_open: false,
             gather_targets: HashMap::new(),
+            next_seq: 1,
+            pending_commands: HashMap::new(),
+            notice: None,
+            balance: Balance::default(),
+            sent_view: None,
         };
 
         // Generate Initial Chunk (0,0)
@@ -525,16 +471,32 @@ impl GameState {
         gs
     }
 
-    fn spawn_units_for_player(&mut self, pid: i32, cx: i32, cy: i32) {
-        let sx = (cx as f32 * CHUNK_SIZE as f32 * TILE_SIZE_BASE) + (CHUNK_SIZE as f32 * TILE_SIZE_BASE / 2.0);
-        let sy = (cy as f32 * CHUNK_SIZE as f32 * TILE_SIZE_BASE) + (CHUNK_SIZE as f32 * TILE_SIZE_BASE / 2.0);
-        
-        let color = if Some(pid) == self.my_id { (0, 0, 255) } else { (255, 0, 0) };
-        
-        self.units.push(Unit { x: sx + 30.0, y: sy + 30.0, path: Vec::new(), selected: false, kind: UnitKind::Worker.to_u8(), color, owner_id: pid, job: UnitJob::Idle, hp: WORKER_HP, carry_wood: 0.0, carry_stone: 0.0, carry_gold: 0.0, carry_food: 0.0 });
-        self.units.push(Unit { x: sx - 20.0, y: sy + 40.0, path: Vec::new(), selected: false, kind: UnitKind::Worker.to_u8(), color, owner_id: pid, job: UnitJob::Idle, hp: WORKER_HP, carry_wood: 0.0, carry_stone: 0.0, carry_gold: 0.0, carry_food: 0.0 });
-        if Some(pid) == self.my_id {
-            self.pop_used += 2;
+    fn unit_from_dto(&self, u: &UnitDTO) -> Unit {
+        let color = if Some(u.owner_id) == self.my_id { (0, 0, 255) } else { (255, 0, 0) };
+        Unit {
+            id: u.unit_id,
+            x: u.x,
+            y: u.y,
+            path: Vec::new(), // Server doesn't sync path, units appear idle
+            selected: false,
+            kind: u.kind,
+            color,
+            owner_id: u.owner_id,
+            job: UnitJob::Idle,
+            hp: u.hp,
+            carry_wood: u.carry_wood,
+            carry_stone: u.carry_stone,
+            carry_gold: u.carry_gold,
+            carry_food: u.carry_food,
+        }
+    }
+
+    fn spawn_units_for_player(&mut self, pid: i32, cx: i32, cy: i32, units: &[UnitDTO]) {
+        // Replace whatever we had for this player (e.g. on reconnect) with the server's units
+        self.units.retain(|u| u.owner_id != pid);
+        for u in units {
+            let unit = self.unit_from_dto(u);
+            self.units.push(unit);
         }
         
         // Spawn Building for this player
@@ -546,38 +508,113 @@ impl GameState {
             tile_y: cy * CHUNK_SIZE + mid, 
             kind: 0,
             owner_id: pid,
-            hp: TOWN_HP,
+            hp: self.balance.building_hp(TOWN_CENTER_KIND),
             selected: false,
         });
     }
 
-    fn calculate_tile_type(cx: i32, cy: i32, lx: i32, ly: i32) -> TileType {
-        // Ensure walkability for Town Center (center of chunk)
+    fn unit_index(&self, owner_id: i32, unit_id: u64) -> Option<usize> {
+        self.units.iter().position(|u| u.owner_id == owner_id && u.id == unit_id)
+    }
+
+    // Adds another player's unit, or replaces what we knew about it
+    fn upsert_unit(&mut self, u: &UnitDTO) {
+        let unit = self.unit_from_dto(u);
+        match self.unit_index(u.owner_id, u.unit_id) {
+            Some(idx) if Some(u.owner_id) != self.my_id => self.units[idx] = unit,
+            Some(_) => {}
+            None => self.units.push(unit),
+        }
+    }
+
+    // Drops other players' units, buildings and construction sites in a chunk the server stopped
+    // (or is about to restart) sending events for. Our own things are always kept up to date.
+    fn forget_chunk(&mut self, cx: i32, cy: i32) {
+        let chunk_px = CHUNK_SIZE as f32 * TILE_SIZE_BASE;
+        let in_chunk = |tx: i32, ty: i32| tx.div_euclid(CHUNK_SIZE) == cx && ty.div_euclid(CHUNK_SIZE) == cy;
+        let my_id = self.my_id;
+        self.units.retain(|u| {
+            Some(u.owner_id) == my_id || (u.x / chunk_px).floor() as i32 != cx || (u.y / chunk_px).floor() as i32 != cy
+        });
+        self.buildings.retain(|b| Some(b.owner_id) == my_id || !in_chunk(b.tile_x, b.tile_y));
+        self.server_progress.retain(|&(tx, ty), _| !in_chunk(tx, ty));
+    }
+
+    // Replaces everything we know with a FullState. Our own units keep their local path, job and
+    // selection so a resync doesn't interrupt what the player is doing.
+    fn replace_world(&mut self, players: Vec<PlayerInfo>, units: Vec<UnitDTO>, buildings: Vec<BuildingDTO>, sites: Vec<BuildSite>) {
+        let old: Vec<Unit> = std::mem::take(&mut self.units);
+        for dto in &units {
+            let mut unit = self.unit_from_dto(dto);
+            if let Some(prev) = old.iter().find(|u| u.owner_id == dto.owner_id && u.id == dto.unit_id) {
+                if Some(dto.owner_id) == self.my_id {
+                    unit.path = prev.path.clone();
+                    unit.job = prev.job;
+                }
+                unit.selected = prev.selected;
+            }
+            self.units.push(unit);
+        }
+        let live: HashSet<(i32, u64)> = self.units.iter().map(|u| (u.owner_id, u.id)).collect();
+        self.gather_targets.retain(|key, _| live.contains(key));
+
+        self.buildings = buildings.into_iter().map(|b| Building {
+            id: b.id,
+            tile_x: b.tile_x,
+            tile_y: b.tile_y,
+            kind: b.kind,
+            owner_id: b.owner_id,
+            hp: b.hp,
+            selected: false,
+        }).collect();
+        // Bases out of sight still show on the map, as on Welcome
         let mid = CHUNK_SIZE / 2;
-        if lx >= mid - 3 && lx <= mid + 3 && ly >= mid - 3 && ly <= mid + 3 {
-            return TileType::Grass;
+        let tc_hp = self.balance.building_hp(TOWN_CENTER_KIND);
+        for p in &players {
+            self.generate_chunk(p.chunk_x, p.chunk_y);
+            let (tile_x, tile_y) = (p.chunk_x * CHUNK_SIZE + mid, p.chunk_y * CHUNK_SIZE + mid);
+            if !self.buildings.iter().any(|b| b.tile_x == tile_x && b.tile_y == tile_y) {
+                self.buildings.push(Building { id: tile_x + tile_y * 100000, tile_x, tile_y, kind: 0, owner_id: p.id, hp: tc_hp, selected: false });
+            }
         }
+        self.other_players = players;
 
-        let seed = ((cx as i64 * 73856093) ^ (cy as i64 * 19349663) ^ (lx as i64 * 83492791) ^ (ly as i64 * 23492871)) as f64;
-        let r = (seed.sin() * 10000.0).fract().abs();
-        
-        if r < 0.25 { TileType::Forest }
-        else if r < 0.28 { TileType::Mountain }
-        else if r < 0.283 { TileType::Gold }
-        else { TileType::Grass }
+        self.server_progress = sites.into_iter()
+            .map(|s| ((s.tile_x, s.tile_y), TileProgress { progress: s.progress, kind: s.kind }))
+            .collect();
+        // Acks may have been among what was dropped; the state above already reflects them
+        self.pending_commands.clear();
+        self.pending_wall.clear();
+        self.building_active = false;
+    }
+
+    // Tells the server which chunks the camera covers, whenever that changes
+    fn sync_view(&mut self) {
+        if self.my_id.is_none() {
+            return;
+        }
+        let chunk_px = CHUNK_SIZE as f32 * TILE_SIZE_BASE;
+        let half_w = WIDTH as f32 / 2.0 / self.zoom;
+        let half_h = HEIGHT as f32 / 2.0 / self.zoom;
+        let view = (
+            ((self.camera_x - half_w) / chunk_px).floor() as i32,
+            ((self.camera_y - half_h) / chunk_px).floor() as i32,
+            ((self.camera_x + half_w) / chunk_px).floor() as i32,
+            ((self.camera_y + half_h) / chunk_px).floor() as i32,
+        );
+        if self.sent_view == Some(view) {
+            return;
+        }
+        self.sent_view = Some(view);
+        let (chunk_x0, chunk_y0, chunk_x1, chunk_y1) = view;
+        self.send_command(GameMessage::SetView { chunk_x0, chunk_y0, chunk_x1, chunk_y1 }, None);
     }
 
     fn generate_chunk(&mut self, cx: i32, cy: i32) {
         if self.chunks.contains_key(&(cx, cy)) { return; }
         
-        let mut tiles = vec![TileType::Grass; (CHUNK_SIZE * CHUNK_SIZE) as usize];
-        for y in 0..CHUNK_SIZE {
-            for x in 0..CHUNK_SIZE {
-                let idx = (y * CHUNK_SIZE + x) as usize;
-                tiles[idx] = GameState::calculate_tile_type(cx, cy, x, y);
-            }
-        }
-        self.chunks.insert((cx, cy), Chunk { tiles });
+        // Same generator as the server, which validates builds and gathers against it
+        self.chunks.insert((cx, cy), Chunk { tiles: terrain::generate_chunk(cx, cy) });
     }
 
     fn get_tile_type(&self, gx: i32, gy: i32) -> Option<TileType> {
@@ -593,7 +630,7 @@ impl GameState {
             Some(chunk.tiles[(ly * CHUNK_SIZE + lx) as usize])
         } else {
             // Virtual terrain for pathfinding (Fog of War)
-            Some(GameState::calculate_tile_type(cx, cy, lx, ly))
+            Some(terrain::calculate_tile_type(cx, cy, lx, ly))
         }
     }
 
@@ -674,11 +711,10 @@ impl GameState {
 
                 if self.is_tile_walkable(next.0, next.1) {
                     // Prevent corner cutting
-                    if *dx != 0 && *dy != 0 {
-                        if !self.is_tile_walkable(current.0 + dx, current.1) || 
-                           !self.is_tile_walkable(current.0, current.1 + dy) {
-                            continue;
-                        }
+                    if *dx != 0 && *dy != 0 &&
+                       (!self.is_tile_walkable(current.0 + dx, current.1) ||
+                        !self.is_tile_walkable(current.0, current.1 + dy)) {
+                        continue;
                     }
 
                     let new_cost = cost_so_far[&current] + 1;
@@ -722,6 +758,7 @@ impl GameState {
         } else {
             self.zoom = self.target_zoom;
         }
+        self.sync_view();
 
         let unit_positions: Vec<(f32, f32)> = self.units.iter().map(|u| (u.x, u.y)).collect();
         let mut updates: Vec<(usize, f32, f32, bool)> = Vec::new();
@@ -902,19 +939,9 @@ impl GameState {
                                      }
                                   }
                                   // Send server commands
-                                  if let Some(ws) = &self.socket {
-                                      for idx in selected_indices {
-                                          // Map to player-local index
-                                            let mut my_unit_idx = 0;
-                                            for (k, u) in self.units.iter().enumerate() {
-                                                if u.owner_id == my_id {
-                                                    if k == idx { break; }
-                                                    my_unit_idx += 1;
-                                                }
-                                            }
-                                            let msg = GameMessage::UnitMove { player_id: my_id, unit_idx: my_unit_idx, x: target_x, y: target_y };
-                                            if let Ok(json) = serde_json::to_string(&msg) { let _ = ws.send_with_str(&json); }
-                                      }
+                                  for idx in selected_indices {
+                                        let unit_id = self.units[idx].id;
+                                        self.send_unit_move(unit_id, target_x, target_y);
                                   }
                              }
                         }
@@ -951,12 +978,8 @@ impl GameState {
                             self.server_progress.insert((tx, ty), TileProgress { progress: 0.0, kind: BuildKind::Wall.to_kind_id() });
                             
                             // Send Build Command
-                            if let Some(ws) = &self.socket {
-                               let msg = GameMessage::Build { kind: BuildKind::Wall.to_kind_id(), tile_x: tx, tile_y: ty };
-                               if let Ok(json) = serde_json::to_string(&msg) {
-                                   let _ = ws.send_with_str(&json);
-                               }
-                            }
+                            let msg = GameMessage::Build { kind: BuildKind::Wall.to_kind_id(), tile_x: tx, tile_y: ty };
+                            self.send_command(msg, Some(PendingCommand::Build { tile_x: tx, tile_y: ty, wall: true }));
                         }
                      }
                  }
@@ -970,38 +993,6 @@ impl GameState {
             s.ttl -= dt as f32;
         }
         self.tower_shots.retain(|s| s.ttl > 0.0);
-        
-        // --- SYNC LOGIC ---
-        // Send UnitSync for MY units every 100ms
-        let now = web_sys::window().unwrap().performance().unwrap().now();
-        if now - self.last_sync_time > 100.0 {
-            if let Some(ws) = &self.socket {
-                // Only send if OPEN (Ready State 1)
-                if ws.ready_state() == 1 {
-                    let my_id = if let Some(id) = self.my_id { id } else { return };
-                    
-                    let mut my_unit_idx = 0;
-                    for u in &self.units {
-                        if u.owner_id == my_id {
-                            let msg = GameMessage::UnitSync {
-                                player_id: my_id,
-                                unit_idx: my_unit_idx,
-                                x: u.x,
-                                y: u.y
-                            };
-                            
-                             if let Ok(json) = serde_json::to_string(&msg) {
-                                 // Safe to send due to ready_state check, but Result ignored
-                                 let _ = ws.send_with_str(&json);
-                             }
-                             
-                            my_unit_idx += 1;
-                        }
-                    }
-                }
-            }
-            self.last_sync_time = now;
-        }
     }
 
     fn screen_to_world(&self, screen_x: f32, screen_y: f32) -> (f32, f32) {
@@ -1046,7 +1037,7 @@ impl GameState {
             ];
             for (idx, kind) in options.iter().enumerate() {
                 let opt_y = build_btn_y - ((idx as f32 + 1.0) * (btn_size + 10.0));
-                    let affordable = self.resources.has(&kind.cost());
+                    let affordable = self.resources.has(&self.balance.build_cost(kind.to_kind_id()));
                     if screen_x >= build_btn_x && screen_x <= build_btn_x + btn_size &&
                        screen_y >= opt_y && screen_y <= opt_y + btn_size {
                         if !affordable { return; }
@@ -1082,13 +1073,9 @@ impl GameState {
             let opt_x = build_btn_x;
             if screen_x >= opt_x && screen_x <= opt_x + btn_size &&
                screen_y >= opt_y && screen_y <= opt_y + btn_size {
-                if self.pop_used < self.pop_cap && self.can_afford(&COST_WORKER) {
-                    if let Some(ws) = &self.socket {
-                        let msg = GameMessage::SpawnUnit;
-                        if let Ok(json) = serde_json::to_string(&msg) {
-                            let _ = ws.send_with_str(&json);
-                        }
-                    }
+                if self.pop_used < self.pop_cap && self.can_afford(&self.balance.costs.worker) {
+                    self.training_queue.push(0.0); // Spawn is instant server-side
+                    self.send_command(GameMessage::SpawnUnit, Some(PendingCommand::SpawnWorker));
                 }
                 self.tc_menu_open = false;
                 return;
@@ -1171,25 +1158,14 @@ impl GameState {
                         Some((true, *idx))
                     } else { None };
                     if let Some((is_b, idx)) = entity_info {
-                        if let Some(ws) = &self.socket {
-                            let msg = if is_b {
-                                let b = &self.buildings[idx];
-                                GameMessage::DeleteBuilding { tile_x: b.tile_x, tile_y: b.tile_y }
-                            } else {
-                                // Calculate player-local unit index
-                                let mut my_idx = 0;
-                                for (k, u) in self.units.iter().enumerate() {
-                                    if u.owner_id == my_id {
-                                        if k == idx { break; }
-                                        my_idx += 1;
-                                    }
-                                }
-                                GameMessage::DeleteUnit { unit_idx: my_idx }
-                            };
-                            if let Ok(json) = serde_json::to_string(&msg) {
-                                let _ = ws.send_with_str(&json);
-                            }
-                        }
+                        let msg = if is_b {
+                            let b = &self.buildings[idx];
+                            GameMessage::DeleteBuilding { tile_x: b.tile_x, tile_y: b.tile_y }
+                        } else {
+                            let unit_id = self.units[idx].id;
+                            GameMessage::DeleteUnit { unit_id }
+                        };
+                        self.send_command(msg, None);
                     }
                     self.show_delete_confirm = false;
                     return;
@@ -1280,14 +1256,9 @@ impl GameState {
                     if screen_x >= train_btn_x && screen_x <= train_btn_x + btn_size &&
                        screen_y >= train_btn_y && screen_y <= train_btn_y + btn_size {
                         if self.pop_used >= self.pop_cap { return; }
-                        if !self.can_afford(&COST_WARRIOR) { return; }
-                        if let Some(ws) = &self.socket {
-                            let building_id = self.buildings[b_idx].id;
-                            let msg = GameMessage::TrainUnit { building_id, kind: UnitKind::Warrior.to_u8() };
-                            if let Ok(json) = serde_json::to_string(&msg) {
-                                let _ = ws.send_with_str(&json);
-                            }
-                        }
+                        if !self.can_afford(&self.balance.costs.warrior) { return; }
+                        let building_id = self.buildings[b_idx].id;
+                        self.send_command(GameMessage::TrainUnit { building_id, kind: UnitKind::Warrior.to_u8() }, None);
                         return;
                     }
                 }
@@ -1354,26 +1325,14 @@ impl GameState {
                             self.show_delete_confirm = false;
 
                             if let Some((is_b, idx)) = entity_info {
-                                if let Some(ws) = &self.socket {
-                                    let msg = if is_b {
-                                        let b = &self.buildings[idx];
-                                        GameMessage::DeleteBuilding { tile_x: b.tile_x, tile_y: b.tile_y }
-                                    } else {
-                                        // Calculate player-local unit index
-                                        let mut my_idx = 0;
-                                        for (k, u) in self.units.iter().enumerate() {
-                                            if u.owner_id == my_id {
-                                                if k == idx { break; }
-                                                my_idx += 1;
-                                            }
-                                        }
-                                        GameMessage::DeleteUnit { unit_idx: my_idx }
-                                    };
-                                    
-                                    if let Ok(json) = serde_json::to_string(&msg) {
-                                        let _ = ws.send_with_str(&json);
-                                    }
-                                }
+                                let msg = if is_b {
+                                    let b = &self.buildings[idx];
+                                    GameMessage::DeleteBuilding { tile_x: b.tile_x, tile_y: b.tile_y }
+                                } else {
+                                    let unit_id = self.units[idx].id;
+                                    GameMessage::DeleteUnit { unit_id }
+                                };
+                                self.send_command(msg, None);
                             }
                             return;
                         }
@@ -1418,12 +1377,10 @@ impl GameState {
                     if self.build_mode && self.wall_end.is_some() {
                         self.confirm_wall_build();
                     } else if let Some((kind, tx, ty)) = self.pending_single_build.take() {
-                        if let Some(ws) = &self.socket {
-                            let msg = GameMessage::Build { kind: kind.to_kind_id(), tile_x: tx, tile_y: ty };
-                            if let Ok(json) = serde_json::to_string(&msg) {
-                                let _ = ws.send_with_str(&json);
-                            }
-                        }
+                        // Show the site right away; rolled back if the server refuses the build
+                        self.server_progress.insert((tx, ty), TileProgress { progress: 0.0, kind: kind.to_kind_id() });
+                        let msg = GameMessage::Build { kind: kind.to_kind_id(), tile_x: tx, tile_y: ty };
+                        self.send_command(msg, Some(PendingCommand::Build { tile_x: tx, tile_y: ty, wall: false }));
                         self.selected_build = None;
                     }
                     return;
@@ -1501,27 +1458,9 @@ impl GameState {
                                 }
                             }
                             
-                            if let Some(ws) = &self.socket {
-                                for idx in selected_indices {
-                                    // Need to find player-local index
-                                    let mut my_unit_idx = 0;
-                                    for (k, u) in self.units.iter().enumerate() {
-                                        if u.owner_id == my_id {
-                                            if k == idx { break; }
-                                            my_unit_idx += 1;
-                                        }
-                                    }
-                                    
-                                    let msg = GameMessage::UnitMove { 
-                                        player_id: my_id, 
-                                        unit_idx: my_unit_idx, 
-                                        x: target_x, 
-                                        y: target_y 
-                                    };
-                                    if let Ok(json) = serde_json::to_string(&msg) {
-                                        let _ = ws.send_with_str(&json);
-                                    }
-                                }
+                            for idx in selected_indices {
+                                let unit_id = self.units[idx].id;
+                                self.send_unit_move(unit_id, target_x, target_y);
                             }
                         }
                     }
@@ -1644,17 +1583,10 @@ impl GameState {
                            let target_tx = (tile_left / TILE_SIZE_BASE) as i32;
                            let target_ty = (tile_top / TILE_SIZE_BASE) as i32;
                            for (i, ux, uy) in selected_units {
-                               // player-local idx
-                               let mut my_idx = 0;
-                               for (k, u) in self.units.iter().enumerate() {
-                                   if u.owner_id == my_id {
-                                       if k == i { break; }
-                                       my_idx += 1;
-                                   }
-                               }
+                               let unit_id = self.units[i].id;
                                if let Some(u) = self.units.get(i) {
                                    if (u.carry_wood + u.carry_stone + u.carry_gold + u.carry_food) > 0.0 {
-                                       deposit_units.push(my_idx);
+                                       deposit_units.push(unit_id);
                                    }
                                }
 
@@ -1667,23 +1599,15 @@ impl GameState {
                                            u.path = path;
                                            u.job = UnitJob::Returning;
                                        }
-                                       move_commands.push((my_idx, wx, wy));
+                                       move_commands.push((unit_id, wx, wy));
                                    }
                                }
                            }
-                           if let Some(ws) = &self.socket {
-                               if !deposit_units.is_empty() {
-                                   let msg = GameMessage::DepositNow { unit_ids: deposit_units.clone() };
-                                   if let Ok(json) = serde_json::to_string(&msg) {
-                                       let _ = ws.send_with_str(&json);
-                                   }
-                               }
-                               for (idx_local, wx, wy) in move_commands {
-                                   let msg = GameMessage::UnitMove { player_id: my_id, unit_idx: idx_local, x: wx, y: wy };
-                                   if let Ok(json) = serde_json::to_string(&msg) {
-                                       let _ = ws.send_with_str(&json);
-                                   }
-                               }
+                           if !deposit_units.is_empty() {
+                               self.send_command(GameMessage::DepositNow { unit_ids: deposit_units }, None);
+                           }
+                           for (unit_id, wx, wy) in move_commands {
+                               self.send_unit_move(unit_id, wx, wy);
                            }
                            return;
                        } else {
@@ -1746,7 +1670,9 @@ impl GameState {
                         }
                     }
 
-                    // Move selected units
+                    // Move selected units (the server walks them to the clicked tile's center)
+                    let wx = clicked_tile_x as f32 * TILE_SIZE_BASE + TILE_SIZE_BASE / 2.0;
+                    let wy = clicked_tile_y as f32 * TILE_SIZE_BASE + TILE_SIZE_BASE / 2.0;
                     let mut paths = Vec::new();
                     let mut move_commands = Vec::new();
                     
@@ -1762,42 +1688,20 @@ impl GameState {
                         if !path.is_empty() {
                             paths.push((i, path));
                             
-                            let mut my_unit_idx = 0;
-                            for (k, u) in self.units.iter().enumerate() {
-                                if u.owner_id == my_id {
-                                    if k == i { break; }
-                                    my_unit_idx += 1;
-                                }
-                            }
-                            move_commands.push((my_unit_idx, wx, wy));
+                            let unit_id = self.units[i].id;
+                            move_commands.push((unit_id, wx, wy));
                         }
                     }
                     
                     for (i, path) in paths {
                         self.units[i].path = path;
                         self.units[i].job = UnitJob::Idle;
-                        let mut my_idx = 0;
-                        for (k, u) in self.units.iter().enumerate() {
-                            if u.owner_id == my_id {
-                                if k == i { break; }
-                                my_idx += 1;
-                            }
-                        }
-                        self.gather_targets.remove(&(my_id, my_idx));
+                        let unit_id = self.units[i].id;
+                        self.gather_targets.remove(&(my_id, unit_id));
                     }
                     
-                    if let Some(ws) = &self.socket {
-                        for (idx, tx, ty) in move_commands {
-                            let msg = GameMessage::UnitMove { 
-                                player_id: my_id, 
-                                unit_idx: idx, 
-                                x: tx, 
-                                y: ty 
-                            };
-                            if let Ok(json) = serde_json::to_string(&msg) {
-                                let _ = ws.send_with_str(&json);
-                            }
-                        }
+                    for (idx, tx, ty) in move_commands {
+                        self.send_unit_move(idx, tx, ty);
                     }
                 }
             }
@@ -1936,10 +1840,35 @@ impl GameState {
         }
         
         // Chunk doesn't exist - use calculate_tile_type to check
-        let tile_type = Self::calculate_tile_type(cx, cy, lx, ly);
+        let tile_type = terrain::calculate_tile_type(cx, cy, lx, ly);
         matches!(tile_type, TileType::Grass)
     }
 
+    /// Sends a move intent for one of my units. The server only takes the target tile, plans its own
+    /// path and streams positions back via UnitSync; local paths are just a prediction.
+    fn send_unit_move(&mut self, unit_id: u64, x: f32, y: f32) {
+        let msg = GameMessage::MoveUnit {
+            unit_id,
+            tile_x: (x / TILE_SIZE_BASE).floor() as i32,
+            tile_y: (y / TILE_SIZE_BASE).floor() as i32,
+        };
+        self.send_command(msg, None);
+    }
+
+    /// Sends a command tagged with the next seq. `undo` is rolled back if the server's CommandAck
+    /// rejects it.
+    fn send_command(&mut self, msg: GameMessage, undo: Option<PendingCommand>) {
+        let seq = self.next_seq;
+        let Some(ws) = &self.socket else { return };
+        if let Ok(json) = serde_json::to_string(&ClientCommand { seq, msg }) {
+            let _ = ws.send_with_str(&json);
+            self.next_seq = self.next_seq.wrapping_add(1);
+            if let Some(undo) = undo {
+                self.pending_commands.insert(seq, undo);
+            }
+        }
+    }
+
     fn can_afford(&self, cost: &Resources) -> bool {
         self.resources.has(cost)
     }
@@ -1962,7 +1891,7 @@ impl GameState {
         }
         // Check resources for all previewed walls
         let wall_count = self.wall_preview.len();
-        if !self.can_afford_total(&COST_WALL, wall_count) {
+        if !self.can_afford_total(&self.balance.costs.wall, wall_count) {
             self.cancel_wall_build();
             return;
         }
@@ -2018,26 +1947,9 @@ impl GameState {
                          }
                      }
                      
-                     if let Some(ws) = &self.socket {
-                         for idx in selected_indices {
-                            let mut my_unit_idx = 0;
-                            for (k, u) in self.units.iter().enumerate() {
-                                if u.owner_id == my_id {
-                                    if k == idx { break; }
-                                    my_unit_idx += 1;
-                                }
-                            }
-                             
-                            let msg = GameMessage::UnitMove { 
-                                player_id: my_id, 
-                                unit_idx: my_unit_idx, 
-                                x: target_x, 
-                                y: target_y 
-                            };
-                            if let Ok(json) = serde_json::to_string(&msg) {
-                                let _ = ws.send_with_str(&json);
-                            }
-                         }
+                     for idx in selected_indices {
+                        let unit_id = self.units[idx].id;
+                        self.send_unit_move(unit_id, target_x, target_y);
                      }
                  }
             }
@@ -2077,7 +1989,7 @@ impl GameState {
                 let dxw = wx - from_x;
                 let dyw = wy - from_y;
                 let d2 = dxw * dxw + dyw * dyw;
-                if best.map_or(true, |(bd2, _, _)| d2 < bd2) {
+                if best.is_none_or(|(bd2, _, _)| d2 < bd2) {
                     best = Some((d2, wx, wy));
                 }
             }
@@ -2097,7 +2009,7 @@ impl GameState {
                 let dxw = wx - from_x;
                 let dyw = wy - from_y;
                 let d2 = dxw*dxw + dyw*dyw;
-                if best.map_or(true, |(bd2, _, _)| d2 < bd2) {
+                if best.is_none_or(|(bd2, _, _)| d2 < bd2) {
                     best = Some((d2, wx, wy));
                 }
             }
@@ -2105,14 +2017,9 @@ impl GameState {
         best.map(|(_, wx, wy)| (wx, wy))
     }
 
-    fn nearest_dropoff(&self, from_x: f32, from_y: f32, res_kind: u8) -> Option<(f32, f32)> {
+    fn nearest_dropoff(&self, from_x: f32, from_y: f32, res_kind: ResourceKind) -> Option<(f32, f32)> {
         let my_id = self.my_id?;
-        let allowed: &[u8] = match res_kind {
-            2 => &[0, BuildKind::LumberMill.to_kind_id()],
-            3 | 4 => &[0, BuildKind::MiningCamp.to_kind_id()],
-            5 => &[0, BuildKind::WheatMill.to_kind_id()],
-            _ => &[0],
-        };
+        let allowed = [TOWN_CENTER_KIND, res_kind.dropoff().to_kind_id()];
         let mut best: Option<(f32, f32, f32)> = None; // dist, wx, wy
         for b in &self.buildings {
             if b.owner_id != my_id { continue; }
@@ -2122,7 +2029,7 @@ impl GameState {
             let dx = wx - from_x;
             let dy = wy - from_y;
             let dist = (dx*dx + dy*dy).sqrt();
-            if best.map_or(true, |(bd, _, _)| dist < bd) {
+            if best.is_none_or(|(bd, _, _)| dist < bd) {
                 best = Some((dist, wx, wy));
             }
         }
@@ -2162,15 +2069,9 @@ impl GameState {
                     u.job = UnitJob::Gathering;
                     if !path.is_empty() { u.path = path; }
                 }
-                let mut my_idx = 0;
-                for (k, uu) in self.units.iter().enumerate() {
-                    if uu.owner_id == my_id {
-                        if k == idx { break; }
-                        my_idx += 1;
-                    }
-                }
-                unit_ids.push(my_idx);
-                self.gather_targets.insert((my_id, my_idx), (target_tile.0, target_tile.1, match kind {
+                let unit_id = self.units[idx].id;
+                unit_ids.push(unit_id);
+                self.gather_targets.insert((my_id, unit_id), (target_tile.0, target_tile.1, match kind {
                     GatherKind::Wood => 2,
                     GatherKind::Stone => 3,
                     GatherKind::Gold => 4,
@@ -2178,22 +2079,18 @@ impl GameState {
                 }));
             }
 
-            if let Some(ws) = &self.socket {
-                let msg = GameMessage::AssignGather {
-                    unit_ids,
-                    target_x: target_tile.0,
-                    target_y: target_tile.1,
-                    kind: match kind {
-                        GatherKind::Wood => 2,
-                        GatherKind::Stone => 3,
-                        GatherKind::Gold => 4,
-                        GatherKind::Farm => 5,
-                    },
-                };
-                if let Ok(json) = serde_json::to_string(&msg) {
-                    let _ = ws.send_with_str(&json);
-                }
-            }
+            let msg = GameMessage::AssignGather {
+                unit_ids,
+                target_x: target_tile.0,
+                target_y: target_tile.1,
+                kind: match kind {
+                    GatherKind::Wood => 2,
+                    GatherKind::Stone => 3,
+                    GatherKind::Gold => 4,
+                    GatherKind::Farm => 5,
+                },
+            };
+            self.send_command(msg, None);
         }
     }
 
@@ -2275,30 +2172,489 @@ impl GameState {
 
 // --- MAIN LOOP ---
 
-#[wasm_bindgen]
-pub fn run_game() -> Result<(), JsValue> {
-    let window = web_sys::window().expect("no global `window` exists");
-    let document = window.document().expect("should have a document on window");
-    let canvas = document.get_element_by_id("temty-canvas")
-        .expect("should have #temty-canvas on the page")
-        .dyn_into::<HtmlCanvasElement>()?;
-    
-    canvas.set_width(WIDTH);
-    canvas.set_height(HEIGHT);
-    
-    let context = canvas
-        .get_context("2d")?
-        .unwrap()
-        .dyn_into::<CanvasRenderingContext2d>()?;
+// Decodes a server frame: JSON text, or binary when we asked for it in Hello
+fn decode_frame(data: &JsValue) -> Option<GameMessage> {
+    if let Some(txt) = data.as_string() {
+        return serde_json::from_str(&txt).ok();
+    }
+    let buf = data.dyn_ref::<js_sys::ArrayBuffer>()?;
+    wire::from_binary(&js_sys::Uint8Array::new(buf).to_vec()).ok()
+}
 
-    let mut buffer = PixelBuffer::new(WIDTH, HEIGHT);
-    let game_state = Rc::new(RefCell::new(GameState::new()));
+// Covers the game until the page is reloaded with a client the server accepts.
+fn show_outdated_overlay() {
+    let window = web_sys::window().unwrap();
+    let document = window.document().unwrap();
+    let body = document.body().unwrap();
 
-    // --- WEBSOCKET ---
-    let ws = WebSocket::new("wss://temty-server-production.up.railway.app").expect("Failed to connect to WS");
-    
-    // Assign Socket to GameState
-    game_state.borrow_mut().socket = Some(ws.clone());
+    let overlay = document.create_element("div").unwrap();
+    overlay.set_attribute("style", "position:fixed;top:0;left:0;width:100%;height:100%;background:rgba(0,0,0,0.9);color:red;display:flex;flex-direction:column;justify-content:center;align-items:center;z-index:9999;font-size:24px;text-align:center;padding:20px;").unwrap();
+
+    overlay.set_inner_html(r#"
+        <div>⚠️ CLIENT OUTDATED ⚠️</div>
+        <div style="color:white;font-size:16px;margin-top:10px;">A new version of the game is available.</div>
+        <div style="color:#aaa;font-size:14px;margin-top:5px;">Please refresh your browser to update.</div>
+        <button onclick="location.reload(true)" style="margin-top:20px;padding:10px 20px;font-size:18px;cursor:pointer;">Update Now</button>
+    "#);
+
+    body.append_child(&overlay).unwrap();
+}
+
+// Shown instead of the game when the page's settings can't be used. The message quotes the page
+// URL, so it goes in as text, never as HTML.
+fn show_config_error(message: &str) {
+    let window = web_sys::window().unwrap();
+    let document = window.document().unwrap();
+    let body = document.body().unwrap();
+
+    let overlay = document.create_element("div").unwrap();
+    overlay.set_attribute("style", "position:fixed;top:0;left:0;width:100%;height:100%;background:rgba(0,0,0,0.9);color:red;display:flex;flex-direction:column;justify-content:center;align-items:center;z-index:9999;font-size:24px;text-align:center;padding:20px;").unwrap();
+    overlay.set_inner_html(r#"
+        <div>⚠️ INVALID CONFIG ⚠️</div>
+        <div style="color:white;font-size:16px;margin-top:10px;"></div>
+        <div style="color:#aaa;font-size:14px;margin-top:5px;">Not connecting. Fix the page's query string or TEMTY_CONFIG and reload.</div>
+    "#);
+    if let Ok(Some(detail)) = overlay.query_selector("div:nth-child(2)") {
+        detail.set_text_content(Some(message));
+    }
+
+    body.append_child(&overlay).unwrap();
+}
+
+// Applies one message from the server.
+fn handle_message(state: &mut GameState, msg: GameMessage) {
+    match msg {
+        GameMessage::Join { .. } => {}, 
+        GameMessage::Hello { .. } => {},
+        GameMessage::ServerHello { version, features, build, .. } => {
+            log(&format!("Server {} speaks protocol {} ({:?})", build, version, features));
+            // A newer server means a newer client is out too
+            if version > PROTOCOL_VERSION {
+                state.update_available = Some(version);
+            }
+        },
+        GameMessage::ServerShutdown { reason, reconnect_after_ms } => {
+            log(&format!("Server shutting down: {} (back in ~{}ms)", reason, reconnect_after_ms));
+            state.restarting = Some(reconnect_after_ms as f64);
+        },
+        GameMessage::NewClientAvailable { version } => {
+            log(&format!("Client protocol {} is available", version));
+            state.update_available = Some(version);
+        },
+        GameMessage::VersionRejected { version, min_version, build } => {
+            log(&format!("Server {} needs protocol {} or newer; we speak {}", build, min_version, version));
+            show_outdated_overlay();
+            state.my_id = None; // Disable input processing by removing ID
+            state.reconnect = false; // Same version would be refused again
+        },
+        GameMessage::DepositNow { .. } => {}, 
+        GameMessage::MoveUnit { .. } => {},
+        GameMessage::CommandAck { seq, ok, reason } => {
+            let undo = state.pending_commands.remove(&seq);
+            if !ok {
+                match undo {
+                    Some(PendingCommand::Build { tile_x, tile_y, wall }) => {
+                        state.server_progress.remove(&(tile_x, tile_y));
+                        if wall {
+                            // Abandon the rest of the wall rather than retrying the same tile
+                            state.pending_wall.clear();
+                            state.building_active = false;
+                        }
+                    }
+                    Some(PendingCommand::SpawnWorker) => {
+                        state.training_queue.pop();
+                    }
+                    None => {}
+                }
+                let text = reason.map(|r| r.describe()).unwrap_or("Command failed");
+                log(&format!("Command {} rejected: {}", seq, text));
+                // We acted on something the server no longer has: our copy is stale
+                if matches!(reason, Some(RejectReason::UnknownUnit | RejectReason::UnknownBuilding)) {
+                    state.send_command(GameMessage::RequestResync, None);
+                }
+                let now = web_sys::window().unwrap().performance().unwrap().now();
+                state.notice = Some((text.to_string(), now + 2500.0));
+            }
+        },
+        GameMessage::Kicked { reason, reconnect } => {
+            log(&format!("Kicked: {} (reconnect: {})", reason, reconnect));
+            state.reconnect = reconnect;
+            state.kicked = Some(reason);
+        },
+        GameMessage::Error { message } => {
+            log(&format!("Server Error: {}", message));
+            let now = web_sys::window().unwrap().performance().unwrap().now();
+            state.notice = Some((message, now + 5000.0));
+        },
+        GameMessage::UnitCarry { owner_id, unit_id, carry_wood, carry_stone, carry_gold, carry_food } => {
+            if Some(owner_id) == state.my_id {
+                if let Some(idx) = state.unit_index(owner_id, unit_id) {
+                    // Snapshot current data immutably
+                    let (ux, uy, job_before) = {
+                        let u = &state.units[idx];
+                        (u.x, u.y, u.job)
+                    };
+
+                    let total = carry_wood + carry_stone + carry_gold + carry_food;
+                    let needs_return = job_before == UnitJob::Gathering && total >= state.balance.carry_cap - 0.1 && total >= 1.0;
+                    let emptied_return = job_before == UnitJob::Returning && total <= 0.01;
+
+                    // Precompute return path (immutable borrow)
+                    let mut return_path: Option<Vec<(f32, f32)>> = None;
+                    if needs_return {
+                        if let Some((tx, ty)) = state.nearest_dropoff(ux, uy, if carry_wood > 0.0 { ResourceKind::Wood } else if carry_stone > 0.0 { ResourceKind::Stone } else if carry_gold > 0.0 { ResourceKind::Gold } else { ResourceKind::Food }) {
+                            let dx = tx - ux;
+                            let dy = ty - uy;
+                            let dist2 = dx*dx + dy*dy;
+                            let drop_rad2 = (TILE_SIZE_BASE * 2.5).powi(2);
+                            // If already at dropoff, no need to path back
+                            if dist2 > drop_rad2 {
+                                let p = state.find_path((ux, uy), (tx, ty));
+                                if !p.is_empty() {
+                                    return_path = Some(p);
+                                }
+                            } else {
+                                // Already at drop-off; don't trigger return path
+                                return_path = Some(Vec::new());
+                            }
+                        }
+                    }
+
+                    // Apply carry updates mutably
+                    {
+                        if let Some(u) = state.units.get_mut(idx) {
+                            u.carry_wood = carry_wood;
+                            u.carry_stone = carry_stone;
+                            u.carry_gold = carry_gold;
+                            u.carry_food = carry_food;
+                            if needs_return {
+                                if let Some(ref p) = return_path {
+                                    u.path = p.clone();
+                                    u.job = UnitJob::Returning;
+                                }
+                            } else if emptied_return {
+                                u.job = UnitJob::Idle; // may switch to Gathering below
+                            }
+                        }
+                    }
+
+                    // Send commands after mutable borrow
+                    if needs_return {
+                        // Path is stored reversed: first element is the destination
+                        if let Some(dest) = return_path.as_ref().and_then(|p| p.first()) {
+                            state.send_unit_move(unit_id, dest.0, dest.1);
+                        }
+                    } else if emptied_return {
+                        if let Some((tx, ty, k)) = state.gather_targets.get(&(owner_id, unit_id)).cloned() {
+                            // Predict the path back to the resource; the server walks the unit there on AssignGather
+                            if let Some(adj) = state.find_closest_walkable_cardinal(tx, ty, ux, uy)
+                                .or_else(|| state.find_closest_walkable(tx, ty, ux, uy))
+                                .or_else(|| state.find_adjacent_walkable(tx, ty)) {
+                                let path_back = state.find_path((ux, uy), adj);
+                                if let Some(u) = state.units.get_mut(idx) {
+                                    u.job = UnitJob::Gathering;
+                                    if !path_back.is_empty() {
+                                        u.path = path_back.clone();
+                                    } else {
+                                        u.path.clear();
+                                    }
+                                }
+                            } else if let Some(u) = state.units.get_mut(idx) {
+                                // If no path could be found, still mark as gathering
+                                u.job = UnitJob::Gathering;
+                            }
+
+                            let msg = GameMessage::AssignGather { unit_ids: vec![unit_id], target_x: tx, target_y: ty, kind: k };
+                            state.send_command(msg, None);
+                        }
+                    }
+                }
+            }
+        },
+        GameMessage::Welcome { player_id, chunk_x, chunk_y, players, units, buildings, token, resources, pop_cap, pop_used, balance } => {
+            // Same player again after a dropped connection: keep the camera where it was
+            let resumed = state.my_id == Some(player_id);
+            state.balance = *balance;
+            state.my_id = Some(player_id);
+            state.my_chunk_x = chunk_x;
+            state.my_chunk_y = chunk_y;
+            state.other_players = players.clone();
+
+            // Save Token
+            let window = web_sys::window().unwrap();
+            let storage = window.local_storage().unwrap().unwrap();
+            storage.set_item(&state.config.token_key(), &token).unwrap();
+
+            // Ensure chunks exist and spawn buildings for ALL players (me + others)
+            state.buildings.clear(); // Clear buildings to avoid dupes if any
+
+            // 1. Add Implicit Town Centers from Player Chunks
+            let tc_hp = state.balance.building_hp(TOWN_CENTER_KIND);
+            for p in &players {
+                state.generate_chunk(p.chunk_x, p.chunk_y);
+                let mid = CHUNK_SIZE / 2;
+                state.buildings.push(Building { 
+                    id: p.chunk_x * CHUNK_SIZE + mid + (p.chunk_y * CHUNK_SIZE + mid) * 100000,
+                    tile_x: p.chunk_x * CHUNK_SIZE + mid, 
+                    tile_y: p.chunk_y * CHUNK_SIZE + mid, 
+                    kind: 0,
+                    owner_id: p.id,
+                    hp: tc_hp,
+                    selected: false,
+                });
+            }
+
+            // 2. Add Explicit Buildings from DB
+            for b in buildings {
+                state.buildings.push(Building {
+                    id: b.id,
+                    tile_x: b.tile_x,
+                    tile_y: b.tile_y,
+                    kind: b.kind,
+                    owner_id: b.owner_id,
+                    hp: b.hp,
+                    selected: false,
+                });
+            }
+
+            // Recalculate population cap from existing houses
+            if let Some(my_id) = state.my_id {
+                let house_count = state.buildings.iter().filter(|b| b.owner_id == my_id && b.kind == BuildKind::House.to_kind_id()).count() as i32;
+                state.pop_cap = 5 + house_count;
+            } else {
+                state.pop_cap = 5;
+            }
+
+            // Move camera to my chunk
+            if !resumed {
+                state.camera_x = (chunk_x as f32 * CHUNK_SIZE as f32 * TILE_SIZE_BASE) + (CHUNK_SIZE as f32 * TILE_SIZE_BASE / 2.0);
+                state.camera_y = (chunk_y as f32 * CHUNK_SIZE as f32 * TILE_SIZE_BASE) + (CHUNK_SIZE as f32 * TILE_SIZE_BASE / 2.0);
+            }
+
+            // Reset Units and Load from Server
+            state.units.clear();
+            state.pop_used = 0;
+
+            for u in units {
+                let unit = state.unit_from_dto(&u);
+                state.units.push(unit);
+                if Some(u.owner_id) == state.my_id {
+                    state.pop_used += 1;
+                }
+            }
+
+        // Set resources and pop cap from welcome
+        state.resources = resources;
+        state.pop_cap = pop_cap;
+        state.pop_used = pop_used;
+        state.server_progress.clear();
+        state.sent_view = None;
+        // Anything in flight on the old connection is gone; the Welcome is the whole truth
+        state.pending_commands.clear();
+        state.training_queue.clear();
+        state.pending_wall.clear();
+        state.building_active = false;
+        state.reconnect_attempt = 0;
+        state.restarting = None;
+        state.kicked = None;
+
+            log(&format!("Welcome! Assigned to Chunk ({}, {})", chunk_x, chunk_y));
+        },
+        GameMessage::NewPlayer { player, units } => {
+            // Ignore if it's me (already handled in Welcome)
+            if Some(player.id) == state.my_id {
+                return;
+            }
+
+            log(&format!("New Player joined at ({}, {})", player.chunk_x, player.chunk_y));
+            state.generate_chunk(player.chunk_x, player.chunk_y);
+            state.other_players.push(player.clone());
+            state.spawn_units_for_player(player.id, player.chunk_x, player.chunk_y, &units);
+        },
+        GameMessage::UnitMove { player_id, unit_id, tile_x, tile_y } => {
+            // Server accepted a move for someone else's unit: predict the walk locally.
+            // Our own moves were already predicted when the command was issued.
+            if Some(player_id) != state.my_id {
+                if let Some(idx) = state.unit_index(player_id, unit_id) {
+                    let start = (state.units[idx].x, state.units[idx].y);
+                    let target = (
+                        tile_x as f32 * TILE_SIZE_BASE + TILE_SIZE_BASE / 2.0,
+                        tile_y as f32 * TILE_SIZE_BASE + TILE_SIZE_BASE / 2.0,
+                    );
+                    // Empty prediction is fine: UnitSync will carry the unit along
+                    let path = state.find_path(start, target);
+                    state.units[idx].path = path;
+                }
+            }
+        },
+        GameMessage::UnitSync { player_id, unit_id, x, y } => {
+            // Authoritative position from the server's movement tick (applies to our own units too)
+            if let Some(idx) = state.unit_index(player_id, unit_id) {
+                let u = &mut state.units[idx];
+                // Snap on large divergence, otherwise ease towards the server position
+                let dist = ((u.x - x).powi(2) + (u.y - y).powi(2)).sqrt();
+                if dist > 50.0 {
+                    u.x = x;
+                    u.y = y;
+                } else {
+                    // Smooth lerp (adjust factor for smoothness vs lag)
+                    u.x += (x - u.x) * 0.2;
+                    u.y += (y - u.y) * 0.2;
+                }
+            }
+        },
+        GameMessage::SpawnUnit => {}, // Should not happen on client
+        GameMessage::UnitSpawned { unit } => {
+            // Add new unit
+            let new_unit = state.unit_from_dto(&unit);
+            state.units.push(new_unit);
+            if Some(unit.owner_id) == state.my_id {
+                state.pop_used += 1;
+                // Drain one training slot if this was a worker
+                if unit.kind == UnitKind::Worker.to_u8() && !state.training_queue.is_empty() {
+                    state.training_queue.remove(0);
+                }
+            }
+            log("New unit spawned!");
+        },
+        GameMessage::Build { .. } => {}, // Should not be received by client, but good for completeness
+        GameMessage::BuildingSpawned { building } => {
+        state.buildings.push(Building {
+            id: building.id,
+            tile_x: building.tile_x,
+            tile_y: building.tile_y,
+            kind: building.kind,
+            owner_id: building.owner_id,
+            hp: building.hp,
+            selected: false,
+        });
+            if Some(building.owner_id) == state.my_id && building.kind == BuildKind::House.to_kind_id() {
+                state.pop_cap += 1;
+            }
+            // Clear progress for that tile
+            state.server_progress.remove(&(building.tile_x, building.tile_y));
+            log("New building spawned!");
+        },
+        GameMessage::BuildProgress { tile_x, tile_y, kind, progress } => {
+            if progress < 0.0 {
+                state.server_progress.remove(&(tile_x, tile_y));
+            } else {
+                state.server_progress.insert((tile_x, tile_y), TileProgress { progress, kind });
+            }
+        },
+        GameMessage::UnitHp { owner_id, unit_id, hp } => {
+            if let Some(idx) = state.unit_index(owner_id, unit_id) {
+                state.units[idx].hp = hp;
+            }
+        },
+        GameMessage::BuildingHp { tile_x, tile_y, hp } => {
+            for b in &mut state.buildings {
+                if b.tile_x == tile_x && b.tile_y == tile_y {
+                    b.hp = hp;
+                    break;
+                }
+            }
+        },
+        GameMessage::ResourceUpdate { player_id, resources, pop_cap, pop_used } => {
+            if Some(player_id) == state.my_id {
+                state.resources = resources;
+                state.pop_cap = pop_cap;
+                state.pop_used = pop_used;
+            }
+        },
+        GameMessage::TowerShot { x1, y1, x2, y2 } => {
+            state.tower_shots.push(TowerShot { x1, y1, x2, y2, ttl: 0.3 });
+        },
+        GameMessage::UnitDied { owner_id, unit_id } => {
+            if let Some(i) = state.unit_index(owner_id, unit_id) {
+                state.units.remove(i);
+            }
+            state.gather_targets.remove(&(owner_id, unit_id));
+        },
+        GameMessage::TrainUnit { .. } => {},
+        GameMessage::AssignGather { .. } => {},
+        GameMessage::BuildingDestroyed { tile_x, tile_y } => {
+            state.buildings.retain(|b| !(b.tile_x == tile_x && b.tile_y == tile_y));
+            state.server_progress.remove(&(tile_x, tile_y));
+        },
+        GameMessage::DeleteUnit { .. } => {},
+        GameMessage::DeleteBuilding { .. } => {},
+        GameMessage::SetView { .. } => {},
+        GameMessage::RequestResync => {},
+        GameMessage::TickBatch { events, .. } => {
+            // All in this callback, so the whole batch lands before the next frame renders
+            for event in events {
+                handle_message(state, event);
+            }
+        },
+        GameMessage::FullState { players, units, buildings, sites, resources, pop_cap, pop_used } => {
+            log(&format!("Resynced: {} units, {} buildings", units.len(), buildings.len()));
+            state.replace_world(players, units, buildings, sites);
+            state.resources = resources;
+            state.pop_cap = pop_cap;
+            state.pop_used = pop_used;
+        },
+        GameMessage::ChunkEnter { chunk_x, chunk_y, units, buildings, sites } => {
+            state.generate_chunk(chunk_x, chunk_y);
+            state.forget_chunk(chunk_x, chunk_y);
+            for u in &units {
+                state.upsert_unit(u);
+            }
+            for b in buildings {
+                if state.buildings.iter().any(|o| o.tile_x == b.tile_x && o.tile_y == b.tile_y) {
+                    continue;
+                }
+                state.buildings.push(Building {
+                    id: b.id,
+                    tile_x: b.tile_x,
+                    tile_y: b.tile_y,
+                    kind: b.kind,
+                    owner_id: b.owner_id,
+                    hp: b.hp,
+                    selected: false,
+                });
+            }
+            for site in sites {
+                state.server_progress.insert((site.tile_x, site.tile_y), TileProgress { progress: site.progress, kind: site.kind });
+            }
+        },
+        GameMessage::ChunkLeave { chunk_x, chunk_y } => {
+            state.forget_chunk(chunk_x, chunk_y);
+        },
+        GameMessage::UnitEnter { unit } => {
+            state.upsert_unit(&unit);
+        },
+        GameMessage::UnitLeave { owner_id, unit_id } => {
+            if Some(owner_id) != state.my_id {
+                if let Some(i) = state.unit_index(owner_id, unit_id) {
+                    state.units.remove(i);
+                }
+            }
+        }
+    }}
+
+// Opens the socket and wires its handlers. Called again (with backoff) whenever the connection
+// drops, e.g. on every server redeploy; the stored token resumes the same player.
+fn connect(game_state: &Rc<RefCell<GameState>>) {
+    let url = game_state.borrow().config.server_url.clone();
+    let ws = match WebSocket::new(&url) {
+        Ok(ws) => ws,
+        Err(e) => {
+            // Only a malformed URL fails here; retrying would not help
+            log(&format!("Bad server URL {}: {:?}", url, e));
+            game_state.borrow_mut().ws_state = WsState::Closed;
+            return;
+        }
+    };
+
+    // Binary frames arrive as ArrayBuffers instead of Blobs, so they can be decoded synchronously
+    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+    {
+        let mut state = game_state.borrow_mut();
+        state.socket = Some(ws.clone());
+        state.ws_state = WsState::Connecting;
+    }
 
     // OnOpen - Send Handshake
     {
@@ -2307,20 +2663,25 @@ pub fn run_game() -> Result<(), JsValue> {
         let onopen_callback = Closure::wrap(Box::new(move || {
              log("WS connected. Sending handshake...");
              // mark connected
-             state_clone.borrow_mut().ws_state = WsState::Connected;
+             let (token_key, encoding) = {
+                 let mut state = state_clone.borrow_mut();
+                 state.ws_state = WsState::Connected;
+                 (state.config.token_key(), state.config.encoding)
+             };
              // Get token from localStorage
              let window = web_sys::window().unwrap();
              let storage = window.local_storage().unwrap().unwrap();
-             let token = storage.get_item("temty_token").unwrap_or(None);
+             let token = storage.get_item(&token_key).unwrap_or(None);
              
-             let msg = serde_json::to_string(&GameMessage::Join { version: CLIENT_VERSION, token }).unwrap();
-             ws_clone.send_with_str(&msg).expect("Failed to send Join message");
+             let features = protocol::feature::ALL.iter().map(|f| f.to_string()).collect();
+             let msg = serde_json::to_string(&GameMessage::Hello { version: PROTOCOL_VERSION, features, token, encoding }).unwrap();
+             ws_clone.send_with_str(&msg).expect("Failed to send Hello message");
         }) as Box<dyn FnMut()>);
         ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
         onopen_callback.forget();
     }
 
-    // OnError - log
+    // OnError - log (a close always follows)
     {
         let state_clone = game_state.clone();
         let onerror_callback = Closure::wrap(Box::new(move |e: web_sys::Event| {
@@ -2331,466 +2692,104 @@ pub fn run_game() -> Result<(), JsValue> {
         onerror_callback.forget();
     }
 
-    // OnClose - log code/reason
+    // OnClose - try again after a backoff, unless the server told us not to come back
     {
         let state_clone = game_state.clone();
         let onclose_callback = Closure::wrap(Box::new(move |e: web_sys::CloseEvent| {
-            state_clone.borrow_mut().ws_state = WsState::Closed;
             log(&format!("WS closed code={} reason={} was_clean={}", e.code(), e.reason(), e.was_clean()));
+            let mut state = state_clone.borrow_mut();
+            state.socket = None;
+            if !state.reconnect {
+                state.ws_state = WsState::Closed;
+                return;
+            }
+            // After a restart notice, wait as long as the server asked (spread out so everyone
+            // doesn't arrive at once); otherwise back off
+            let delay = match state.restarting {
+                Some(after_ms) if state.reconnect_attempt == 0 => after_ms * (1.0 + 0.5 * random()),
+                _ => reconnect_delay_ms(state.reconnect_attempt),
+            };
+            state.reconnect_attempt += 1;
+            let window = web_sys::window().unwrap();
+            let now = window.performance().unwrap().now();
+            state.ws_state = WsState::Reconnecting { at: now + delay };
+            drop(state);
+
+            log(&format!("Reconnecting in {:.1}s", delay / 1000.0));
+            let gs = state_clone.clone();
+            let retry = Closure::once_into_js(move || connect(&gs));
+            let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(retry.unchecked_ref(), delay as i32);
         }) as Box<dyn FnMut(_)>);
         ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
         onclose_callback.forget();
     }
-    
-    // OnClose - Handle disconnection (likely due to server restart/update)
-    {
-        let onclose_callback = Closure::wrap(Box::new(move || {
-            log("WS Disconnected.");
-            // Don't auto-reload - it causes unsettling screen flashes.
-            // User can manually refresh if needed.
-        }) as Box<dyn FnMut()>);
-        ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
-        onclose_callback.forget();
-    }
 
     {
         let gs = game_state.clone();
         let onmessage_callback = Closure::wrap(Box::new(move |e: MessageEvent| {
-            if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                let txt: String = txt.into();
-                if let Ok(msg) = serde_json::from_str::<GameMessage>(&txt) {
-                    let mut state = gs.borrow_mut();
-                    match msg {
-                        GameMessage::Join { .. } => {}, 
-                        GameMessage::DepositNow { .. } => {}, 
-                        GameMessage::Error { message } => {
-                            log(&format!("Server Error: {}", message));
-                            
-                            // Specific handling for version errors
-                            if message.contains("Client version") && message.contains("too old") {
-                                let window = web_sys::window().unwrap();
-                                let document = window.document().unwrap();
-                                let body = document.body().unwrap();
-                                
-                                let overlay = document.create_element("div").unwrap();
-                                overlay.set_attribute("style", "position:fixed;top:0;left:0;width:100%;height:100%;background:rgba(0,0,0,0.9);color:red;display:flex;flex-direction:column;justify-content:center;align-items:center;z-index:9999;font-size:24px;text-align:center;padding:20px;").unwrap();
-                                
-                                overlay.set_inner_html(r#"
-                                    <div>⚠️ CLIENT OUTDATED ⚠️</div>
-                                    <div style="color:white;font-size:16px;margin-top:10px;">A new version of the game is available.</div>
-                                    <div style="color:#aaa;font-size:14px;margin-top:5px;">Please refresh your browser to update.</div>
-                                    <button onclick="location.reload(true)" style="margin-top:20px;padding:10px 20px;font-size:18px;cursor:pointer;">Update Now</button>
-                                "#);
-                                
-                                body.append_child(&overlay).unwrap();
-                                
-                                // Stop the game loop
-                                // We can't easily stop the requestAnimationFrame loop from here without structure changes,
-                                // but the overlay blocks interaction. Ideally we'd set a flag in GameState.
-                                state.my_id = None; // Disable input processing by removing ID
-                            } else {
-                                web_sys::window().unwrap().alert_with_message(&message).unwrap();
-                            }
-                        },
-                        GameMessage::UnitCarry { owner_id, unit_idx, carry_wood, carry_stone, carry_gold, carry_food } => {
-                            if Some(owner_id) == state.my_id {
-                                // Locate unit index and my-local index without holding a mutable borrow
-                                let mut target_idx = None;
-                                let mut my_local_idx = 0;
-                                for (k, u) in state.units.iter().enumerate() {
-                                    if u.owner_id == owner_id {
-                                        if my_local_idx == unit_idx {
-                                            target_idx = Some(k);
-                                            break;
-                                        }
-                                        my_local_idx += 1;
-                                    }
-                                }
+            if let Some(msg) = decode_frame(&e.data()) {
+                handle_message(&mut gs.borrow_mut(), msg);
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+        onmessage_callback.forget();
+    }
+}
 
-                                if let Some(idx) = target_idx {
-                                    // Snapshot current data immutably
-                                    let (ux, uy, job_before) = {
-                                        let u = &state.units[idx];
-                                        (u.x, u.y, u.job)
-                                    };
-
-                                    let total = carry_wood + carry_stone + carry_gold + carry_food;
-                                    let needs_return = job_before == UnitJob::Gathering && total >= CARRY_CAP - 0.1 && total >= 1.0;
-                                    let emptied_return = job_before == UnitJob::Returning && total <= 0.01;
-
-                                    // Precompute return path (immutable borrow)
-                                    let mut return_path: Option<Vec<(f32, f32)>> = None;
-                                    if needs_return {
-                                        if let Some((tx, ty)) = state.nearest_dropoff(ux, uy, if carry_wood > 0.0 { 2 } else if carry_stone > 0.0 { 3 } else if carry_gold > 0.0 { 4 } else { 5 }) {
-                                            let dx = tx - ux;
-                                            let dy = ty - uy;
-                                            let dist2 = dx*dx + dy*dy;
-                                            let drop_rad2 = (TILE_SIZE_BASE * 2.5).powi(2);
-                                            // If already at dropoff, no need to path back
-                                            if dist2 > drop_rad2 {
-                                                let p = state.find_path((ux, uy), (tx, ty));
-                                                if !p.is_empty() {
-                                                    return_path = Some(p);
-                                                }
-                                            } else {
-                                                // Already at drop-off; don't trigger return path
-                                                return_path = Some(Vec::new());
-                                            }
-                                        }
-                                    }
+// Doubles per failed attempt up to RECONNECT_MAX_MS, with jitter so a redeploy doesn't bring
+// every client back in the same instant.
+fn reconnect_delay_ms(attempt: u32) -> f64 {
+    let base = (RECONNECT_BASE_MS * 2f64.powi(attempt.min(16) as i32)).min(RECONNECT_MAX_MS);
+    base * (0.5 + 0.5 * random())
+}
 
-                                    // Apply carry updates mutably
-                                    {
-                                        if let Some(u) = state.units.get_mut(idx) {
-                                            u.carry_wood = carry_wood;
-                                            u.carry_stone = carry_stone;
-                                            u.carry_gold = carry_gold;
-                                            u.carry_food = carry_food;
-                                            if needs_return {
-                                                if let Some(ref p) = return_path {
-                                                    u.path = p.clone();
-                                                    u.job = UnitJob::Returning;
-                                                }
-                                            } else if emptied_return {
-                                                u.job = UnitJob::Idle; // may switch to Gathering below
-                                            }
-                                        }
-                                    }
+// `options` is an optional object such as `{ server: "ws://localhost:9001", wire: "json" }`;
+// see ClientConfig for the settings and how query parameters override them.
+#[wasm_bindgen]
+pub fn run_game(options: JsValue) -> Result<(), JsValue> {
+    let window = web_sys::window().expect("no global `window` exists");
+    let document = window.document().expect("should have a document on window");
+    let canvas = document.get_element_by_id("temty-canvas")
+        .expect("should have #temty-canvas on the page")
+        .dyn_into::<HtmlCanvasElement>()?;
+    
+    canvas.set_width(WIDTH);
+    canvas.set_height(HEIGHT);
+    
+    let context = canvas
+        .get_context("2d")?
+        .unwrap()
+        .dyn_into::<CanvasRenderingContext2d>()?;
 
-                                    // Send commands after mutable borrow
-                                    if needs_return {
-                                        if let Some(ref p) = return_path {
-                                            if let Some(ws) = &state.socket {
-                                                if let Some(last) = p.last() {
-                                                    let msg = GameMessage::UnitMove { player_id: owner_id, unit_idx: my_local_idx, x: last.0, y: last.1 };
-                                                    if let Ok(json) = serde_json::to_string(&msg) { let _ = ws.send_with_str(&json); }
-                                                }
-                                            }
-                                        }
-                                    } else if emptied_return {
-                                        if let Some((tx, ty, k)) = state.gather_targets.get(&(owner_id, my_local_idx)).cloned() {
-                                            // Plot a path back to the resource before reassigning gather
-                                            let mut move_target: Option<(f32, f32)> = None;
-                                            if let Some(adj) = state.find_closest_walkable_cardinal(tx, ty, ux, uy)
-                                                .or_else(|| state.find_closest_walkable(tx, ty, ux, uy))
-                                                .or_else(|| state.find_adjacent_walkable(tx, ty)) {
-                                                let path_back = state.find_path((ux, uy), adj);
-                                                move_target = path_back.last().copied().or(Some(adj));
-                                                if let Some(u) = state.units.get_mut(idx) {
-                                                    u.job = UnitJob::Gathering;
-                                                    if !path_back.is_empty() {
-                                                        u.path = path_back.clone();
-                                                    } else {
-                                                        u.path.clear();
-                                                    }
-                                                }
-                                            } else if let Some(u) = state.units.get_mut(idx) {
-                                                // If no path could be found, still mark as gathering
-                                                u.job = UnitJob::Gathering;
-                                            }
-
-                                            if let Some(ws) = &state.socket {
-                                                let msg = GameMessage::AssignGather { unit_ids: vec![my_local_idx], target_x: tx, target_y: ty, kind: k };
-                                                if let Ok(json) = serde_json::to_string(&msg) { let _ = ws.send_with_str(&json); }
-
-                                                if let Some((mx, my)) = move_target {
-                                                    let move_msg = GameMessage::UnitMove { player_id: owner_id, unit_idx: my_local_idx, x: mx, y: my };
-                                                    if let Ok(json) = serde_json::to_string(&move_msg) { let _ = ws.send_with_str(&json); }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        },
-                        GameMessage::Welcome { player_id, chunk_x, chunk_y, players, units, buildings, token, resources, pop_cap, pop_used } => {
-                            state.my_id = Some(player_id);
-                            state.my_chunk_x = chunk_x;
-                            state.my_chunk_y = chunk_y;
-                            state.other_players = players.clone();
-                            
-                            // Save Token
-                            let window = web_sys::window().unwrap();
-                            let storage = window.local_storage().unwrap().unwrap();
-                            storage.set_item("temty_token", &token).unwrap();
-                            
-                            // Ensure chunks exist and spawn buildings for ALL players (me + others)
-                            state.buildings.clear(); // Clear buildings to avoid dupes if any
-                            
-                            // 1. Add Implicit Town Centers from Player Chunks
-                            for p in &players {
-                                state.generate_chunk(p.chunk_x, p.chunk_y);
-                                let mid = CHUNK_SIZE / 2;
-                                state.buildings.push(Building { 
-                                    id: p.chunk_x * CHUNK_SIZE + mid + (p.chunk_y * CHUNK_SIZE + mid) * 100000,
-                                    tile_x: p.chunk_x * CHUNK_SIZE + mid, 
-                                    tile_y: p.chunk_y * CHUNK_SIZE + mid, 
-                                    kind: 0,
-                                    owner_id: p.id,
-                                    hp: TOWN_HP,
-                                    selected: false,
-                                });
-                            }
-                            
-                            // 2. Add Explicit Buildings from DB
-                            for b in buildings {
-                                state.buildings.push(Building {
-                                    id: b.id,
-                                    tile_x: b.tile_x,
-                                    tile_y: b.tile_y,
-                                    kind: b.kind,
-                                    owner_id: b.owner_id,
-                                    hp: b.hp,
-                                    selected: false,
-                                });
-                            }
+    // A bad setting stops here, before any socket is opened
+    let config = match ClientConfig::load(&options) {
+        Ok(config) => config,
+        Err(e) => {
+            log(&format!("Invalid config: {}", e));
+            show_config_error(&e);
+            return Err(JsValue::from_str(&e));
+        }
+    };
 
-                            // Recalculate population cap from existing houses
-                            if let Some(my_id) = state.my_id {
-                                let house_count = state.buildings.iter().filter(|b| b.owner_id == my_id && b.kind == BuildKind::House.to_kind_id()).count() as i32;
-                                state.pop_cap = 5 + house_count;
-                            } else {
-                                state.pop_cap = 5;
-                            }
-                            
-                            // Move camera to my chunk
-                            state.camera_x = (chunk_x as f32 * CHUNK_SIZE as f32 * TILE_SIZE_BASE) + (CHUNK_SIZE as f32 * TILE_SIZE_BASE / 2.0);
-                            state.camera_y = (chunk_y as f32 * CHUNK_SIZE as f32 * TILE_SIZE_BASE) + (CHUNK_SIZE as f32 * TILE_SIZE_BASE / 2.0);
-                            
-                            // Reset Units and Load from Server
-                            state.units.clear();
-                            state.pop_used = 0;
-                            
-                            for u in units {
-                                let color = if Some(u.owner_id) == state.my_id { (0, 0, 255) } else { (255, 0, 0) };
-                                state.units.push(Unit {
-                                    x: u.x,
-                                    y: u.y,
-                                    path: Vec::new(), // Server doesn't sync path, units appear idle
-                                    selected: false,
-                                    kind: u.kind,
-                                    color,
-                                    owner_id: u.owner_id,
-                                    job: UnitJob::Idle,
-                                    hp: u.hp,
-                                    carry_wood: u.carry_wood,
-                                    carry_stone: u.carry_stone,
-                                    carry_gold: u.carry_gold,
-                                    carry_food: u.carry_food,
-                                });
-                                if Some(u.owner_id) == state.my_id {
-                                    state.pop_used += 1;
-                                }
-                            }
+    let mut buffer = PixelBuffer::new(WIDTH, HEIGHT);
+    let game_state = Rc::new(RefCell::new(GameState::new(config)));
 
-                        // Set resources and pop cap from welcome
-                        state.resources = resources;
-                        state.pop_cap = pop_cap;
-                        state.pop_used = pop_used;
-                        state.server_progress.clear();
-
-                            log(&format!("Welcome! Assigned to Chunk ({}, {})", chunk_x, chunk_y));
-                        },
-                        GameMessage::NewPlayer { player } => {
-                            // Ignore if it's me (already handled in Welcome)
-                            if Some(player.id) == state.my_id {
-                                return;
-                            }
-                            
-                            log(&format!("New Player joined at ({}, {})", player.chunk_x, player.chunk_y));
-                            state.generate_chunk(player.chunk_x, player.chunk_y);
-                            state.other_players.push(player.clone());
-                            state.spawn_units_for_player(player.id, player.chunk_x, player.chunk_y);
-                        },
-                        GameMessage::UnitMove { player_id, unit_idx, x, y } => {
-                            if Some(player_id) != state.my_id {
-                                // Find the unit
-                                let mut count = 0;
-                                let mut target_unit_idx = None;
-                                
-                                for (i, u) in state.units.iter().enumerate() {
-                                    if u.owner_id == player_id {
-                                        if count == unit_idx {
-                                            target_unit_idx = Some(i);
-                                            break;
-                                        }
-                                        count += 1;
-                                    }
-                                }
-                                
-                                if let Some(idx) = target_unit_idx {
-                                    let start_x = state.units[idx].x;
-                                    let start_y = state.units[idx].y;
-
-                                    // CRITICAL FIX: Ensure chunks exist for the path!
-                                    let min_wx = start_x.min(x);
-                                    let min_wy = start_y.min(y);
-                                    let max_wx = start_x.max(x);
-                                    let max_wy = start_y.max(y);
-
-        let _min_cx = (min_wx / (CHUNK_SIZE as f32 * TILE_SIZE_BASE)).floor() as i32;
-        let _min_cy = (min_wy / (CHUNK_SIZE as f32 * TILE_SIZE_BASE)).floor() as i32;
-        let _max_cx = (max_wx / (CHUNK_SIZE as f32 * TILE_SIZE_BASE)).floor() as i32;
-        let _max_cy = (max_wy / (CHUNK_SIZE as f32 * TILE_SIZE_BASE)).floor() as i32;
-
-                                    // Removed aggressive chunk generation for Fog of War logic
-                                    // Chunks will generate only when units approach them in update()
-
-                                    let path = state.find_path((start_x, start_y), (x, y));
-                                    state.units[idx].path = path;
-                                    
-                                    // Teleport fallback to prevent desync
-                                    if state.units[idx].path.is_empty() {
-                                        state.units[idx].x = x;
-                                        state.units[idx].y = y;
-                                    }
-                                }
-                            }
-                        },
-                        GameMessage::UnitSync { player_id, unit_idx, x, y } => {
-                             if Some(player_id) != state.my_id {
-                                // Snap remote unit to position (or Lerp in future)
-                                let mut count = 0;
-                                for u in &mut state.units {
-                                    if u.owner_id == player_id {
-                                        if count == unit_idx {
-                                            // LERP or SNAP?
-                                            // For now SNAP to ensure sync.
-                                            // To make it smooth, we can apply simple easing:
-                                            // u.x = u.x + (x - u.x) * 0.5; 
-                                            // But let's snap first to fix the "out of sync" complaint.
-                                            
-                                            // Actually, if we only receive 10 updates/sec, snap looks choppy.
-                                            // Let's use a simple smooth approach:
-                                            let dist = ((u.x - x).powi(2) + (u.y - y).powi(2)).sqrt();
-                                            if dist > 50.0 {
-                                                u.x = x;
-                                                u.y = y;
-                                            } else {
-                                                // Smooth lerp (adjust factor for smoothness vs lag)
-                                                u.x += (x - u.x) * 0.2;
-                                                u.y += (y - u.y) * 0.2;
-                                            }
-                                            break;
-                                        }
-                                        count += 1;
-                                    }
-                                }
-                             }
-                        },
-                        GameMessage::SpawnUnit => {}, // Should not happen on client
-                        GameMessage::UnitSpawned { unit } => {
-                            // Add new unit
-                            let color = if Some(unit.owner_id) == state.my_id { (0, 0, 255) } else { (255, 0, 0) };
-                            state.units.push(Unit {
-                                x: unit.x,
-                                y: unit.y,
-                                path: Vec::new(),
-                                selected: false,
-                                kind: unit.kind,
-                                color,
-                                owner_id: unit.owner_id,
-                                job: UnitJob::Idle,
-                                hp: unit.hp,
-                                carry_wood: unit.carry_wood,
-                                carry_stone: unit.carry_stone,
-                                carry_gold: unit.carry_gold,
-                                carry_food: unit.carry_food,
-                            });
-                            if Some(unit.owner_id) == state.my_id {
-                                state.pop_used += 1;
-                                // Drain one training slot if this was a worker
-                                if unit.kind == UnitKind::Worker.to_u8() && !state.training_queue.is_empty() {
-                                    state.training_queue.remove(0);
-                                }
-                            }
-                            log("New unit spawned!");
-                        },
-                        GameMessage::Build { .. } => {}, // Should not be received by client, but good for completeness
-                        GameMessage::BuildingSpawned { building } => {
-                        state.buildings.push(Building {
-                            id: building.id,
-                            tile_x: building.tile_x,
-                            tile_y: building.tile_y,
-                            kind: building.kind,
-                            owner_id: building.owner_id,
-                            hp: building.hp,
-                            selected: false,
-                        });
-                            if Some(building.owner_id) == state.my_id && building.kind == BuildKind::House.to_kind_id() {
-                                state.pop_cap += 1;
-                            }
-                            // Clear progress for that tile
-                            state.server_progress.remove(&(building.tile_x, building.tile_y));
-                            log("New building spawned!");
-                        },
-                        GameMessage::BuildProgress { tile_x, tile_y, kind, progress } => {
-                            if progress < 0.0 {
-                                state.server_progress.remove(&(tile_x, tile_y));
-                            } else {
-                                state.server_progress.insert((tile_x, tile_y), TileProgress { progress, kind });
-                            }
-                        },
-                        GameMessage::UnitHp { owner_id, unit_idx, hp } => {
-                            let mut count = 0;
-                            for u in &mut state.units {
-                                if u.owner_id == owner_id {
-                                    if count == unit_idx {
-                                        u.hp = hp;
-                                        break;
-                                    }
-                                    count += 1;
-                                }
-                            }
-                        },
-                        GameMessage::BuildingHp { tile_x, tile_y, hp } => {
-                            for b in &mut state.buildings {
-                                if b.tile_x == tile_x && b.tile_y == tile_y {
-                                    b.hp = hp;
-                                    break;
-                                }
-                            }
-                        },
-                        GameMessage::ResourceUpdate { player_id, resources, pop_cap, pop_used } => {
-                            if Some(player_id) == state.my_id {
-                                state.resources = resources;
-                                state.pop_cap = pop_cap;
-                                state.pop_used = pop_used;
-                            }
-                        },
-                        GameMessage::TowerShot { x1, y1, x2, y2 } => {
-                            state.tower_shots.push(TowerShot { x1, y1, x2, y2, ttl: 0.3 });
-                        },
-                        GameMessage::UnitDied { owner_id, unit_idx } => {
-                            let mut count = 0;
-                            let mut remove: Option<usize> = None;
-                            for (i, u) in state.units.iter().enumerate() {
-                                if u.owner_id == owner_id {
-                                    if count == unit_idx {
-                                        remove = Some(i);
-                                        break;
-                                    }
-                                    count += 1;
-                                }
-                            }
-                            if let Some(i) = remove {
-                                state.units.remove(i);
-                            }
-                        },
-                        GameMessage::TrainUnit { .. } => {},
-                        GameMessage::AssignGather { .. } => {},
-                        GameMessage::BuildingDestroyed { tile_x, tile_y } => {
-                            state.buildings.retain(|b| !(b.tile_x == tile_x && b.tile_y == tile_y));
-                            state.server_progress.remove(&(tile_x, tile_y));
-                        },
-                        GameMessage::DeleteUnit { .. } => {},
-                        GameMessage::DeleteBuilding { .. } => {}
-                    }
-                }
+    // --- WEBSOCKET ---
+    connect(&game_state);
+
+    // Background tabs get throttled and may have missed events; catch up when shown again
+    {
+        let gs = game_state.clone();
+        let document = web_sys::window().unwrap().document().unwrap();
+        let doc = document.clone();
+        let closure = Closure::wrap(Box::new(move || {
+            let mut gs = gs.borrow_mut();
+            if !doc.hidden() && gs.my_id.is_some() {
+                gs.send_command(GameMessage::RequestResync, None);
             }
-        }) as Box<dyn FnMut(MessageEvent)>);
-        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-        onmessage_callback.forget();
+        }) as Box<dyn FnMut()>);
+        document.add_event_listener_with_callback("visibilitychange", closure.as_ref().unchecked_ref())?;
+        closure.forget();
     }
 
     // --- INPUT ---
@@ -3072,7 +3071,7 @@ pub fn run_game() -> Result<(), JsValue> {
                             let sy = (tile_world_y - cam_y) * zoom + screen_center_y;
                             
                             // Optimization: skip if off screen
-                            if sx < -(tile_size as f32) || sy < -(tile_size as f32) || sx > WIDTH as f32 || sy > HEIGHT as f32 {
+                            if sx < -tile_size || sy < -tile_size || sx > WIDTH as f32 || sy > HEIGHT as f32 {
                                 continue;
                             }
 
@@ -3181,18 +3180,7 @@ pub fn run_game() -> Result<(), JsValue> {
 
             // Health bar for buildings
             // Only draw health bar if building is selected OR if it's damaged (< 100%)
-            let max_hp = match b.kind {
-                0 => TOWN_HP,
-                1 => WALL_HP,
-                2 => FARM_HP,
-                3 => HOUSE_HP,
-                4 => TOWER_HP,
-                5 => BARRACKS_HP,
-                6 => LUMBER_HP,
-                7 => MINING_HP,
-                8 => WHEAT_HP,
-                _ => 200.0,
-            };
+            let max_hp = gs.balance.building_hp(b.kind);
             
             // Check if selected
             let is_selected = b.selected && Some(b.owner_id) == gs.my_id;
@@ -3358,15 +3346,7 @@ pub fn run_game() -> Result<(), JsValue> {
 
                 // If this unit is selected and has a gather target, draw a dashed box on the target tile
                 if u.selected {
-                    // find my local idx
-                    let mut my_idx = 0;
-                    for (k, uu) in gs.units.iter().enumerate() {
-                        if uu.owner_id == u.owner_id {
-                            if std::ptr::eq(uu, u) { break; }
-                            my_idx += 1;
-                        }
-                    }
-                    if let Some((tx, ty, _k)) = gs.gather_targets.get(&(u.owner_id, my_idx)) {
+                    if let Some((tx, ty, _k)) = gs.gather_targets.get(&(u.owner_id, u.id)) {
                         let twx = *tx as f32 * TILE_SIZE_BASE;
                         let twy = *ty as f32 * TILE_SIZE_BASE;
                         let tsx = (twx - cam_x) * zoom + screen_center_x;
@@ -3420,11 +3400,11 @@ pub fn run_game() -> Result<(), JsValue> {
                 buffer.rect(unit_draw_x as i32, unit_draw_y as i32, w as i32, w as i32, draw_color.0, draw_color.1, draw_color.2);
 
                 // Carry bars per resource (stacked above HP) only if selected
-                if u.selected || (Some(u.owner_id) != gs.my_id && u.selected) {
+                if u.selected {
                     let mut bar_y = unit_draw_y - 10.0;
                     let bar_w = w;
-                    let cap = CARRY_CAP.max(1.0);
-                    let mut draw_carry_bar = |amount: f32, r: u8, g: u8, b: u8, buffer: &mut PixelBuffer, x: f32, y: f32, w: f32| {
+                    let cap = gs.balance.carry_cap.max(1.0);
+                    let draw_carry_bar = |amount: f32, r: u8, g: u8, b: u8, buffer: &mut PixelBuffer, x: f32, y: f32, w: f32| {
                         if amount > 0.0 {
                             let ratio = (amount / cap).clamp(0.0, 1.0);
                             let filled = (w * ratio) as i32;
@@ -3450,7 +3430,7 @@ pub fn run_game() -> Result<(), JsValue> {
 
                 // Health bar (Only if selected)
                 if u.selected {
-                    let hp_ratio = (u.hp / if u.kind == UnitKind::Warrior.to_u8() { WARRIOR_HP } else { WORKER_HP }).clamp(0.0, 1.0);
+                    let hp_ratio = (u.hp / gs.balance.unit_hp(u.kind)).clamp(0.0, 1.0);
                     let bar_w = w + 2.0;
                     let filled = (bar_w * hp_ratio) as i32;
                     buffer.rect((unit_draw_x - 1.0) as i32, (unit_draw_y - 4.0) as i32, bar_w as i32, 4, 60, 20, 20);
@@ -3467,6 +3447,7 @@ pub fn run_game() -> Result<(), JsValue> {
             WsState::Connected => (0, 200, 0),
             WsState::Connecting => (220, 180, 0),
             WsState::Error => (220, 0, 0),
+            WsState::Reconnecting { .. } => (255, 120, 0),
             WsState::Closed => (120, 120, 120),
         };
         buffer.rect(10, 10, 10, 10, status_color.0, status_color.1, status_color.2);
@@ -3641,7 +3622,7 @@ pub fn run_game() -> Result<(), JsValue> {
                     );
                      for (idx, (kind, color)) in options.iter().enumerate() {
                         let opt_y = home_btn_y - ((idx as f32 + 1.0) * (btn_size + menu_gap));
-                         let affordable = gs.resources.has(&kind.cost());
+                         let affordable = gs.resources.has(&gs.balance.build_cost(kind.to_kind_id()));
                          
                          // Highlight selected build type
                          let is_active = gs.selected_build == Some(*kind) || (gs.build_mode && *kind == BuildKind::Wall);
@@ -3880,6 +3861,50 @@ pub fn run_game() -> Result<(), JsValue> {
             Clamped(&buffer.pixels), WIDTH, HEIGHT).unwrap();
         context.put_image_data(&image_data, 0.0, 0.0).unwrap();
 
+        // Why the last command was refused (drawn over the pixel buffer, which has no text)
+        if let Some((text, until)) = &gs.notice {
+            if now < *until {
+                context.set_font("bold 14px sans-serif");
+                context.set_fill_style_str("#ff5050");
+                let _ = context.fill_text(text, 10.0, 48.0);
+            }
+        }
+
+        // Connection lost: the world is frozen until the Welcome on reconnect
+        let cause = match (&gs.kicked, gs.restarting) {
+            (Some(reason), _) => reason.as_str(),
+            (None, Some(_)) => "Server restarting",
+            (None, None) => "Connection lost",
+        };
+        if let (WsState::Closed, Some(reason)) = (gs.ws_state, &gs.kicked) {
+            // Told not to come back
+            context.set_font("bold 14px sans-serif");
+            context.set_fill_style_str("#ff5050");
+            let _ = context.fill_text(reason, 10.0, 68.0);
+        } else if let WsState::Reconnecting { at } = gs.ws_state {
+            let secs = ((at - now) / 1000.0).ceil();
+            let text = if secs >= 1.0 { format!("{}, reconnecting in {}s", cause, secs) } else { "Reconnecting...".to_string() };
+            context.set_font("bold 14px sans-serif");
+            context.set_fill_style_str("#ffa030");
+            let _ = context.fill_text(&text, 10.0, 68.0);
+        } else if let WsState::Connecting = gs.ws_state {
+            if gs.reconnect_attempt > 0 {
+                context.set_font("bold 14px sans-serif");
+                context.set_fill_style_str("#ffa030");
+                let _ = context.fill_text(&format!("{}, reconnecting...", cause), 10.0, 68.0);
+            }
+        } else if let (Some(_), WsState::Connected) = (gs.restarting, gs.ws_state) {
+            // Between the notice and the close
+            context.set_font("bold 14px sans-serif");
+            context.set_fill_style_str("#ffa030");
+            let _ = context.fill_text("Server restarting...", 10.0, 68.0);
+        }
+        if gs.update_available.is_some() {
+            context.set_font("12px sans-serif");
+            context.set_fill_style_str("#9fd4ff");
+            let _ = context.fill_text("Update available - reload to get it", 10.0, 86.0);
+        }
+
         request_animation_frame(f.borrow().as_ref().unwrap());
     }) as Box<dyn FnMut()>));
 